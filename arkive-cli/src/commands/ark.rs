@@ -1,6 +1,10 @@
-use arkive_core::{Result, WalletManager};
+use crate::commands::open_wallet;
+use arkive_core::storage::{ExitStage, UnilateralExit};
+use arkive_core::wallet::RenewalOutcome;
+use arkive_core::{Amount, ArkiveError, Result, WalletManager};
 use clap::Subcommand;
 use comfy_table::{presets::UTF8_FULL, Table};
+use dialoguer::Confirm;
 
 #[derive(Subcommand)]
 pub enum ArkCommands {
@@ -19,12 +23,52 @@ pub enum ArkCommands {
         /// Wallet name
         wallet: String,
     },
+    /// Exit VTXOs on chain without the Ark server
+    Exit {
+        /// Wallet name
+        wallet: String,
+        /// VTXO outpoints (txid:vout)
+        #[arg(required = true)]
+        outpoints: Vec<String>,
+        /// Skip confirmation prompt
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Advance unfinished unilateral exits and show their progress
+    Exits {
+        /// Wallet name
+        wallet: String,
+    },
+    /// Move funds from Ark to an on-chain address in the next round. All
+    /// spendable VTXOs may be used, the change returns as a new VTXO.
+    Offboard {
+        /// Wallet name
+        wallet: String,
+        /// Amount in satoshis
+        amount: u64,
+        /// On-chain address, the wallet's own by default
+        #[arg(short, long)]
+        address: Option<String>,
+        /// Skip confirmation prompt
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Renew VTXOs close to expiry in the next round
+    Renew {
+        /// Wallet name
+        wallet: String,
+    },
+    /// Check which VTXOs could be exited without the Ark server
+    VerifyExits {
+        /// Wallet name
+        wallet: String,
+    },
 }
 
 pub async fn handle_ark_command(cmd: ArkCommands, manager: &WalletManager) -> Result<()> {
     match cmd {
         ArkCommands::Vtxos { wallet } => {
-            let wallet = manager.load_wallet(&wallet).await?;
+            let wallet = open_wallet(manager, &wallet).await?;
 
             println!("VTXOs for wallet '{}':", wallet.name());
 
@@ -59,7 +103,7 @@ pub async fn handle_ark_command(cmd: ArkCommands, manager: &WalletManager) -> Re
         }
 
         ArkCommands::Round { wallet } => {
-            let wallet = manager.load_wallet(&wallet).await?;
+            let wallet = open_wallet(manager, &wallet).await?;
 
             println!("Participating in round for wallet '{}'...", wallet.name());
 
@@ -79,7 +123,7 @@ pub async fn handle_ark_command(cmd: ArkCommands, manager: &WalletManager) -> Re
         }
 
         ArkCommands::Sync { wallet } => {
-            let wallet = manager.load_wallet(&wallet).await?;
+            let wallet = open_wallet(manager, &wallet).await?;
 
             println!("Syncing wallet '{}'...", wallet.name());
 
@@ -101,7 +145,211 @@ pub async fn handle_ark_command(cmd: ArkCommands, manager: &WalletManager) -> Re
                 }
             }
         }
+
+        ArkCommands::Exit {
+            wallet,
+            outpoints,
+            force,
+        } => {
+            let wallet = open_wallet(manager, &wallet).await?;
+
+            if !force {
+                println!("A unilateral exit broadcasts the VTXOs' presigned transactions");
+                println!("and sweeps them on chain once their exit delay has passed.");
+                println!("Fees of anchored transactions are paid from the on-chain wallet.");
+                println!("On-chain fees are paid for every transaction on the way.");
+
+                let confirm = Confirm::new()
+                    .with_prompt(format!("Exit {} VTXO(s) unilaterally?", outpoints.len()))
+                    .default(false)
+                    .interact()
+                    .map_err(|e| ArkiveError::dialog(e.to_string()))?;
+
+                if !confirm {
+                    println!("Exit cancelled.");
+                    return Ok(());
+                }
+            }
+
+            let exits = wallet.unilateral_exit(&outpoints).await?;
+            print_exits(&exits);
+            println!(
+                "Run `arkive ark exits {}` to continue the exits.",
+                wallet.name()
+            );
+        }
+
+        ArkCommands::Exits { wallet } => {
+            let wallet = open_wallet(manager, &wallet).await?;
+
+            let exits = wallet.resume_exits().await?;
+            if exits.is_empty() {
+                println!("No unilateral exits found.");
+                return Ok(());
+            }
+
+            print_exits(&exits);
+        }
+
+        ArkCommands::Offboard {
+            wallet,
+            amount,
+            address,
+            force,
+        } => {
+            let wallet = open_wallet(manager, &wallet).await?;
+            let amount = Amount::from_sat(amount);
+            let address = match address {
+                Some(address) => address,
+                None => wallet.get_onchain_address().await?.address,
+            };
+
+            let fee = wallet.estimate_offboard_fee(amount).await?;
+
+            if !force {
+                let confirm = Confirm::new()
+                    .with_prompt(format!(
+                        "Offboard {} sats to {} for an estimated {} sats server fee?",
+                        amount.to_sat(),
+                        address,
+                        fee.to_sat()
+                    ))
+                    .default(false)
+                    .interact()
+                    .map_err(|e| ArkiveError::dialog(e.to_string()))?;
+
+                if !confirm {
+                    println!("Offboard cancelled.");
+                    return Ok(());
+                }
+            }
+
+            println!("Waiting for the next round...");
+            match wallet.offboard(&address, amount).await {
+                Ok(round_txid) => {
+                    println!("Offboarded {} sats to {}", amount.to_sat(), address);
+                    println!("Round transaction ID: {}", round_txid);
+                }
+                Err(e) => {
+                    println!("Offboard failed: {}", e);
+                    return Err(e);
+                }
+            }
+        }
+
+        ArkCommands::Renew { wallet } => {
+            let wallet = open_wallet(manager, &wallet).await?;
+
+            println!(
+                "Renewing VTXOs of wallet '{}' expiring within {} minutes...",
+                wallet.name(),
+                wallet.config().renewal_threshold.as_secs() / 60
+            );
+
+            let report = wallet.renew_expiring_vtxos().await;
+            match report.outcome {
+                RenewalOutcome::NothingDue => println!("No VTXOs are due for renewal."),
+                RenewalOutcome::Renewed { round_txid } => {
+                    println!(
+                        "Renewed {} expiring VTXO(s), settling {} VTXO(s) and {} boarding output(s) in total",
+                        report.expiring.len(),
+                        report.settled.len(),
+                        report.boarded.len()
+                    );
+                    println!("Round transaction ID: {}", round_txid);
+                }
+                RenewalOutcome::Skipped(reason) => println!("Renewal skipped: {}", reason),
+                RenewalOutcome::Failed(error) => {
+                    println!(
+                        "Failed to renew {} VTXO(s) after {} attempt(s): {}",
+                        report.expiring.len(),
+                        report.attempts,
+                        error
+                    );
+                    for outpoint in &report.expiring {
+                        println!("  {}", outpoint);
+                    }
+                }
+            }
+        }
+
+        ArkCommands::VerifyExits { wallet } => {
+            let wallet = open_wallet(manager, &wallet).await?;
+
+            let report = wallet.verify_exit_readiness().await?;
+            if report.is_empty() {
+                println!("No unspent VTXOs found.");
+                return Ok(());
+            }
+
+            let mut table = Table::new();
+            table.load_preset(UTF8_FULL);
+            table.set_header(vec!["Outpoint", "Amount (sats)", "Status", "Exit-capable"]);
+
+            for vtxo in &report {
+                let capable = if vtxo.is_exit_capable() { "Yes" } else { "No" };
+                table.add_row(vec![
+                    &format!("{}...", &vtxo.outpoint[..16]),
+                    &vtxo.amount.to_sat().to_string(),
+                    &format!("{:?}", vtxo.status),
+                    &capable.to_string(),
+                ]);
+            }
+
+            println!("{}", table);
+
+            let at_risk: Vec<_> = report.iter().filter(|v| !v.is_exit_capable()).collect();
+            if at_risk.is_empty() {
+                println!("All VTXOs can be exited without the Ark server.");
+            } else {
+                println!("{} VTXO(s) cannot be exited unilaterally:", at_risk.len());
+                for vtxo in at_risk {
+                    println!("  {}", vtxo.outpoint);
+                    for problem in &vtxo.problems {
+                        println!("    - {}", problem);
+                    }
+                }
+            }
+        }
     }
 
     Ok(())
 }
+
+fn print_exits(exits: &[UnilateralExit]) {
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+    table.set_header(vec![
+        "Outpoint",
+        "Amount (sats)",
+        "Stage",
+        "Claim TXID",
+        "Last Error",
+    ]);
+
+    for exit in exits {
+        let outpoint = exit.outpoint.to_string();
+        let stage = match exit.stage {
+            ExitStage::Broadcasting => format!("Broadcasting ({} sent)", exit.next_tx),
+            ExitStage::AwaitingConfirmation => "Awaiting confirmation".to_string(),
+            ExitStage::Timelocked => match exit.confirmed_height {
+                Some(height) => format!("Timelocked (confirmed at {})", height),
+                None => "Timelocked".to_string(),
+            },
+            ExitStage::Claimed => "Claimed".to_string(),
+        };
+
+        table.add_row(vec![
+            &format!("{}...", &outpoint[..16]),
+            &exit.amount.to_sat().to_string(),
+            &stage,
+            &exit
+                .claim_txid
+                .map(|txid| txid.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            &exit.last_error.clone().unwrap_or_else(|| "-".to_string()),
+        ]);
+    }
+
+    println!("{}", table);
+}
@@ -1,3 +1,4 @@
+use crate::commands::open_wallet;
 use arkive_core::{ArkiveError, Result, WalletManager};
 use clap::Subcommand;
 use dialoguer::{Confirm, Password};
@@ -35,7 +36,7 @@ pub enum BackupCommands {
 pub async fn handle_backup_command(cmd: BackupCommands, manager: &WalletManager) -> Result<()> {
     match cmd {
         BackupCommands::Create { wallet, output } => {
-            let wallet_instance = manager.load_wallet(&wallet).await?;
+            let wallet_instance = open_wallet(manager, &wallet).await?;
 
             let password = Password::new()
                 .with_prompt("Enter backup password")
@@ -93,7 +94,7 @@ pub async fn handle_backup_command(cmd: BackupCommands, manager: &WalletManager)
                 return Ok(());
             }
 
-            let _wallet_instance = manager.load_wallet(&wallet).await?;
+            let _wallet_instance = open_wallet(manager, &wallet).await?;
 
             println!("Exporting wallet data...");
 
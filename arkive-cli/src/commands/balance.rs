@@ -1,4 +1,5 @@
-use arkive_core::{Result, WalletManager};
+use crate::commands::open_wallet;
+use arkive_core::{Amount, ArkWallet, Result, WalletManager};
 use clap::Subcommand;
 use comfy_table::{presets::UTF8_FULL, Table};
 
@@ -21,13 +22,42 @@ pub enum BalanceCommands {
         /// Address type (onchain, ark, boarding)
         #[arg(short, long)]
         address_type: Option<String>,
+        /// Derive a fresh on-chain address instead of the next unused one
+        #[arg(long)]
+        new: bool,
+    },
+    /// Show a BIP21 payment URI with both the on-chain and the Ark address
+    Uri {
+        /// Wallet name
+        wallet: String,
+        /// Requested amount in satoshis
+        #[arg(long)]
+        amount: Option<u64>,
+        /// Label for the recipient
+        #[arg(long)]
+        label: Option<String>,
+        /// Message describing the payment
+        #[arg(long)]
+        message: Option<String>,
+    },
+    /// List on-chain UTXOs and freeze or unfreeze them
+    Utxos {
+        /// Wallet name
+        wallet: String,
+        /// Exclude a UTXO (txid:vout) from automatic coin selection
+        #[arg(long)]
+        freeze: Vec<String>,
+        /// Make a frozen UTXO (txid:vout) spendable again
+        #[arg(long)]
+        unfreeze: Vec<String>,
     },
 }
 
 pub async fn handle_balance_command(cmd: BalanceCommands, manager: &WalletManager) -> Result<()> {
     match cmd {
         BalanceCommands::Show { wallet } => {
-            let wallet = manager.load_wallet(&wallet).await?;
+            let wallet = open_wallet(manager, &wallet).await?;
+            sync_onchain(&wallet).await;
 
             println!("Balance for wallet '{}':", wallet.name());
 
@@ -50,7 +80,8 @@ pub async fn handle_balance_command(cmd: BalanceCommands, manager: &WalletManage
         }
 
         BalanceCommands::Detail { wallet } => {
-            let wallet = manager.load_wallet(&wallet).await?;
+            let wallet = open_wallet(manager, &wallet).await?;
+            sync_onchain(&wallet).await;
 
             println!("Detailed balance for wallet '{}':", wallet.name());
             println!();
@@ -107,12 +138,18 @@ pub async fn handle_balance_command(cmd: BalanceCommands, manager: &WalletManage
         BalanceCommands::Address {
             wallet,
             address_type,
+            new,
         } => {
-            let wallet = manager.load_wallet(&wallet).await?;
+            let wallet = open_wallet(manager, &wallet).await?;
 
             match address_type.as_deref() {
                 Some("onchain") => {
-                    if let Ok(addr) = wallet.get_onchain_address().await {
+                    let addr = if new {
+                        wallet.new_onchain_address().await
+                    } else {
+                        wallet.get_onchain_address().await
+                    };
+                    if let Ok(addr) = addr {
                         println!("On-chain address: {}", addr.address);
                     }
                 }
@@ -141,7 +178,73 @@ pub async fn handle_balance_command(cmd: BalanceCommands, manager: &WalletManage
                 }
             }
         }
+
+        BalanceCommands::Uri {
+            wallet,
+            amount,
+            label,
+            message,
+        } => {
+            let wallet = open_wallet(manager, &wallet).await?;
+            let uri = wallet
+                .payment_uri(amount.map(Amount::from_sat), label, message)
+                .await?;
+            println!("{}", uri);
+        }
+
+        BalanceCommands::Utxos {
+            wallet,
+            freeze,
+            unfreeze,
+        } => {
+            let wallet = open_wallet(manager, &wallet).await?;
+            sync_onchain(&wallet).await;
+
+            for outpoint in &freeze {
+                wallet.freeze_utxo(outpoint).await?;
+                println!("Frozen: {}", outpoint);
+            }
+            for outpoint in &unfreeze {
+                wallet.unfreeze_utxo(outpoint).await?;
+                println!("Unfrozen: {}", outpoint);
+            }
+
+            let utxos = wallet.list_utxos().await?;
+            if utxos.is_empty() {
+                println!("No on-chain UTXOs found.");
+                return Ok(());
+            }
+
+            let mut table = Table::new();
+            table.load_preset(UTF8_FULL);
+            table.set_header(vec![
+                "Outpoint",
+                "Amount (sats)",
+                "Confirmations",
+                "Address",
+                "Frozen",
+            ]);
+
+            for utxo in &utxos {
+                table.add_row(vec![
+                    &utxo.outpoint,
+                    &utxo.value.to_sat().to_string(),
+                    &utxo.confirmations.to_string(),
+                    &utxo.address,
+                    if utxo.frozen { "yes" } else { "no" },
+                ]);
+            }
+
+            println!("{}", table);
+        }
     }
 
     Ok(())
 }
+
+/// Refresh the local UTXO set, falling back to the last synced state
+async fn sync_onchain(wallet: &ArkWallet) {
+    if let Err(e) = wallet.sync_onchain().await {
+        println!("On-chain sync failed, showing last synced state: {}", e);
+    }
+}
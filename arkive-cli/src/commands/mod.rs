@@ -10,4 +10,4 @@ pub use backup::{handle_backup_command, BackupCommands};
 pub use balance::{handle_balance_command, BalanceCommands};
 pub use sync::{handle_sync_command, SyncCommands};
 pub use transaction::{handle_transaction_command, TransactionCommands};
-pub use wallet::{handle_wallet_command, WalletCommands};
+pub use wallet::{handle_wallet_command, open_wallet, WalletCommands};
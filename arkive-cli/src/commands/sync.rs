@@ -1,3 +1,4 @@
+use crate::commands::open_wallet;
 use arkive_core::{ArkiveError, Result, WalletManager};
 use clap::Subcommand;
 use comfy_table::{presets::UTF8_FULL, Table};
@@ -46,7 +47,7 @@ pub enum SyncCommands {
 pub async fn handle_sync_command(cmd: SyncCommands, manager: &WalletManager) -> Result<()> {
     match cmd {
         SyncCommands::Init { wallet } => {
-            let wallet_instance = manager.load_wallet(&wallet).await?;
+            let wallet_instance = open_wallet(manager, &wallet).await?;
 
             println!("Initializing sync for wallet '{}'...", wallet);
 
@@ -58,7 +59,7 @@ pub async fn handle_sync_command(cmd: SyncCommands, manager: &WalletManager) ->
         }
 
         SyncCommands::Package { wallet, output } => {
-            let wallet_instance = manager.load_wallet(&wallet).await?;
+            let wallet_instance = open_wallet(manager, &wallet).await?;
 
             println!("Creating sync package for wallet '{}'...", wallet);
 
@@ -100,7 +101,7 @@ pub async fn handle_sync_command(cmd: SyncCommands, manager: &WalletManager) ->
         }
 
         SyncCommands::Status { wallet } => {
-            let wallet_instance = manager.load_wallet(&wallet).await?;
+            let wallet_instance = open_wallet(manager, &wallet).await?;
 
             println!("Sync status for wallet '{}':", wallet);
 
@@ -131,7 +132,7 @@ pub async fn handle_sync_command(cmd: SyncCommands, manager: &WalletManager) ->
             auto_local,
             auto_remote,
         } => {
-            let wallet_instance = manager.load_wallet(&wallet).await?;
+            let wallet_instance = open_wallet(manager, &wallet).await?;
 
             println!("Checking conflicts for wallet '{}'...", wallet);
 
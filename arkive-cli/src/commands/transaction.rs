@@ -1,7 +1,13 @@
+use crate::commands::open_wallet;
+use arkive_core::types::UtxoInfo;
+use arkive_core::wallet::FeePriority;
 use arkive_core::{ArkiveError, Result, WalletManager};
-use bitcoin::Amount;
+use bitcoin::{Amount, FeeRate, OutPoint};
 use clap::Subcommand;
 use comfy_table::{presets::UTF8_FULL, Table};
+use dialoguer::Confirm;
+use std::path::PathBuf;
+use std::str::FromStr;
 
 #[derive(Subcommand)]
 pub enum TransactionCommands {
@@ -12,7 +18,51 @@ pub enum TransactionCommands {
         /// Recipient address
         address: String,
         /// Amount in satoshis
-        amount: u64,
+        #[arg(required_unless_present = "max")]
+        amount: Option<u64>,
+        /// Spend exactly these UTXOs (txid:vout), repeatable
+        #[arg(long = "input")]
+        inputs: Vec<String>,
+        /// Send all unfrozen UTXOs, deducting the fee from the amount
+        #[arg(long, conflicts_with_all = ["amount", "inputs"])]
+        max: bool,
+    },
+    /// Pay a BIP21 URI or address, via Ark when possible and on-chain otherwise
+    Pay {
+        /// Wallet name
+        wallet: String,
+        /// BIP21 URI, on-chain address or Ark address
+        uri: String,
+        /// Amount in satoshis, required if the URI has none
+        amount: Option<u64>,
+    },
+    /// Pay many on-chain recipients in one transaction
+    SendBatch {
+        /// Wallet name
+        wallet: String,
+        /// CSV file with one `address,amount_sats` line per recipient
+        file: PathBuf,
+        /// Skip confirmation prompt
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Replace an unconfirmed on-chain transaction with a higher fee (RBF)
+    BumpFee {
+        /// Wallet name
+        wallet: String,
+        /// Transaction ID to replace
+        txid: String,
+        /// New fee rate in sat/vB
+        fee_rate: u64,
+    },
+    /// Accelerate an unconfirmed incoming transaction with a child paying its fee (CPFP)
+    Cpfp {
+        /// Wallet name
+        wallet: String,
+        /// Transaction ID to accelerate
+        txid: String,
+        /// Fee rate in sat/vB for the parent and child together
+        fee_rate: u64,
     },
     /// Send Ark transaction
     SendArk {
@@ -42,6 +92,9 @@ pub enum TransactionCommands {
         address: String,
         /// Amount in satoshis
         amount: u64,
+        /// On-chain fee priority (slow, normal, fast, fastest), defaults to the wallet's policy
+        #[arg(short, long)]
+        priority: Option<String>,
     },
 }
 
@@ -54,12 +107,46 @@ pub async fn handle_transaction_command(
             wallet,
             address,
             amount,
+            inputs,
+            max,
         } => {
-            let wallet = manager.load_wallet(&wallet).await?;
-            let amount = Amount::from_sat(amount);
+            let wallet = open_wallet(manager, &wallet).await?;
+
+            if max {
+                let (amount, quote) = wallet.quote_onchain_max(&address).await?;
+                println!(
+                    "Sending all unfrozen funds to {}: {} sats after a {} sats fee ({} sat/vB)...",
+                    address,
+                    amount.to_sat(),
+                    quote.fee.to_sat(),
+                    quote.sat_per_vb()
+                );
+
+                match wallet.send_onchain_max(&address).await {
+                    Ok((txid, amount)) => {
+                        println!("Transaction sent successfully!");
+                        println!("Amount sent: {} sats", amount.to_sat());
+                        println!("Transaction ID: {}", txid);
+                    }
+                    Err(e) => {
+                        println!("Transaction failed: {}", e);
+                        return Err(e);
+                    }
+                }
+                return Ok(());
+            }
+
+            // Clap requires the amount unless --max is given
+            let amount = Amount::from_sat(amount.unwrap_or_default());
 
-            // Check balance
-            let balance = wallet.onchain_balance().await?;
+            // Check what can be spent: the given inputs, or every unfrozen
+            // output when coins are selected automatically
+            let inputs = parse_inputs(&inputs)?;
+            let balance = if inputs.is_empty() {
+                wallet.unfrozen_onchain_balance().await?
+            } else {
+                inputs_total(&wallet.list_utxos().await?, &inputs)?
+            };
             if balance < amount {
                 return Err(ArkiveError::InsufficientFunds {
                     need: amount.to_sat(),
@@ -85,7 +172,15 @@ pub async fn handle_transaction_command(
                 }
             }
 
-            match wallet.send_onchain(&address, amount).await {
+            let result = if inputs.is_empty() {
+                wallet.send_onchain(&address, amount).await
+            } else {
+                wallet
+                    .send_onchain_with_inputs(&address, amount, &inputs)
+                    .await
+            };
+
+            match result {
                 Ok(txid) => {
                     println!("Transaction sent successfully!");
                     println!("Transaction ID: {}", txid);
@@ -97,12 +192,148 @@ pub async fn handle_transaction_command(
             }
         }
 
+        TransactionCommands::Pay {
+            wallet,
+            uri,
+            amount,
+        } => {
+            let wallet = open_wallet(manager, &wallet).await?;
+
+            match wallet.send_to_uri(&uri, amount.map(Amount::from_sat)).await {
+                Ok((rail, txid)) => {
+                    println!("Payment sent successfully via {:?}!", rail);
+                    println!("Transaction ID: {}", txid);
+                }
+                Err(e) => {
+                    println!("Payment failed: {}", e);
+                    return Err(e);
+                }
+            }
+        }
+
+        TransactionCommands::SendBatch {
+            wallet,
+            file,
+            force,
+        } => {
+            let wallet = open_wallet(manager, &wallet).await?;
+            let csv = tokio::fs::read_to_string(&file).await?;
+            let recipients = parse_recipients(&csv)?;
+            let total = recipients.iter().map(|(_, amount)| *amount).sum::<Amount>();
+
+            let quote = wallet.quote_onchain_batch(&recipients).await?;
+
+            let mut table = Table::new();
+            table.load_preset(UTF8_FULL);
+            table.set_header(vec!["#", "Address", "Amount"]);
+            for (i, (address, amount)) in recipients.iter().enumerate() {
+                table.add_row(vec![
+                    &(i + 1).to_string(),
+                    address,
+                    &format!("{} sats", amount.to_sat()),
+                ]);
+            }
+            println!("{}", table);
+
+            println!("Recipients: {}", recipients.len());
+            println!("Total: {} sats", total.to_sat());
+            println!(
+                "Fee: {} sats ({} sat/vB, {} vB)",
+                quote.fee.to_sat(),
+                quote.sat_per_vb(),
+                quote.vsize
+            );
+            println!("Total with fee: {} sats", (total + quote.fee).to_sat());
+
+            if !force {
+                let confirm = Confirm::new()
+                    .with_prompt(format!(
+                        "Send {} sats to {} recipients?",
+                        total.to_sat(),
+                        recipients.len()
+                    ))
+                    .default(false)
+                    .interact()
+                    .map_err(|e| ArkiveError::dialog(e.to_string()))?;
+
+                if !confirm {
+                    println!("Batch payment cancelled.");
+                    return Ok(());
+                }
+            }
+
+            match wallet.send_onchain_batch(&recipients).await {
+                Ok(txid) => {
+                    println!("Batch transaction sent successfully!");
+                    println!("Transaction ID: {}", txid);
+                }
+                Err(e) => {
+                    println!("Transaction failed: {}", e);
+                    return Err(e);
+                }
+            }
+        }
+
+        TransactionCommands::BumpFee {
+            wallet,
+            txid,
+            fee_rate,
+        } => {
+            let wallet = open_wallet(manager, &wallet).await?;
+            let fee_rate = FeeRate::from_sat_per_vb(fee_rate)
+                .ok_or_else(|| ArkiveError::config("Fee rate is too high"))?;
+
+            println!(
+                "Replacing transaction {} at {} sat/vB...",
+                txid,
+                fee_rate.to_sat_per_vb_ceil()
+            );
+
+            match wallet.bump_fee(&txid, fee_rate).await {
+                Ok(replacement) => {
+                    println!("Replacement transaction sent successfully!");
+                    println!("Transaction ID: {}", replacement);
+                }
+                Err(e) => {
+                    println!("Fee bump failed: {}", e);
+                    return Err(e);
+                }
+            }
+        }
+
+        TransactionCommands::Cpfp {
+            wallet,
+            txid,
+            fee_rate,
+        } => {
+            let wallet = open_wallet(manager, &wallet).await?;
+            let fee_rate = FeeRate::from_sat_per_vb(fee_rate)
+                .ok_or_else(|| ArkiveError::config("Fee rate is too high"))?;
+
+            println!(
+                "Accelerating transaction {} to {} sat/vB...",
+                txid,
+                fee_rate.to_sat_per_vb_ceil()
+            );
+
+            match wallet.cpfp(&txid, fee_rate).await {
+                Ok(child) => {
+                    println!("Child transaction sent successfully!");
+                    println!("Transaction ID: {}", child);
+                }
+                Err(e) => {
+                    println!("Fee bump failed: {}", e);
+                    return Err(e);
+                }
+            }
+        }
+
         TransactionCommands::SendArk {
             wallet,
             address,
             amount,
         } => {
-            let wallet = manager.load_wallet(&wallet).await?;
+            let wallet = open_wallet(manager, &wallet).await?;
             let amount = Amount::from_sat(amount);
 
             // Check Ark balance
@@ -138,7 +369,7 @@ pub async fn handle_transaction_command(
         }
 
         TransactionCommands::History { wallet, limit } => {
-            let wallet = manager.load_wallet(&wallet).await?;
+            let wallet = open_wallet(manager, &wallet).await?;
             println!("Transaction history for wallet '{}':", wallet.name());
 
             let transactions = wallet.transaction_history().await?;
@@ -148,9 +379,14 @@ pub async fn handle_transaction_command(
                 return Ok(());
             }
 
+            // Confirmation counts are left out when the chain is unreachable
+            let tip_height = wallet.tip_height().await.ok();
+
             let mut table = Table::new();
             table.load_preset(UTF8_FULL);
-            table.set_header(vec!["Date", "Type", "Amount", "Status", "TXID", "Round"]);
+            table.set_header(vec![
+                "Date", "Type", "Amount", "Status", "Conf", "TXID", "Round",
+            ]);
 
             for tx in transactions.iter().take(limit) {
                 let amount_str = if tx.amount >= 0 {
@@ -165,11 +401,17 @@ pub async fn handle_transaction_command(
                     .map(|id| id.replace("round_", ""))
                     .unwrap_or_else(|| "-".to_string());
 
+                let confirmations_display = match (tx.block_height, tip_height) {
+                    (Some(_), Some(tip)) => tx.confirmations(tip).to_string(),
+                    _ => "-".to_string(),
+                };
+
                 table.add_row(vec![
                     &tx.timestamp.format("%Y-%m-%d %H:%M").to_string(),
                     &format!("{:?}", tx.tx_type),
                     &amount_str,
                     &format!("{:?}", tx.status),
+                    &confirmations_display,
                     &tx.txid[..16],
                     &round_display,
                 ]);
@@ -191,17 +433,26 @@ pub async fn handle_transaction_command(
             tx_type,
             address,
             amount,
+            priority,
         } => {
-            let wallet = manager.load_wallet(&wallet).await?;
+            let wallet = open_wallet(manager, &wallet).await?;
             let amount = Amount::from_sat(amount);
+            let priority = priority.as_deref().map(parse_priority).transpose()?;
 
             match tx_type.as_str() {
-                "onchain" => match wallet.estimate_onchain_fee(&address, amount).await {
-                    Ok(fee) => {
+                "onchain" => match wallet.quote_onchain_fee(&address, amount, priority).await {
+                    Ok(quote) => {
                         println!("On-chain transaction fee estimate:");
                         println!("  Amount: {} sats", amount.to_sat());
-                        println!("  Fee: {} sats", fee.to_sat());
-                        println!("  Total: {} sats", (amount + fee).to_sat());
+                        println!(
+                            "  Priority: {:?} (~{} blocks)",
+                            quote.priority,
+                            quote.priority.confirmation_target()
+                        );
+                        println!("  Fee rate: {} sat/vB", quote.sat_per_vb());
+                        println!("  Size: {} vB", quote.vsize);
+                        println!("  Fee: {} sats", quote.fee.to_sat());
+                        println!("  Total: {} sats", (amount + quote.fee).to_sat());
                     }
                     Err(e) => {
                         println!("Failed to estimate fee: {}", e);
@@ -229,3 +480,181 @@ pub async fn handle_transaction_command(
 
     Ok(())
 }
+
+fn parse_priority(priority: &str) -> Result<FeePriority> {
+    match priority.to_lowercase().as_str() {
+        "slow" => Ok(FeePriority::Slow),
+        "normal" => Ok(FeePriority::Normal),
+        "fast" => Ok(FeePriority::Fast),
+        "fastest" => Ok(FeePriority::Fastest),
+        _ => Err(ArkiveError::config(format!(
+            "Invalid fee priority: {}. Use slow, normal, fast or fastest",
+            priority
+        ))),
+    }
+}
+
+/// Parse `--input` outpoints, keeping the first of any duplicates
+fn parse_inputs(inputs: &[String]) -> Result<Vec<OutPoint>> {
+    let mut outpoints = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let outpoint = OutPoint::from_str(input.trim())
+            .map_err(|e| ArkiveError::config(format!("Invalid input {}: {}", input, e)))?;
+        if !outpoints.contains(&outpoint) {
+            outpoints.push(outpoint);
+        }
+    }
+    Ok(outpoints)
+}
+
+/// Total value of `inputs`, which must all be unfrozen outputs in `utxos`
+fn inputs_total(utxos: &[UtxoInfo], inputs: &[OutPoint]) -> Result<Amount> {
+    let mut total = Amount::ZERO;
+    for input in inputs {
+        let outpoint = input.to_string();
+        let utxo = utxos
+            .iter()
+            .find(|utxo| utxo.outpoint == outpoint)
+            .ok_or_else(|| {
+                ArkiveError::config(format!(
+                    "Input {} is not an unspent output of this wallet",
+                    outpoint
+                ))
+            })?;
+        if utxo.frozen {
+            return Err(ArkiveError::config(format!(
+                "Input {} is frozen, unfreeze it before spending",
+                outpoint
+            )));
+        }
+        total += utxo.value;
+    }
+    Ok(total)
+}
+
+/// Parse `address,amount_sats` lines, skipping blank lines, `#` comments and
+/// a header row. Only a first row without an address counts as the header,
+/// so a typo in the first amount is still reported.
+fn parse_recipients(csv: &str) -> Result<Vec<(String, Amount)>> {
+    let mut recipients = Vec::new();
+    let mut first_row = true;
+
+    for (i, line) in csv.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (address, amount) = line.split_once(',').ok_or_else(|| {
+            ArkiveError::config(format!("Line {}: expected `address,amount`", i + 1))
+        })?;
+        let (address, amount) = (address.trim(), amount.trim());
+        let is_first_row = std::mem::replace(&mut first_row, false);
+
+        let amount = match amount.parse::<u64>() {
+            Ok(amount) => Amount::from_sat(amount),
+            Err(_) if is_first_row && is_header(address) => continue,
+            Err(e) => {
+                return Err(ArkiveError::config(format!(
+                    "Line {}: invalid amount {}: {}",
+                    i + 1,
+                    amount,
+                    e
+                )))
+            }
+        };
+        recipients.push((address.to_string(), amount));
+    }
+
+    if recipients.is_empty() {
+        return Err(ArkiveError::config("No recipients found in the CSV file"));
+    }
+
+    Ok(recipients)
+}
+
+fn is_header(address: &str) -> bool {
+    address.eq_ignore_ascii_case("address") || bitcoin::Address::from_str(address).is_err()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+
+    #[test]
+    fn test_parse_recipients() {
+        let csv = format!("address,amount\n\n# payroll\n{ADDRESS},10000\n  {ADDRESS} , 20000 \n");
+        let recipients = parse_recipients(&csv).unwrap();
+        assert_eq!(
+            recipients,
+            vec![
+                (ADDRESS.to_string(), Amount::from_sat(10_000)),
+                (ADDRESS.to_string(), Amount::from_sat(20_000)),
+            ]
+        );
+
+        // Any header naming its columns, or none at all
+        let csv = format!("recipient,sats\n{ADDRESS},10000");
+        assert_eq!(parse_recipients(&csv).unwrap().len(), 1);
+        assert_eq!(
+            parse_recipients(&format!("{ADDRESS},10000")).unwrap().len(),
+            1
+        );
+    }
+
+    fn utxo(outpoint: &str, sats: u64, frozen: bool) -> UtxoInfo {
+        UtxoInfo {
+            outpoint: outpoint.to_string(),
+            value: Amount::from_sat(sats),
+            confirmations: 1,
+            address: ADDRESS.to_string(),
+            frozen,
+        }
+    }
+
+    #[test]
+    fn test_inputs() {
+        let first = format!("{}:0", "11".repeat(32));
+        let second = format!("{}:1", "11".repeat(32));
+        let frozen = format!("{}:0", "22".repeat(32));
+
+        let inputs = parse_inputs(&[first.clone(), second.clone(), format!(" {first} ")]).unwrap();
+        assert_eq!(inputs.len(), 2);
+        assert_eq!(inputs[0].to_string(), first);
+        assert!(parse_inputs(&["not-an-outpoint".to_string()]).is_err());
+
+        let utxos = vec![
+            utxo(&first, 10_000, false),
+            utxo(&second, 20_000, false),
+            utxo(&frozen, 50_000, true),
+        ];
+        assert_eq!(
+            inputs_total(&utxos, &inputs).unwrap(),
+            Amount::from_sat(30_000)
+        );
+
+        // Frozen or unknown inputs are refused before anything is sent
+        let inputs = parse_inputs(&[first.clone(), frozen]).unwrap();
+        assert!(inputs_total(&utxos, &inputs).is_err());
+        let unknown = parse_inputs(&[format!("{}:5", "33".repeat(32))]).unwrap();
+        assert!(inputs_total(&utxos, &unknown).is_err());
+    }
+
+    #[test]
+    fn test_parse_recipients_rejects_bad_rows() {
+        // A typo in the first amount is not mistaken for a header
+        let csv = format!("{ADDRESS},10o00\n{ADDRESS},20000");
+        assert!(parse_recipients(&csv).is_err());
+
+        let csv = format!("address,amount\n{ADDRESS},20000\n{ADDRESS},abc");
+        assert!(parse_recipients(&csv).is_err());
+
+        assert!(parse_recipients(ADDRESS).is_err());
+
+        // Only a header or comments
+        assert!(parse_recipients("address,amount\n# nothing\n").is_err());
+        assert!(parse_recipients("").is_err());
+    }
+}
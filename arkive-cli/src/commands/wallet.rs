@@ -1,8 +1,36 @@
-use arkive_core::{ArkiveError, Result, WalletManager};
+use arkive_core::wallet::{
+    Bip39Passphrase, BitcoindConfig, ChainBackendConfig, ElectrumConfig, OnchainScriptType, RpcAuth,
+};
+use arkive_core::{ArkWallet, ArkiveError, Result, WalletConfig, WalletManager};
 use bitcoin::Network;
-use clap::Subcommand;
+use clap::{Args, Subcommand};
 use comfy_table::{presets::UTF8_FULL, Table};
 use dialoguer::{Confirm, Password};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Where a new wallet reads chain data from, Esplora unless given
+#[derive(Args)]
+pub struct ChainArgs {
+    /// Esplora endpoint to fail over to, can be repeated
+    #[arg(long = "esplora-fallback", value_name = "URL")]
+    esplora_fallbacks: Vec<String>,
+    /// Use a Bitcoin Core node's JSON-RPC at this URL instead of Esplora
+    #[arg(long, conflicts_with = "esplora_fallbacks")]
+    bitcoind_url: Option<String>,
+    /// bitcoind RPC cookie file
+    #[arg(long, requires = "bitcoind_url", conflicts_with = "rpc_user")]
+    rpc_cookie: Option<PathBuf>,
+    /// bitcoind RPC user (will prompt for the password)
+    #[arg(long, requires = "bitcoind_url")]
+    rpc_user: Option<String>,
+    /// Use an Electrum server (tcp://host:port or ssl://host:port) instead of Esplora
+    #[arg(long, conflicts_with_all = ["bitcoind_url", "esplora_fallbacks"])]
+    electrum_url: Option<String>,
+    /// Accept the Electrum server's TLS certificate without checking its domain
+    #[arg(long, requires = "electrum_url")]
+    no_validate_domain: bool,
+}
 
 #[derive(Subcommand)]
 pub enum WalletCommands {
@@ -13,6 +41,17 @@ pub enum WalletCommands {
         /// Bitcoin network (regtest, signet, mutinynet)
         #[arg(short, long, default_value = "regtest")]
         network: String,
+        /// Protect the seed with a BIP39 passphrase (will prompt)
+        #[arg(long)]
+        bip39_passphrase: bool,
+        /// Store the BIP39 passphrase encrypted with the seed
+        #[arg(long, requires = "bip39_passphrase")]
+        store_bip39_passphrase: bool,
+        /// Use taproot (BIP86) on-chain addresses instead of native segwit (BIP84)
+        #[arg(long)]
+        taproot: bool,
+        #[command(flatten)]
+        chain: ChainArgs,
     },
     /// Import a wallet from mnemonic
     Import {
@@ -24,6 +63,17 @@ pub enum WalletCommands {
         /// Mnemonic phrase (will prompt if not provided)
         #[arg(short, long)]
         mnemonic: Option<String>,
+        /// The mnemonic is protected by a BIP39 passphrase (will prompt)
+        #[arg(long)]
+        bip39_passphrase: bool,
+        /// Store the BIP39 passphrase encrypted with the seed
+        #[arg(long, requires = "bip39_passphrase")]
+        store_bip39_passphrase: bool,
+        /// Use taproot (BIP86) on-chain addresses instead of native segwit (BIP84)
+        #[arg(long)]
+        taproot: bool,
+        #[command(flatten)]
+        chain: ChainArgs,
     },
     /// List all wallets
     List,
@@ -44,20 +94,37 @@ pub enum WalletCommands {
 
 pub async fn handle_wallet_command(cmd: WalletCommands, manager: &WalletManager) -> Result<()> {
     match cmd {
-        WalletCommands::Create { name, network } => {
-            let (network, is_mutinynet) = parse_network(&network)?;
+        WalletCommands::Create {
+            name,
+            network,
+            bip39_passphrase,
+            store_bip39_passphrase,
+            taproot,
+            chain,
+        } => {
+            let config = wallet_config(&network, taproot, &chain)?;
 
-            println!("Creating wallet '{}'...", name);
-            let (wallet, mnemonic) = if is_mutinynet {
-                manager.create_wallet_mutinynet(&name).await?
+            let passphrase = read_passphrase(true)?;
+            let bip39 = if bip39_passphrase {
+                Some(read_bip39_passphrase(true, store_bip39_passphrase)?)
             } else {
-                manager.create_wallet(&name, network).await?
+                None
             };
 
+            println!("Creating wallet '{}'...", name);
+            let (wallet, mnemonic) = manager
+                .create_wallet_with_config(&name, config, &passphrase, bip39.as_ref())
+                .await?;
+
             println!("Wallet created successfully!");
             println!();
             println!("IMPORTANT: Save your mnemonic phrase securely!");
             println!("Mnemonic: {}", mnemonic);
+            if bip39.as_ref().is_some_and(|b| !b.persist) {
+                println!(
+                    "Your BIP39 passphrase is not stored and is required to open this wallet."
+                );
+            }
             println!();
             println!("Wallet Details:");
             println!("  Name: {}", wallet.name());
@@ -77,8 +144,12 @@ pub async fn handle_wallet_command(cmd: WalletCommands, manager: &WalletManager)
             name,
             network,
             mnemonic,
+            bip39_passphrase,
+            store_bip39_passphrase,
+            taproot,
+            chain,
         } => {
-            let (network, is_mutinynet) = parse_network(&network)?;
+            let config = wallet_config(&network, taproot, &chain)?;
 
             let mnemonic = if let Some(m) = mnemonic {
                 m
@@ -89,13 +160,18 @@ pub async fn handle_wallet_command(cmd: WalletCommands, manager: &WalletManager)
                     .map_err(|e| ArkiveError::dialog(e.to_string()))?
             };
 
-            println!("Importing wallet '{}'...", name);
-            let wallet = if is_mutinynet {
-                manager.import_wallet_mutinynet(&name, &mnemonic).await?
+            let passphrase = read_passphrase(true)?;
+            let bip39 = if bip39_passphrase {
+                Some(read_bip39_passphrase(false, store_bip39_passphrase)?)
             } else {
-                manager.import_wallet(&name, &mnemonic, network).await?
+                None
             };
 
+            println!("Importing wallet '{}'...", name);
+            let wallet = manager
+                .import_wallet_with_config(&name, &mnemonic, config, &passphrase, bip39.as_ref())
+                .await?;
+
             println!("Wallet imported successfully!");
             println!("  Name: {}", wallet.name());
             println!("  ID: {}", wallet.id());
@@ -103,7 +179,7 @@ pub async fn handle_wallet_command(cmd: WalletCommands, manager: &WalletManager)
         }
 
         WalletCommands::List => {
-            let wallets = manager.list_wallets().await?;
+            let wallets = manager.list_wallet_summaries().await?;
 
             if wallets.is_empty() {
                 println!("No wallets found.");
@@ -115,27 +191,40 @@ pub async fn handle_wallet_command(cmd: WalletCommands, manager: &WalletManager)
             table.load_preset(UTF8_FULL);
             table.set_header(vec!["Name", "Network", "Status"]);
 
-            for wallet_name in wallets {
-                match manager.load_wallet(&wallet_name).await {
-                    Ok(wallet) => {
-                        table.add_row(vec![wallet.name(), &wallet.network_display(), "Available"]);
-                    }
-                    Err(_) => {
-                        table.add_row(vec![&wallet_name, "Unknown", "Error"]);
-                    }
-                }
+            for summary in wallets {
+                let status = if summary.seed_encrypted {
+                    "Encrypted"
+                } else {
+                    "Unencrypted (load to migrate)"
+                };
+                table.add_row(vec![&summary.name, &summary.network_display, status]);
             }
 
             println!("{}", table);
         }
 
         WalletCommands::Info { name } => {
-            let wallet = manager.load_wallet(&name).await?;
+            let wallet = open_wallet(manager, &name).await?;
 
             println!("Wallet Information:");
             println!("  Name: {}", wallet.name());
             println!("  ID: {}", wallet.id());
             println!("  Network: {:?}", wallet.network_display());
+            println!("  On-chain script: {:?}", wallet.config().onchain_script);
+            match &wallet.config().chain_backend {
+                ChainBackendConfig::Esplora => {
+                    println!(
+                        "  Chain backend: Esplora ({})",
+                        wallet.config().esplora_urls().join(", ")
+                    )
+                }
+                ChainBackendConfig::Bitcoind(rpc) => {
+                    println!("  Chain backend: bitcoind ({})", rpc.url)
+                }
+                ChainBackendConfig::Electrum(electrum) => {
+                    println!("  Chain backend: Electrum ({})", electrum.url)
+                }
+            }
             println!();
 
             // Get addresses
@@ -182,6 +271,107 @@ pub async fn handle_wallet_command(cmd: WalletCommands, manager: &WalletManager)
     Ok(())
 }
 
+/// Read the wallet passphrase from `ARKIVE_PASSPHRASE`, or prompt for it
+pub fn read_passphrase(confirm: bool) -> Result<String> {
+    if let Ok(passphrase) = std::env::var("ARKIVE_PASSPHRASE") {
+        return Ok(passphrase);
+    }
+
+    let mut prompt = Password::new().with_prompt("Enter wallet passphrase");
+    if confirm {
+        prompt = prompt.with_confirmation("Confirm wallet passphrase", "Passphrases don't match");
+    }
+
+    prompt
+        .interact()
+        .map_err(|e| ArkiveError::dialog(e.to_string()))
+}
+
+/// Read the BIP39 passphrase from `ARKIVE_BIP39_PASSPHRASE`, or prompt for it
+fn read_bip39_passphrase(confirm: bool, persist: bool) -> Result<Bip39Passphrase> {
+    let passphrase = if let Ok(passphrase) = std::env::var("ARKIVE_BIP39_PASSPHRASE") {
+        passphrase
+    } else {
+        let mut prompt = Password::new()
+            .with_prompt("Enter BIP39 passphrase")
+            .allow_empty_password(true);
+        if confirm {
+            prompt =
+                prompt.with_confirmation("Confirm BIP39 passphrase", "Passphrases don't match");
+        }
+        prompt
+            .interact()
+            .map_err(|e| ArkiveError::dialog(e.to_string()))?
+    };
+
+    Ok(Bip39Passphrase {
+        passphrase,
+        persist,
+    })
+}
+
+/// Load a wallet, decrypting its seed with the user's passphrase
+pub async fn open_wallet(manager: &WalletManager, name: &str) -> Result<Arc<ArkWallet>> {
+    let passphrase = read_passphrase(false)?;
+
+    match manager.load_wallet(name, &passphrase).await {
+        Err(ArkiveError::Bip39PassphraseRequired) => {
+            let bip39 = read_bip39_passphrase(false, false)?;
+            manager
+                .load_wallet_with_bip39(name, &passphrase, Some(&bip39.passphrase))
+                .await
+        }
+        result => result,
+    }
+}
+
+fn wallet_config(network: &str, taproot: bool, chain: &ChainArgs) -> Result<WalletConfig> {
+    let (network, is_mutinynet) = parse_network(network)?;
+    let script_type = if taproot {
+        OnchainScriptType::P2tr
+    } else {
+        OnchainScriptType::P2wpkh
+    };
+
+    let mut config = WalletConfig::new_with_mutinynet(network, is_mutinynet)
+        .with_onchain_script(script_type)
+        .with_chain_backend(chain_backend(chain)?);
+    if !chain.esplora_fallbacks.is_empty() {
+        config = config.with_esplora_fallbacks(chain.esplora_fallbacks.clone());
+    }
+
+    Ok(config)
+}
+
+fn chain_backend(chain: &ChainArgs) -> Result<ChainBackendConfig> {
+    if let Some(url) = &chain.electrum_url {
+        return Ok(ChainBackendConfig::Electrum(ElectrumConfig {
+            url: url.clone(),
+            validate_domain: !chain.no_validate_domain,
+        }));
+    }
+
+    let Some(url) = &chain.bitcoind_url else {
+        return Ok(ChainBackendConfig::Esplora);
+    };
+
+    let auth = match (&chain.rpc_cookie, &chain.rpc_user) {
+        (Some(path), _) => RpcAuth::CookieFile(path.clone()),
+        (None, Some(username)) => RpcAuth::UserPass {
+            username: username.clone(),
+            password: Password::new()
+                .with_prompt("Enter bitcoind RPC password")
+                .interact()?,
+        },
+        (None, None) => RpcAuth::None,
+    };
+
+    Ok(ChainBackendConfig::Bitcoind(BitcoindConfig {
+        url: url.clone(),
+        auth,
+    }))
+}
+
 fn parse_network(network: &str) -> Result<(Network, bool)> {
     match network.to_lowercase().as_str() {
         "signet" => Ok((Network::Signet, false)),
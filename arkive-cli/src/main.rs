@@ -100,6 +100,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             ArkiveError::InvalidAddress(addr) => {
                 eprintln!("Error: Invalid address: {}", addr);
             }
+            ArkiveError::InvalidPassphrase => {
+                eprintln!("Error: Invalid wallet passphrase");
+            }
+            ArkiveError::Bip39PassphraseRequired => {
+                eprintln!("Error: This wallet requires its BIP39 passphrase");
+            }
+            ArkiveError::WalletLocked => {
+                eprintln!("Error: Wallet is locked");
+                eprintln!("Unlock it with the wallet passphrase and try again");
+            }
             _ => {
                 eprintln!("Error: {}", e);
             }
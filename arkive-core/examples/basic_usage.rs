@@ -16,7 +16,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     println!("Creating wallet...");
     let (wallet, mnemonic) = manager
-        .create_wallet("example-wallet", Network::Regtest)
+        .create_wallet(
+            "example-wallet",
+            Network::Regtest,
+            "example-passphrase",
+            None,
+        )
         .await?;
 
     println!("Wallet created!");
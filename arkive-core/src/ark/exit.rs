@@ -0,0 +1,385 @@
+//! Building blocks of unilateral exits: decoding a VTXO's presigned branch,
+//! checking its exit timelock and sweeping it once the timelock has passed
+
+use crate::bitcoin::tx_builder;
+use crate::error::{ArkiveError, Result};
+use crate::storage::vtxo_store::VtxoState;
+
+use bitcoin::absolute::LockTime;
+use bitcoin::hashes::Hash;
+use bitcoin::key::Keypair;
+use bitcoin::relative;
+use bitcoin::secp256k1::{Message, Secp256k1, Verification};
+use bitcoin::sighash::{Prevouts, SighashCache, TapSighashType};
+use bitcoin::taproot::{self, LeafVersion, TapLeafHash};
+use bitcoin::transaction::Version;
+use bitcoin::{
+    Amount, FeeRate, OutPoint, Script, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
+    Witness, XOnlyPublicKey,
+};
+
+/// Decode the presigned branch of `vtxo`, checking that it ends in the
+/// transaction creating the VTXO
+pub fn exit_branch(vtxo: &VtxoState, outpoint: &OutPoint) -> Result<Vec<Transaction>> {
+    if vtxo.exit_transactions.is_empty() {
+        return Err(ArkiveError::ark(format!(
+            "No presigned exit transactions stored for VTXO {}",
+            outpoint
+        )));
+    }
+
+    let branch = vtxo
+        .exit_transactions
+        .iter()
+        .map(|raw| {
+            bitcoin::consensus::deserialize::<Transaction>(raw).map_err(|e| {
+                ArkiveError::internal(format!("Invalid stored exit transaction: {}", e))
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let leaf_txid = branch.last().map(|tx| tx.compute_txid());
+    if leaf_txid != Some(outpoint.txid) {
+        return Err(ArkiveError::ark(format!(
+            "Exit branch of VTXO {} ends in {:?} instead of its own transaction",
+            outpoint, leaf_txid
+        )));
+    }
+
+    Ok(branch)
+}
+
+/// Pay-to-anchor output of a tree transaction, through which a child pays
+/// its fee. Tree transactions with one pay no fee themselves.
+pub fn anchor(tx: &Transaction) -> Option<(OutPoint, TxOut)> {
+    let txid = tx.compute_txid();
+    tx.output
+        .iter()
+        .enumerate()
+        .find(|(_, output)| tx_builder::is_anchor(&output.script_pubkey))
+        .map(|(vout, output)| (OutPoint::new(txid, vout as u32), output.clone()))
+}
+
+/// Problems that would keep `branch` from exiting the VTXO at `outpoint`:
+/// transactions not spending their predecessor, signatures not valid for
+/// the outputs they spend, or a VTXO output other than `vtxo_script` with
+/// `amount`. The root's signature is only checked if the batch's
+/// `commitment` transaction is at hand.
+pub fn verify_branch(
+    branch: &[Transaction],
+    commitment_txid: Txid,
+    commitment: Option<&Transaction>,
+    outpoint: &OutPoint,
+    amount: Amount,
+    vtxo_script: &Script,
+) -> Vec<String> {
+    let secp = Secp256k1::verification_only();
+    let mut problems = Vec::new();
+
+    let mut parent_txid = commitment_txid;
+    let mut parent = commitment;
+    for tx in branch {
+        let txid = tx.compute_txid();
+
+        match tx.input.as_slice() {
+            [input] if input.previous_output.txid != parent_txid => problems.push(format!(
+                "Transaction {} does not spend {}",
+                txid, parent_txid
+            )),
+            [input] => {
+                let vout = input.previous_output.vout as usize;
+                match parent.map(|parent| parent.output.get(vout)) {
+                    Some(Some(prevout)) => {
+                        if let Err(problem) = verify_key_spend(&secp, tx, prevout) {
+                            problems.push(format!("Transaction {} {}", txid, problem));
+                        }
+                    }
+                    Some(None) => problems.push(format!(
+                        "Transaction {} spends missing output {}",
+                        txid, input.previous_output
+                    )),
+                    None => {}
+                }
+            }
+            inputs => problems.push(format!(
+                "Transaction {} has {} inputs instead of one",
+                txid,
+                inputs.len()
+            )),
+        }
+
+        parent_txid = txid;
+        parent = Some(tx);
+    }
+
+    match branch
+        .last()
+        .and_then(|tx| tx.output.get(outpoint.vout as usize))
+    {
+        _ if parent_txid != outpoint.txid => problems.push(format!(
+            "Branch does not end in the VTXO's transaction {}",
+            outpoint.txid
+        )),
+        Some(output) => {
+            if output.script_pubkey.as_script() != vtxo_script {
+                problems.push(
+                    "VTXO output is not claimable with our key and the server's exit delay"
+                        .to_string(),
+                );
+            }
+            if output.value != amount {
+                problems.push(format!(
+                    "VTXO output holds {} sats instead of {}",
+                    output.value.to_sat(),
+                    amount.to_sat()
+                ));
+            }
+        }
+        None => problems.push(format!("VTXO output {} is missing", outpoint)),
+    }
+
+    problems
+}
+
+/// Check the key path signature of a single-input transaction
+fn verify_key_spend<C: Verification>(
+    secp: &Secp256k1<C>,
+    tx: &Transaction,
+    prevout: &TxOut,
+) -> std::result::Result<(), String> {
+    let script = &prevout.script_pubkey;
+    let output_key = script
+        .is_p2tr()
+        .then(|| XOnlyPublicKey::from_slice(&script.as_bytes()[2..34]).ok())
+        .flatten()
+        .ok_or("spends a non-taproot output")?;
+
+    let witness = &tx.input[0].witness;
+    if witness.len() != 1 {
+        return Err("is not signed through the key path".to_string());
+    }
+    let signature = taproot::Signature::from_slice(&witness[0])
+        .map_err(|e| format!("has a malformed signature: {}", e))?;
+
+    let sighash = SighashCache::new(tx)
+        .taproot_key_spend_signature_hash(0, &Prevouts::All(&[prevout]), signature.sighash_type)
+        .map_err(|e| format!("cannot be hashed for signing: {}", e))?;
+    secp.verify_schnorr(
+        &signature.signature,
+        &Message::from_digest(sighash.to_byte_array()),
+        &output_key,
+    )
+    .map_err(|_| "has an invalid signature".to_string())
+}
+
+/// Whether an output confirmed at `confirmed_height`, in a block with time
+/// `confirmed_time`, can be spent with relative timelock `sequence` in the
+/// block after `tip_height`.
+///
+/// Time-based locks are checked against the wall clock, which runs ahead of
+/// the median time past consensus uses, so a claim may still be rejected
+/// for a while and has to be retried.
+pub fn is_claimable(
+    sequence: Sequence,
+    confirmed_height: u32,
+    confirmed_time: u64,
+    tip_height: u32,
+    now: u64,
+) -> bool {
+    match sequence.to_relative_lock_time() {
+        Some(relative::LockTime::Blocks(blocks)) => {
+            tip_height + 1 >= confirmed_height + blocks.value() as u32
+        }
+        Some(relative::LockTime::Time(time)) => now >= confirmed_time + time.value() as u64 * 512,
+        None => true,
+    }
+}
+
+/// Spend an exited VTXO through its exit path to `destination`, paying
+/// `fee_rate` out of the VTXO's amount
+pub fn build_claim_tx(
+    vtxo: &ark_core::Vtxo,
+    outpoint: OutPoint,
+    amount: Amount,
+    exit_delay: Sequence,
+    destination: ScriptBuf,
+    fee_rate: FeeRate,
+    keypair: &Keypair,
+) -> Result<Transaction> {
+    let (exit_script, control_block) = vtxo.exit_spend_info();
+    let witness = |signature: &[u8]| {
+        Witness::from_slice(&[
+            signature.to_vec(),
+            exit_script.to_bytes(),
+            control_block.serialize(),
+        ])
+    };
+
+    let mut tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: outpoint,
+            script_sig: ScriptBuf::new(),
+            sequence: exit_delay,
+            // Placeholder of the final signature's size, to size the fee
+            witness: witness(&[0; 64]),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: destination,
+        }],
+    };
+
+    let fee = fee_rate
+        .fee_vb(tx.vsize() as u64)
+        .ok_or_else(|| ArkiveError::bitcoin("Fee overflow"))?;
+    let dust_limit = tx.output[0].script_pubkey.minimal_non_dust();
+    if amount < fee + dust_limit {
+        return Err(ArkiveError::InsufficientFunds {
+            need: (fee + dust_limit).to_sat(),
+            available: amount.to_sat(),
+        });
+    }
+    tx.output[0].value = amount - fee;
+
+    let prevout = TxOut {
+        value: amount,
+        script_pubkey: vtxo.script_pubkey(),
+    };
+    let leaf_hash = TapLeafHash::from_script(&exit_script, LeafVersion::TapScript);
+    let sighash = SighashCache::new(&tx)
+        .taproot_script_spend_signature_hash(
+            0,
+            &Prevouts::All(&[prevout]),
+            leaf_hash,
+            TapSighashType::Default,
+        )
+        .map_err(|e| ArkiveError::bitcoin(format!("Failed to compute sighash: {}", e)))?;
+
+    let secp = Secp256k1::new();
+    let msg = Message::from_digest(sighash.to_byte_array());
+    let signature = secp.sign_schnorr_no_aux_rand(&msg, keypair);
+    tx.input[0].witness = witness(&signature.serialize());
+
+    Ok(tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_claimable_blocks() {
+        let delay = Sequence::from_height(144);
+
+        // Spendable in the block 144 blocks after the confirming one
+        assert!(!is_claimable(delay, 1000, 0, 1142, 0));
+        assert!(is_claimable(delay, 1000, 0, 1143, 0));
+    }
+
+    /// Chain of `depth` key path spends from `commitment`, signed by `keypair`
+    fn signed_branch(
+        commitment: &Transaction,
+        keypair: &Keypair,
+        depth: usize,
+    ) -> Vec<Transaction> {
+        let secp = Secp256k1::new();
+        let mut branch: Vec<Transaction> = Vec::new();
+
+        for _ in 0..depth {
+            let parent = branch.last().unwrap_or(commitment);
+            let mut tx = Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output: OutPoint::new(parent.compute_txid(), 0),
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::new(),
+                }],
+                output: vec![parent.output[0].clone()],
+            };
+
+            let sighash = SighashCache::new(&tx)
+                .taproot_key_spend_signature_hash(
+                    0,
+                    &Prevouts::All(&[&parent.output[0]]),
+                    TapSighashType::Default,
+                )
+                .unwrap();
+            let signature = secp
+                .sign_schnorr_no_aux_rand(&Message::from_digest(sighash.to_byte_array()), keypair);
+            tx.input[0].witness = Witness::p2tr_key_spend(&taproot::Signature {
+                signature,
+                sighash_type: TapSighashType::Default,
+            });
+            branch.push(tx);
+        }
+
+        branch
+    }
+
+    #[test]
+    fn test_verify_branch() {
+        let secp = Secp256k1::new();
+        let keypair = Keypair::from_seckey_slice(&secp, &[1; 32]).unwrap();
+        let amount = Amount::from_sat(10_000);
+        let script = ScriptBuf::new_p2tr_tweaked(
+            bitcoin::key::TweakedPublicKey::dangerous_assume_tweaked(keypair.x_only_public_key().0),
+        );
+
+        let commitment = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![],
+            output: vec![TxOut {
+                value: amount,
+                script_pubkey: script.clone(),
+            }],
+        };
+        let commitment_txid = commitment.compute_txid();
+
+        let branch = signed_branch(&commitment, &keypair, 2);
+        let outpoint = OutPoint::new(branch[1].compute_txid(), 0);
+        let verify = |branch: &[Transaction], outpoint: &OutPoint, script: &Script| {
+            verify_branch(
+                branch,
+                commitment_txid,
+                Some(&commitment),
+                outpoint,
+                amount,
+                script,
+            )
+        };
+
+        assert!(verify(&branch, &outpoint, &script).is_empty());
+        assert_eq!(verify(&branch, &outpoint, &ScriptBuf::new()).len(), 1);
+
+        // Changing the leaf invalidates its signature
+        let mut tampered = branch.clone();
+        tampered[1].output[0].value = Amount::from_sat(9_000);
+        let tampered_outpoint = OutPoint::new(tampered[1].compute_txid(), 0);
+        let problems = verify(&tampered, &tampered_outpoint, &script);
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].ends_with("has an invalid signature"));
+
+        // The root is missing
+        let problems = verify(&branch[1..], &outpoint, &script);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("does not spend"));
+
+        // Without the commitment transaction the root can't be checked
+        let problems = verify_branch(&branch, commitment_txid, None, &outpoint, amount, &script);
+        assert!(problems.is_empty());
+    }
+
+    #[test]
+    fn test_is_claimable_time() {
+        // 2 intervals of 512 seconds
+        let delay = Sequence::from_512_second_intervals(2);
+
+        assert!(!is_claimable(delay, 1000, 10_000, 1000, 11_023));
+        assert!(is_claimable(delay, 1000, 10_000, 1000, 11_024));
+    }
+}
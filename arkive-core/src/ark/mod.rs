@@ -1,12 +1,19 @@
 #![allow(unused_imports)]
+pub mod exit;
+pub mod tree;
+
+use crate::bitcoin::BitcoinService;
+use crate::chain::{ChainBackend, TxStatus};
 use crate::error::{ArkiveError, Result};
 use crate::storage::vtxo_store::{VtxoState, VtxoTreeData};
+use crate::storage::{ArkServerParams, ExitStage, ExitStore, UnilateralExit};
 use crate::storage::{BoardingOutputState, BoardingStore};
 use crate::storage::{Storage, VtxoStore};
 use crate::types::{
-    Transaction, TransactionSource, TransactionStatus, TransactionType, VtxoInfo, VtxoStatus,
+    ExitReadiness, Transaction, TransactionSource, TransactionStatus, TransactionType, VtxoInfo,
+    VtxoStatus,
 };
-use crate::wallet::WalletConfig;
+use crate::wallet::{KeySession, OnchainScriptType, WalletConfig, WalletPublicKeys};
 
 use ark_client::{Blockchain, Client, ExplorerUtxo, OfflineClient, SpendStatus};
 use ark_core::coin_select::select_vtxos;
@@ -17,24 +24,24 @@ use bip39::rand::SeedableRng;
 use bitcoin::key::Keypair;
 use bitcoin::{Amount, Network, Psbt};
 use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
 use rusqlite::params;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use tree::{BatchTree, TreeNode};
 
-// Blockchain implementation for Esplora
-pub struct EsploraBlockchain {
-    client: esplora_client::AsyncClient,
+// Blockchain implementation on the wallet's chain backend
+pub struct ChainBlockchain {
+    chain: Arc<dyn ChainBackend>,
 }
 
-impl EsploraBlockchain {
-    pub fn new(url: &str) -> Result<Self> {
-        let client = esplora_client::Builder::new(url)
-            .build_async()
-            .map_err(|e| ArkiveError::esplora(format!("Failed to create esplora client: {}", e)))?;
-        Ok(Self { client })
+impl ChainBlockchain {
+    pub fn new(chain: Arc<dyn ChainBackend>) -> Self {
+        Self { chain }
     }
 }
 
-impl Blockchain for EsploraBlockchain {
+impl Blockchain for ChainBlockchain {
     async fn find_outpoints(
         &self,
         address: &bitcoin::Address,
@@ -42,32 +49,31 @@ impl Blockchain for EsploraBlockchain {
         let script_pubkey = address.script_pubkey();
 
         let txs = self
-            .client
-            .scripthash_txs(&script_pubkey, None)
+            .chain
+            .script_history(&script_pubkey, None)
             .await
-            .map_err(|e| ark_client::Error::wallet(anyhow::anyhow!("Esplora error: {}", e)))?;
+            .map_err(|e| ark_client::Error::wallet(anyhow::anyhow!("{}", e)))?;
+
+        // Any spend of an output to the script is part of the script's history
+        let spent: HashSet<bitcoin::OutPoint> = txs
+            .iter()
+            .flat_map(|tx| tx.tx.input.iter().map(|input| input.previous_output))
+            .collect();
 
         let mut utxos = Vec::new();
-        for tx in txs {
-            for (vout, output) in tx.vout.iter().enumerate() {
-                if output.scriptpubkey == script_pubkey {
+        for tx in &txs {
+            for (vout, output) in tx.tx.output.iter().enumerate() {
+                if output.script_pubkey == script_pubkey {
                     let outpoint = bitcoin::OutPoint {
                         txid: tx.txid,
                         vout: vout as u32,
                     };
 
-                    let is_spent = match self.client.get_output_status(&tx.txid, vout as u64).await
-                    {
-                        Ok(Some(status)) => status.spent,
-                        Ok(None) => false,
-                        Err(_) => false,
-                    };
-
                     utxos.push(ExplorerUtxo {
                         outpoint,
-                        amount: bitcoin::Amount::from_sat(output.value),
+                        amount: output.value,
                         confirmation_blocktime: tx.status.block_time,
-                        is_spent,
+                        is_spent: spent.contains(&outpoint),
                     });
                 }
             }
@@ -80,23 +86,10 @@ impl Blockchain for EsploraBlockchain {
         &self,
         txid: &bitcoin::Txid,
     ) -> std::result::Result<Option<bitcoin::Transaction>, ark_client::Error> {
-        match self.client.get_tx(txid).await {
-            Ok(Some(tx)) => {
-                let tx_bytes = bitcoin::consensus::serialize(&tx);
-                match bitcoin::consensus::deserialize(&tx_bytes) {
-                    Ok(tx) => Ok(Some(tx)),
-                    Err(e) => Err(ark_client::Error::wallet(anyhow::anyhow!(
-                        "Deserialization error: {}",
-                        e
-                    ))),
-                }
-            }
-            Ok(None) => Ok(None),
-            Err(e) => Err(ark_client::Error::wallet(anyhow::anyhow!(
-                "Esplora error: {}",
-                e
-            ))),
-        }
+        self.chain
+            .get_tx(txid)
+            .await
+            .map_err(|e| ark_client::Error::wallet(anyhow::anyhow!("{}", e)))
     }
 
     async fn get_output_status(
@@ -104,47 +97,50 @@ impl Blockchain for EsploraBlockchain {
         txid: &bitcoin::Txid,
         vout: u32,
     ) -> std::result::Result<SpendStatus, ark_client::Error> {
-        let status = self
-            .client
-            .get_output_status(txid, vout as u64)
+        let spend_txid = self
+            .chain
+            .get_output_spend(&bitcoin::OutPoint::new(*txid, vout))
             .await
-            .map_err(|e| ark_client::Error::wallet(anyhow::anyhow!("Esplora error: {}", e)))?;
+            .map_err(|e| ark_client::Error::wallet(anyhow::anyhow!("{}", e)))?;
 
-        Ok(SpendStatus {
-            spend_txid: status.and_then(|s| s.txid),
-        })
+        Ok(SpendStatus { spend_txid })
     }
 
     async fn broadcast(
         &self,
         tx: &bitcoin::Transaction,
     ) -> std::result::Result<(), ark_client::Error> {
-        self.client
+        self.chain
             .broadcast(tx)
             .await
-            .map_err(|e| ark_client::Error::wallet(anyhow::anyhow!("Broadcast error: {}", e)))?;
-        Ok(())
+            .map_err(|e| ark_client::Error::wallet(anyhow::anyhow!("{}", e)))
     }
 }
 
 // Wallet implementation for Ark
 pub struct ArkWalletImpl {
-    keypair: Keypair,
+    session: KeySession,
+    public_keys: WalletPublicKeys,
     network: Network,
+    onchain_script: OnchainScriptType,
     storage: Arc<Storage>,
     wallet_id: String,
 }
 
 impl ArkWalletImpl {
     pub fn new(
-        keypair: Keypair,
+        session: KeySession,
+        public_keys: WalletPublicKeys,
         network: Network,
+        onchain_script: OnchainScriptType,
         storage: Arc<Storage>,
         wallet_id: String,
     ) -> Self {
         Self {
-            keypair,
+            session,
+            public_keys,
             network,
+            onchain_script,
             storage,
             wallet_id,
         }
@@ -159,7 +155,7 @@ impl ark_client::wallet::BoardingWallet for ArkWalletImpl {
         network: Network,
     ) -> std::result::Result<ark_core::BoardingOutput, ark_client::Error> {
         let secp = bitcoin::secp256k1::Secp256k1::new();
-        let (owner_pk, _) = self.keypair.x_only_public_key();
+        let (owner_pk, _) = self.public_keys.ark.x_only_public_key();
 
         ark_core::BoardingOutput::new(&secp, server_pk, owner_pk, exit_delay, network).map_err(
             |e| {
@@ -218,24 +214,35 @@ impl ark_client::wallet::BoardingWallet for ArkWalletImpl {
 
     fn sign_for_pk(
         &self,
-        _pk: &bitcoin::XOnlyPublicKey,
+        pk: &bitcoin::XOnlyPublicKey,
         msg: &bitcoin::secp256k1::Message,
     ) -> std::result::Result<bitcoin::secp256k1::schnorr::Signature, ark_client::Error> {
-        let secp = bitcoin::secp256k1::Secp256k1::new();
-        let sig = secp.sign_schnorr_no_aux_rand(msg, &self.keypair);
-        Ok(sig)
+        let signature = self
+            .session
+            .with_keys(|keys| {
+                let keypair = if keys.onchain.x_only_public_key().0 == *pk {
+                    &keys.onchain
+                } else if keys.ark.x_only_public_key().0 == *pk {
+                    &keys.ark
+                } else {
+                    return None;
+                };
+
+                let secp = bitcoin::secp256k1::Secp256k1::new();
+                Some(secp.sign_schnorr_no_aux_rand(msg, keypair))
+            })
+            .map_err(|e| ark_client::Error::wallet(anyhow::anyhow!("{}", e)))?;
+
+        signature
+            .ok_or_else(|| ark_client::Error::wallet(anyhow::anyhow!("Unknown public key {}", pk)))
     }
 }
 
 impl ark_client::wallet::OnchainWallet for ArkWalletImpl {
     fn get_onchain_address(&self) -> std::result::Result<bitcoin::Address, ark_client::Error> {
-        let pubkey = self.keypair.public_key();
-        let pubkey_bytes = pubkey.serialize();
-        let wpkh = bitcoin::key::CompressedPublicKey::from_slice(&pubkey_bytes).map_err(|e| {
-            ark_client::Error::wallet(anyhow::anyhow!("Failed to create WPKH: {}", e))
-        })?;
-        let address = bitcoin::Address::p2wpkh(&wpkh, self.network);
-        Ok(address)
+        Ok(self
+            .onchain_script
+            .address(&self.public_keys.onchain, self.network))
     }
 
     async fn sync(&self) -> std::result::Result<(), ark_client::Error> {
@@ -271,31 +278,55 @@ impl ark_client::wallet::OnchainWallet for ArkWalletImpl {
     }
 }
 
+type ArkClient = Client<ChainBlockchain, ArkWalletImpl>;
+
 pub struct ArkService {
-    client: Option<Client<EsploraBlockchain, ArkWalletImpl>>,
-    keypair: Keypair,
+    client: Arc<RwLock<Option<Arc<ArkClient>>>>,
+    session: KeySession,
+    public_keys: WalletPublicKeys,
     config: WalletConfig,
+    chain: Arc<dyn ChainBackend>,
     storage: Arc<Storage>,
     wallet_id: String,
     tx_manager: TransactionManager,
+    // Addresses from the last connection, so they stay available while locked
+    cached_address: RwLock<Option<String>>,
+    cached_boarding_address: RwLock<Option<String>>,
 }
 
 impl ArkService {
     pub async fn new(
-        keypair: Keypair,
+        session: KeySession,
+        public_keys: WalletPublicKeys,
         config: WalletConfig,
+        chain: Arc<dyn ChainBackend>,
         storage: Arc<Storage>,
         wallet_id: String,
     ) -> Result<Self> {
         let tx_manager = TransactionManager::new(storage.clone(), wallet_id.clone());
 
-        let mut service = Self {
-            client: None,
-            keypair,
+        // The client holds its own copy of the Ark key, so it goes as soon
+        // as the session locks, including on timeout
+        let client = Arc::new(RwLock::new(None::<Arc<ArkClient>>));
+        let weak_client = Arc::downgrade(&client);
+        session.on_lock(move || {
+            let client = weak_client.upgrade();
+            if client.is_some_and(|client| client.write().take().is_some()) {
+                tracing::info!("Disconnected from Ark server");
+            }
+        });
+
+        let service = Self {
+            client,
+            session,
+            public_keys,
             config,
+            chain,
             storage: storage.clone(),
             wallet_id: wallet_id.clone(),
             tx_manager,
+            cached_address: RwLock::new(None),
+            cached_boarding_address: RwLock::new(None),
         };
 
         // Try to connect to Ark server
@@ -306,18 +337,26 @@ impl ArkService {
         Ok(service)
     }
 
-    async fn connect(&mut self) -> Result<()> {
-        let blockchain = Arc::new(EsploraBlockchain::new(&self.config.esplora_url)?);
+    /// Connect to the Ark server. The client holds the signing key, so this
+    /// requires an unlocked session.
+    pub async fn connect(&self) -> Result<()> {
+        // The client signs with its own copy of the key, which is why it is
+        // dropped when the session locks
+        let keypair = self.session.with_keys(|keys| keys.ark)?;
+
+        let blockchain = Arc::new(ChainBlockchain::new(self.chain.clone()));
         let wallet = Arc::new(ArkWalletImpl::new(
-            self.keypair,
+            self.session.clone(),
+            self.public_keys,
             self.config.network,
+            self.config.onchain_script,
             self.storage.clone(),
             self.wallet_id.clone(),
         ));
 
         let offline_client = OfflineClient::new(
             "arkive-sdk".to_string(),
-            self.keypair,
+            keypair,
             blockchain,
             wallet,
             self.config.ark_server_url.clone(),
@@ -325,9 +364,29 @@ impl ArkService {
 
         match offline_client.connect().await {
             Ok(client) => {
-                self.client = Some(client);
+                if let Ok((address, _)) = client.get_offchain_address() {
+                    *self.cached_address.write() = Some(address.to_string());
+                }
+                if let Ok(address) = client.get_boarding_address() {
+                    *self.cached_boarding_address.write() = Some(address.to_string());
+                }
+
+                // Kept for exiting without the server later
+                let params = ArkServerParams {
+                    server_pubkey: client.server_info.pk.x_only_public_key().0.to_string(),
+                    unilateral_exit_delay: client
+                        .server_info
+                        .unilateral_exit_delay
+                        .to_consensus_u32(),
+                    boarding_exit_delay: client.server_info.boarding_exit_delay.to_consensus_u32(),
+                };
+
+                *self.client.write() = Some(Arc::new(client));
                 tracing::info!("Connected to Ark server");
-                Ok(())
+
+                VtxoStore::new(&self.storage)
+                    .save_server_params(&self.wallet_id, &params)
+                    .await
             }
             Err(e) => Err(ArkiveError::ark(format!(
                 "Failed to connect to Ark server: {}",
@@ -336,11 +395,31 @@ impl ArkService {
         }
     }
 
+    /// Drop the server connection together with the signing key it holds
+    pub fn disconnect(&self) {
+        if self.client.write().take().is_some() {
+            tracing::info!("Disconnected from Ark server");
+        }
+    }
+
+    /// Current server connection, dropped once the session has been locked
+    fn current_client(&self) -> Option<Arc<ArkClient>> {
+        if !self.session.is_unlocked() {
+            self.disconnect();
+            return None;
+        }
+        self.client.read().clone()
+    }
+
+    fn connected_client(&self) -> Result<Arc<ArkClient>> {
+        // Surface a locked wallet before a missing connection
+        self.session.ensure_unlocked()?;
+        self.current_client()
+            .ok_or_else(|| ArkiveError::internal("Ark server not connected"))
+    }
+
     pub async fn send(&self, address: ArkAddress, amount: Amount) -> Result<String> {
-        let client = self
-            .client
-            .as_ref()
-            .ok_or_else(|| ArkiveError::internal("Ark server not connected"))?;
+        let client = self.connected_client()?;
 
         // 1. Get available VTXOs
         let available_vtxos = self.get_spendable_vtxos().await?;
@@ -393,7 +472,7 @@ impl ArkService {
                         // Create VTXO from stored state
                         let secp = bitcoin::secp256k1::Secp256k1::new();
                         let server_pk = client.server_info.pk.x_only_public_key().0;
-                        let (owner_pk, _) = self.keypair.x_only_public_key();
+                        let (owner_pk, _) = self.public_keys.ark.x_only_public_key();
 
                         let vtxo = ark_core::Vtxo::new_default(
                             &secp,
@@ -429,23 +508,26 @@ impl ArkService {
         .map_err(|e| ArkiveError::ark(format!("Failed to build transaction: {}", e)))?;
 
         // 6. Sign the transaction
-        let sign_fn = |msg: bitcoin::secp256k1::Message| -> std::result::Result<
-            (
-                bitcoin::secp256k1::schnorr::Signature,
-                bitcoin::XOnlyPublicKey,
-            ),
-            ark_core::Error,
-        > {
-            let secp = bitcoin::secp256k1::Secp256k1::new();
-            let sig = secp.sign_schnorr_no_aux_rand(&msg, &self.keypair);
-            let pk = self.keypair.x_only_public_key().0;
-            Ok((sig, pk))
-        };
+        self.session.with_keys(|keys| -> Result<()> {
+            let sign_fn = |msg: bitcoin::secp256k1::Message| -> std::result::Result<
+                (
+                    bitcoin::secp256k1::schnorr::Signature,
+                    bitcoin::XOnlyPublicKey,
+                ),
+                ark_core::Error,
+            > {
+                let secp = bitcoin::secp256k1::Secp256k1::new();
+                let sig = secp.sign_schnorr_no_aux_rand(&msg, &keys.ark);
+                let pk = keys.ark.x_only_public_key().0;
+                Ok((sig, pk))
+            };
 
-        for (i, _) in vtxo_inputs.iter().enumerate() {
-            sign_redeem_transaction(sign_fn, &mut redeem_psbt, &vtxo_inputs, i)
-                .map_err(|e| ArkiveError::ark(format!("Failed to sign transaction: {}", e)))?;
-        }
+            for (i, _) in vtxo_inputs.iter().enumerate() {
+                sign_redeem_transaction(sign_fn, &mut redeem_psbt, &vtxo_inputs, i)
+                    .map_err(|e| ArkiveError::ark(format!("Failed to sign transaction: {}", e)))?;
+            }
+            Ok(())
+        })??;
 
         // 7. Submit to server
         let signed_psbt = client
@@ -525,10 +607,7 @@ impl ArkService {
     }
 
     pub async fn participate_in_round(&self) -> Result<Option<String>> {
-        let client = self
-            .client
-            .as_ref()
-            .ok_or_else(|| ArkiveError::internal("Ark server not connected"))?;
+        let client = self.connected_client()?;
 
         // Sync to detect any new boarding outputs
         self.detect_and_store_boarding_outputs().await?;
@@ -581,8 +660,8 @@ impl ArkService {
             tracing::info!("Round participation attempt {}", attempt);
 
             match client.board(&mut rng).await {
-                Ok(_) => {
-                    let round_id = format!("round_{}", chrono::Utc::now().timestamp());
+                Ok(round_txid) => {
+                    let round_id = round_txid.to_string();
 
                     // Wait for server processing
                     let wait_time = std::cmp::min(5 + (attempt - 1) * 2, 10);
@@ -594,6 +673,16 @@ impl ArkService {
                     let new_vtxos = self.get_spendable_vtxos().await?;
 
                     if !new_vtxos.is_empty() {
+                        // The settled VTXOs were spent into the round
+                        let vtxo_store = VtxoStore::new(&self.storage);
+                        for vtxo in &vtxos {
+                            let spent = VtxoState {
+                                status: VtxoStatus::Spent,
+                                ..vtxo.clone()
+                            };
+                            vtxo_store.save_vtxo_state(&self.wallet_id, &spent).await?;
+                        }
+
                         // Mark boarding outputs as spent with round tracking
                         let boarding_outpoints: Vec<bitcoin::OutPoint> =
                             boarding_states.iter().map(|s| s.outpoint).collect();
@@ -636,13 +725,172 @@ impl ArkService {
             }
         }
 
-        unreachable!("Loop always returns")
+        Err(ArkiveError::ark(
+            "Round completed but no new VTXOs showed up after 3 attempts",
+        ))
+    }
+
+    /// Cooperatively leave Ark: join the next round asking for an on-chain
+    /// output of `amount` to `address`. Every spendable VTXO may be forfeited
+    /// into the round, not just enough to cover the amount, with the rest
+    /// returned as a new VTXO minus the server's fee. Returns the round's
+    /// commitment txid.
+    pub async fn offboard(&self, address: bitcoin::Address, amount: Amount) -> Result<String> {
+        self.session.ensure_unlocked()?;
+
+        let dust_limit = address.script_pubkey().minimal_non_dust();
+        if amount < dust_limit {
+            return Err(ArkiveError::config(format!(
+                "Offboard amount {} sats is below the dust limit of {} sats",
+                amount.to_sat(),
+                dust_limit.to_sat()
+            )));
+        }
+
+        // The server takes its fee from the VTXOs on top of the amount
+        let estimated_fee = self.estimate_fee(amount).await?;
+        let spendable = self.get_spendable_vtxos().await?;
+        let available: Amount = spendable.iter().map(|vtxo| vtxo.amount).sum();
+        if available < amount + estimated_fee {
+            return Err(ArkiveError::InsufficientFunds {
+                need: (amount + estimated_fee).to_sat(),
+                available: available.to_sat(),
+            });
+        }
+
+        let client = self.connected_client()?;
+        let mut rng = StdRng::from_entropy();
+        let round_txid = client
+            .collaborative_redeem(&mut rng, address.clone(), amount)
+            .await
+            .map_err(|e| ArkiveError::ark(format!("Failed to offboard: {}", e)))?
+            .to_string();
+
+        // Recorded before syncing so the server's history doesn't claim the
+        // round as a plain Ark transaction. The amount is corrected below
+        // once the change is known.
+        self.tx_manager
+            .record_transaction_if_new(
+                &round_txid,
+                -((amount + estimated_fee).to_sat() as i64),
+                TransactionType::Exit,
+                TransactionSource::LocalRound,
+            )
+            .await?;
+
+        // VTXOs the server no longer lists were spent into the round
+        let unspent: HashSet<String> = client
+            .spendable_vtxos()
+            .await
+            .map_err(|e| ArkiveError::ark(format!("Failed to get VTXOs from server: {}", e)))?
+            .into_iter()
+            .flat_map(|(outpoints, _)| outpoints)
+            .map(|outpoint| outpoint.outpoint.to_string())
+            .collect();
+        let vtxo_store = VtxoStore::new(&self.storage);
+        let mut forfeited = Amount::ZERO;
+        let mut known = HashSet::new();
+        for vtxo in spendable {
+            known.insert(vtxo.outpoint.clone());
+            if !unspent.contains(&vtxo.outpoint) {
+                forfeited += vtxo.amount;
+                let spent = VtxoState {
+                    status: VtxoStatus::Spent,
+                    ..vtxo
+                };
+                vtxo_store.save_vtxo_state(&self.wallet_id, &spent).await?;
+            }
+        }
+
+        // Pick up the change VTXO
+        self.force_sync_with_server().await?;
+
+        // What left the wallet is what was forfeited minus the change, the
+        // server's fee being whatever that exceeds the amount by
+        let change: Amount = self
+            .get_spendable_vtxos()
+            .await?
+            .iter()
+            .filter(|vtxo| !known.contains(&vtxo.outpoint))
+            .map(|vtxo| vtxo.amount)
+            .sum();
+        let fee = match forfeited.checked_sub(change + amount) {
+            Some(fee) => {
+                self.tx_manager
+                    .update_transaction_cost(&round_txid, -((amount + fee).to_sat() as i64), fee)
+                    .await?;
+                fee
+            }
+            None => {
+                tracing::warn!(
+                    "Could not tell the fee of offboard round {}, keeping the estimate of {} sats",
+                    round_txid,
+                    estimated_fee.to_sat()
+                );
+                estimated_fee
+            }
+        };
+
+        tracing::info!(
+            "Offboarded {} sats to {} in round {} with {} sats fee",
+            amount.to_sat(),
+            address,
+            round_txid,
+            fee.to_sat()
+        );
+        Ok(round_txid)
+    }
+
+    /// Spendable VTXOs expiring within `threshold`, soonest first
+    pub async fn get_renewable_vtxos(
+        &self,
+        threshold: std::time::Duration,
+    ) -> Result<Vec<VtxoState>> {
+        let deadline = Utc::now()
+            + chrono::Duration::from_std(threshold)
+                .map_err(|e| ArkiveError::config(format!("Invalid renewal threshold: {}", e)))?;
+
+        let mut vtxos: Vec<VtxoState> = self
+            .get_spendable_vtxos()
+            .await?
+            .into_iter()
+            .filter(|vtxo| vtxo.expiry <= deadline)
+            .collect();
+        vtxos.sort_by_key(|vtxo| vtxo.expiry);
+        Ok(vtxos)
+    }
+
+    /// Outpoints of the spendable VTXOs and of the unspent boarding outputs,
+    /// all of which a round joined now would settle
+    pub async fn settleable_outpoints(&self) -> Result<(HashSet<String>, HashSet<String>)> {
+        let vtxos = self
+            .get_spendable_vtxos()
+            .await?
+            .into_iter()
+            .map(|vtxo| vtxo.outpoint)
+            .collect();
+        let boarding = BoardingStore::new(&self.storage)
+            .load_unspent_boarding_outputs(&self.wallet_id)
+            .await?
+            .into_iter()
+            .map(|state| state.outpoint.to_string())
+            .collect();
+
+        Ok((vtxos, boarding))
+    }
+
+    /// Settle all spendable VTXOs in the next round, reconnecting to the
+    /// server first if the connection was lost
+    pub async fn renew_vtxos(&self) -> Result<Option<String>> {
+        if self.current_client().is_none() {
+            self.connect().await?;
+        }
+        self.participate_in_round().await
     }
 
     async fn force_sync_with_server(&self) -> Result<()> {
         let client = self
-            .client
-            .as_ref()
+            .current_client()
             .ok_or_else(|| ArkiveError::internal("Ark server not connected"))?;
 
         // Get current VTXOs from server
@@ -655,20 +903,32 @@ impl ArkService {
         let vtxo_store = VtxoStore::new(&self.storage);
 
         // Get existing VTXOs to avoid duplicates
-        let existing_vtxos = self.get_all_vtxos().await?;
-        let existing_outpoints: std::collections::HashSet<String> =
-            existing_vtxos.iter().map(|v| v.outpoint.clone()).collect();
+        let existing_vtxos: HashMap<String, VtxoState> = self
+            .get_all_vtxos()
+            .await?
+            .into_iter()
+            .map(|v| (v.outpoint.clone(), v))
+            .collect();
+
+        // Batch trees fetched so far, by commitment txid
+        let mut trees: HashMap<bitcoin::Txid, Option<BatchTree>> = HashMap::new();
+        let mut grpc = None;
 
         // Process server VTXOs
         let mut new_vtxo_count = 0;
         for (outpoints, vtxo) in server_vtxos {
             for outpoint in outpoints {
-                // Skip if we already have this VTXO
-                if existing_outpoints.contains(&outpoint.outpoint.to_string()) {
+                let existing = existing_vtxos.get(&outpoint.outpoint.to_string());
+
+                // Skip if we already have this VTXO with its exit branch.
+                // Preconfirmed VTXOs get theirs once settled in a batch.
+                if existing.is_some_and(|v| !v.exit_transactions.is_empty())
+                    || (existing.is_some() && outpoint.is_pending)
+                {
                     continue;
                 }
 
-                let vtxo_state = VtxoState {
+                let mut vtxo_state = existing.cloned().unwrap_or_else(|| VtxoState {
                     outpoint: outpoint.outpoint.to_string(),
                     amount: outpoint.amount,
                     status: if outpoint.is_pending {
@@ -679,15 +939,65 @@ impl ArkService {
                     expiry: chrono::DateTime::from_timestamp(outpoint.expire_at, 0)
                         .unwrap_or_else(Utc::now),
                     address: vtxo.address().to_string(),
-                    batch_id: format!("batch_{}", outpoint.expire_at),
-                    tree_path: Vec::new(), // [TODO] Extract from VTXO tree
-                    exit_transactions: Vec::new(), // [TODO] Store exit transactions
-                };
+                    batch_id: outpoint.round_txid.to_string(),
+                    tree_path: Vec::new(),
+                    exit_transactions: Vec::new(),
+                });
+
+                if !outpoint.is_pending {
+                    let commitment_txid = outpoint.round_txid;
+                    if !trees.contains_key(&commitment_txid) {
+                        let tree = match self.fetch_batch_tree(&mut grpc, commitment_txid).await {
+                            Ok(tree) => {
+                                self.save_batch_tree(&tree, outpoint.expire_at).await?;
+                                Some(tree)
+                            }
+                            Err(e) => {
+                                tracing::warn!(
+                                    "Failed to fetch VTXO tree of batch {}: {}",
+                                    commitment_txid,
+                                    e
+                                );
+                                None
+                            }
+                        };
+                        trees.insert(commitment_txid, tree);
+                    }
+
+                    if let Some(tree) = &trees[&commitment_txid] {
+                        match tree.branch(outpoint.outpoint.txid) {
+                            Ok((path, branch)) => {
+                                vtxo_state.batch_id = commitment_txid.to_string();
+                                vtxo_state.tree_path = path;
+                                vtxo_state.exit_transactions =
+                                    branch.iter().map(bitcoin::consensus::serialize).collect();
+                            }
+                            Err(e) => tracing::warn!(
+                                "No exit branch for VTXO {}: {}",
+                                vtxo_state.outpoint,
+                                e
+                            ),
+                        }
+                    }
+                }
+
+                if existing.is_some() && vtxo_state.exit_transactions.is_empty() {
+                    continue;
+                }
 
                 vtxo_store
                     .save_vtxo_state(&self.wallet_id, &vtxo_state)
                     .await?;
 
+                if existing.is_some() {
+                    tracing::info!(
+                        "Stored exit branch of VTXO {} ({} transactions)",
+                        vtxo_state.outpoint,
+                        vtxo_state.exit_transactions.len()
+                    );
+                    continue;
+                }
+
                 new_vtxo_count += 1;
                 tracing::info!(
                     "Added new VTXO from server: {} with {} sats (status: {:?})",
@@ -736,10 +1046,76 @@ impl ArkService {
         Ok(())
     }
 
+    /// Fetch the VTXO tree of the batch settled by `commitment_txid`,
+    /// connecting to the server's gRPC API on first use
+    async fn fetch_batch_tree(
+        &self,
+        grpc: &mut Option<ark_grpc::Client>,
+        commitment_txid: bitcoin::Txid,
+    ) -> Result<BatchTree> {
+        if grpc.is_none() {
+            let mut client = ark_grpc::Client::new(self.config.ark_server_url.clone());
+            client.connect().await.map_err(|e| {
+                ArkiveError::network_connection(format!("Failed to connect to Ark server: {}", e))
+            })?;
+            *grpc = Some(client);
+        }
+        let client = grpc.as_ref().expect("connected above");
+
+        let round = client
+            .get_round(commitment_txid.to_string())
+            .await
+            .map_err(|e| ArkiveError::ark(format!("Failed to get batch: {}", e)))?;
+        let vtxo_tree = round.vtxo_tree.ok_or_else(|| {
+            ArkiveError::ark(format!(
+                "Server returned no VTXO tree for batch {}",
+                commitment_txid
+            ))
+        })?;
+
+        let levels = vtxo_tree
+            .levels
+            .into_iter()
+            .map(|level| {
+                level
+                    .nodes
+                    .into_iter()
+                    .map(|node| TreeNode {
+                        txid: node.txid,
+                        parent_txid: node.parent_txid,
+                        psbt: node.tx,
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Ok(BatchTree {
+            commitment_txid,
+            levels,
+        })
+    }
+
+    async fn save_batch_tree(&self, tree: &BatchTree, expire_at: i64) -> Result<()> {
+        let params = self.server_params().await?;
+
+        let tree_data = VtxoTreeData {
+            batch_id: tree.commitment_txid.to_string(),
+            commitment_txid: tree.commitment_txid.to_string(),
+            tree_structure: tree.structure()?,
+            presigned_transactions: tree.presigned_transactions()?,
+            expiry: chrono::DateTime::from_timestamp(expire_at, 0).unwrap_or_else(Utc::now),
+            server_pubkey: params.server_pubkey,
+            user_pubkey: self.public_keys.ark.x_only_public_key().0.to_string(),
+        };
+
+        VtxoStore::new(&self.storage)
+            .save_vtxo_tree(&self.wallet_id, &tree_data)
+            .await
+    }
+
     async fn detect_and_store_boarding_outputs(&self) -> Result<()> {
         let client = self
-            .client
-            .as_ref()
+            .current_client()
             .ok_or_else(|| ArkiveError::internal("Ark server not connected"))?;
 
         // Get boarding address from the client (this uses correct parameters)
@@ -748,20 +1124,50 @@ impl ArkService {
             .map_err(|e| ArkiveError::internal(format!("Invalid boarding address: {}", e)))?
             .assume_checked();
 
-        // Use blockchain client to find UTXOs at boarding address
-        let blockchain = Arc::new(EsploraBlockchain::new(&self.config.esplora_url)?);
-        let utxos = blockchain
-            .find_outpoints(&address)
+        // Find outputs to the boarding address along with the block they
+        // confirmed in
+        let script_pubkey = address.script_pubkey();
+        let txs = self
+            .chain
+            .script_history(&script_pubkey, None)
             .await
             .map_err(|e| ArkiveError::ark(format!("Failed to find boarding outputs: {}", e)))?;
+        let spent: HashSet<bitcoin::OutPoint> = txs
+            .iter()
+            .flat_map(|tx| tx.tx.input.iter().map(|input| input.previous_output))
+            .collect();
 
         let boarding_store = BoardingStore::new(&self.storage);
+        let stored: HashSet<bitcoin::OutPoint> = boarding_store
+            .load_boarding_outputs(&self.wallet_id)
+            .await?
+            .into_iter()
+            .filter(|state| state.confirmation_blocktime.is_some())
+            .map(|state| state.outpoint)
+            .collect();
+
+        let utxos = txs.iter().flat_map(|tx| {
+            tx.tx
+                .output
+                .iter()
+                .enumerate()
+                .filter(|(_, output)| output.script_pubkey == script_pubkey)
+                .map(move |(vout, output)| {
+                    (
+                        bitcoin::OutPoint::new(tx.txid, vout as u32),
+                        output.value,
+                        tx.status,
+                    )
+                })
+        });
 
         // Store confirmed, unspent boarding outputs
-        for utxo in utxos {
-            if !utxo.is_spent && utxo.confirmation_blocktime.is_some() {
+        for (outpoint, amount, status) in utxos {
+            let is_spent = spent.contains(&outpoint);
+
+            if !is_spent && status.is_confirmed() {
                 let server_pk = client.server_info.pk.x_only_public_key().0;
-                let (user_pk, _) = self.keypair.x_only_public_key();
+                let (user_pk, _) = self.public_keys.ark.x_only_public_key();
 
                 // CRITICAL: Use the SAME exit delay that the server used to create the boarding address
                 // This should match what's in the boarding descriptor template
@@ -773,16 +1179,18 @@ impl ArkService {
                 );
 
                 let boarding_state = BoardingOutputState {
-                    outpoint: utxo.outpoint,
-                    amount: utxo.amount,
+                    outpoint,
+                    amount,
                     address: boarding_address.clone(),
                     script_pubkey: address.script_pubkey().to_hex_string(),
                     exit_delay, // Use server's unilateral exit delay, not boarding exit delay
                     server_pubkey: server_pk.to_string(),
                     user_pubkey: user_pk.to_string(),
-                    confirmation_blocktime: utxo
-                        .confirmation_blocktime
+                    confirmation_blocktime: status
+                        .block_time
                         .and_then(|t| DateTime::from_timestamp(t as i64, 0)),
+                    block_height: status.block_height,
+                    block_hash: status.block_hash.map(|hash| hash.to_string()),
                     is_spent: false,
                     is_mutinynet: self.config.is_mutinynet,
                 };
@@ -793,8 +1201,8 @@ impl ArkService {
 
                 self.tx_manager
                     .record_transaction_if_new(
-                        &utxo.outpoint.txid.to_string(),
-                        utxo.amount.to_sat() as i64,
+                        &outpoint.txid.to_string(),
+                        amount.to_sat() as i64,
                         TransactionType::Boarding,
                         TransactionSource::Blockchain,
                     )
@@ -802,10 +1210,27 @@ impl ArkService {
 
                 tracing::info!(
                     "Detected and stored boarding output: {} with {} sats (exit_delay: {})",
-                    utxo.outpoint,
-                    utxo.amount.to_sat(),
+                    outpoint,
+                    amount.to_sat(),
                     boarding_state.exit_delay
                 );
+            } else if !is_spent {
+                // Its block was reorganized out, it can't board until it
+                // confirms again
+                if stored.contains(&outpoint) {
+                    boarding_store
+                        .mark_boarding_output_unconfirmed(&self.wallet_id, &outpoint)
+                        .await?;
+                    tracing::warn!(
+                        "Boarding output {} is no longer confirmed, skipping it until it is",
+                        outpoint
+                    );
+                } else {
+                    tracing::debug!(
+                        "Boarding output {} is unconfirmed, waiting for it to confirm",
+                        outpoint
+                    );
+                }
             }
         }
 
@@ -822,7 +1247,7 @@ impl ArkService {
     }
 
     pub async fn get_balance(&self) -> Result<(Amount, Amount)> {
-        if let Some(client) = &self.client {
+        if let Some(client) = self.current_client() {
             // Get balance from server
             match client.offchain_balance().await {
                 Ok(balance) => {
@@ -898,7 +1323,8 @@ impl ArkService {
         let conn = self.storage.get_connection().await;
 
         let mut stmt = conn.prepare(
-            "SELECT txid, amount, timestamp, tx_type, status, fee, source, ark_round_id
+            "SELECT txid, amount, timestamp, tx_type, status, fee, source, ark_round_id,
+                    block_height, block_hash
              FROM transactions 
              WHERE wallet_id = ?1 
              ORDER BY timestamp DESC",
@@ -946,6 +1372,8 @@ impl ArkService {
                         )
                     })?,
                     ark_round_id: row.get::<_, Option<String>>(7)?,
+                    block_height: row.get(8)?,
+                    block_hash: row.get(9)?,
                 })
             })?
             .collect::<std::result::Result<Vec<_>, _>>()?;
@@ -961,15 +1389,17 @@ impl ArkService {
     }
 
     pub async fn get_address(&self) -> Result<String> {
-        if let Some(client) = &self.client {
+        if let Some(client) = self.current_client() {
             let (address, _) = client
                 .get_offchain_address()
                 .map_err(|e| ArkiveError::ark(format!("Failed to get address: {}", e)))?;
             Ok(address.to_string())
+        } else if let Some(address) = self.cached_address.read().clone() {
+            Ok(address)
         } else {
             // Generate address offline
             let secp = bitcoin::secp256k1::Secp256k1::new();
-            let (owner_pk, _) = self.keypair.x_only_public_key();
+            let (owner_pk, _) = self.public_keys.ark.x_only_public_key();
 
             // Use placeholder server key for offline mode
             let server_pk = bitcoin::XOnlyPublicKey::from_str(
@@ -991,18 +1421,20 @@ impl ArkService {
     }
 
     pub async fn get_boarding_address(&self) -> Result<String> {
-        if let Some(client) = &self.client {
+        if let Some(client) = self.current_client() {
             let address = client
                 .get_boarding_address()
                 .map_err(|e| ArkiveError::ark(format!("Failed to get boarding address: {}", e)))?;
             Ok(address.to_string())
+        } else if let Some(address) = self.cached_boarding_address.read().clone() {
+            Ok(address)
         } else {
             Err(ArkiveError::internal("Ark server not connected"))
         }
     }
 
     pub async fn sync(&self) -> Result<()> {
-        if self.client.is_some() {
+        if self.current_client().is_some() {
             self.sync_with_server().await
         } else {
             // Try to reconnect
@@ -1024,6 +1456,410 @@ impl ArkService {
             .get_expiring_vtxos(&self.wallet_id, hours_threshold)
             .await
     }
+
+    /// Server key and exit delays as recorded on the last connection, which
+    /// is all exiting needs from the server
+    async fn server_params(&self) -> Result<ArkServerParams> {
+        VtxoStore::new(&self.storage)
+            .load_server_params(&self.wallet_id)
+            .await?
+            .ok_or_else(|| {
+                ArkiveError::ark("Ark server parameters unknown, connect to the server once first")
+            })
+    }
+
+    /// Start exiting `outpoints` on chain without the server's cooperation,
+    /// sweeping them to `destination` once their exit delay has passed.
+    /// VTXOs already being exited are left as they are.
+    pub async fn start_unilateral_exit(
+        &self,
+        outpoints: &[bitcoin::OutPoint],
+        destination: &str,
+    ) -> Result<()> {
+        self.session.ensure_unlocked()?;
+
+        let params = self.server_params().await?;
+        let vtxo_store = VtxoStore::new(&self.storage);
+        let exit_store = ExitStore::new(&self.storage);
+        let vtxos = self.get_all_vtxos().await?;
+
+        // Check every VTXO before starting any exit
+        let mut to_exit = Vec::new();
+        for outpoint in outpoints {
+            if exit_store
+                .load_exit(&self.wallet_id, outpoint)
+                .await?
+                .is_some()
+            {
+                tracing::info!("VTXO {} is already being exited", outpoint);
+                continue;
+            }
+
+            let vtxo = vtxos
+                .iter()
+                .find(|v| v.outpoint == outpoint.to_string())
+                .ok_or_else(|| {
+                    ArkiveError::ark(format!("{} is not a VTXO of this wallet", outpoint))
+                })?;
+            if matches!(vtxo.status, VtxoStatus::Spent) {
+                return Err(ArkiveError::ark(format!(
+                    "VTXO {} is already spent",
+                    outpoint
+                )));
+            }
+            exit::exit_branch(vtxo, outpoint)?;
+
+            to_exit.push((*outpoint, vtxo.clone()));
+        }
+
+        for (outpoint, mut vtxo) in to_exit {
+            let exit = UnilateralExit {
+                outpoint,
+                amount: vtxo.amount,
+                destination: destination.to_string(),
+                server_pubkey: params.server_pubkey.clone(),
+                exit_delay: params.unilateral_exit_delay,
+                stage: ExitStage::Broadcasting,
+                next_tx: 0,
+                confirmed_height: None,
+                confirmed_time: None,
+                claim_txid: None,
+                last_error: None,
+                started_at: Utc::now(),
+            };
+            exit_store.save_exit(&self.wallet_id, &exit).await?;
+
+            vtxo.status = VtxoStatus::Exiting;
+            vtxo_store.save_vtxo_state(&self.wallet_id, &vtxo).await?;
+
+            tracing::info!(
+                "Started unilateral exit of VTXO {} ({} sats) to {}",
+                outpoint,
+                vtxo.amount.to_sat(),
+                destination
+            );
+        }
+
+        Ok(())
+    }
+
+    pub async fn list_exits(&self) -> Result<Vec<UnilateralExit>> {
+        ExitStore::new(&self.storage)
+            .load_exits(&self.wallet_id)
+            .await
+    }
+
+    /// Check that every unspent VTXO could be exited from local data
+    /// alone: its presigned branch is complete and validly signed, and ends
+    /// in an output our key can claim after the server's exit delay
+    pub async fn verify_exit_readiness(&self) -> Result<Vec<ExitReadiness>> {
+        let params = self.server_params().await?;
+        let server_pk = bitcoin::XOnlyPublicKey::from_str(&params.server_pubkey)
+            .map_err(|e| ArkiveError::internal(format!("Invalid server key: {}", e)))?;
+
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let vtxo_script = ark_core::Vtxo::new_default(
+            &secp,
+            server_pk,
+            self.public_keys.ark.x_only_public_key().0,
+            bitcoin::Sequence::from_consensus(params.unilateral_exit_delay),
+            self.config.network,
+        )
+        .map_err(|e| ArkiveError::internal(format!("Failed to create VTXO: {}", e)))?
+        .script_pubkey();
+
+        // Commitment transactions by txid, or why they are unavailable
+        let mut commitments: HashMap<
+            bitcoin::Txid,
+            std::result::Result<bitcoin::Transaction, String>,
+        > = HashMap::new();
+
+        let mut report = Vec::new();
+        for vtxo in self.get_all_vtxos().await? {
+            if matches!(vtxo.status, VtxoStatus::Spent | VtxoStatus::Expired) {
+                continue;
+            }
+
+            let problems = match self
+                .exit_problems(&vtxo, &vtxo_script, &mut commitments)
+                .await
+            {
+                Ok(problems) => problems,
+                Err(e) => vec![e.to_string()],
+            };
+            if !problems.is_empty() {
+                tracing::warn!(
+                    "VTXO {} is not exit-capable: {}",
+                    vtxo.outpoint,
+                    problems.join("; ")
+                );
+            }
+
+            report.push(ExitReadiness {
+                outpoint: vtxo.outpoint,
+                amount: vtxo.amount,
+                status: vtxo.status,
+                problems,
+            });
+        }
+
+        Ok(report)
+    }
+
+    async fn exit_problems(
+        &self,
+        vtxo: &VtxoState,
+        vtxo_script: &bitcoin::Script,
+        commitments: &mut HashMap<bitcoin::Txid, std::result::Result<bitcoin::Transaction, String>>,
+    ) -> Result<Vec<String>> {
+        let outpoint = bitcoin::OutPoint::from_str(&vtxo.outpoint)
+            .map_err(|e| ArkiveError::internal(format!("Invalid outpoint: {}", e)))?;
+        let branch = exit::exit_branch(vtxo, &outpoint)?;
+        let commitment_txid = bitcoin::Txid::from_str(&vtxo.batch_id).map_err(|_| {
+            ArkiveError::ark(format!(
+                "Unknown commitment transaction of batch {}",
+                vtxo.batch_id
+            ))
+        })?;
+
+        if !commitments.contains_key(&commitment_txid) {
+            let commitment = match self.chain.get_tx(&commitment_txid).await {
+                Ok(Some(tx)) => Ok(tx),
+                Ok(None) => Err(format!(
+                    "Commitment transaction {} is not on chain",
+                    commitment_txid
+                )),
+                Err(e) => Err(format!(
+                    "Commitment transaction {} could not be fetched: {}",
+                    commitment_txid, e
+                )),
+            };
+            commitments.insert(commitment_txid, commitment);
+        }
+        let commitment = &commitments[&commitment_txid];
+
+        let mut problems = exit::verify_branch(
+            &branch,
+            commitment_txid,
+            commitment.as_ref().ok(),
+            &outpoint,
+            vtxo.amount,
+            vtxo_script,
+        );
+        if let Err(problem) = commitment {
+            problems.push(problem.clone());
+        }
+
+        Ok(problems)
+    }
+
+    /// Take every unfinished exit as far as the chain allows right now.
+    /// Failures are recorded on the exit and retried on the next call.
+    pub async fn advance_exits(&self, wallet: &BitcoinService) -> Result<Vec<UnilateralExit>> {
+        let exit_store = ExitStore::new(&self.storage);
+        let mut exits = exit_store.load_exits(&self.wallet_id).await?;
+
+        for exit in exits
+            .iter_mut()
+            .filter(|exit| exit.stage != ExitStage::Claimed)
+        {
+            let result = self.advance_exit(exit, wallet).await;
+            exit.last_error = result.err().map(|e| {
+                tracing::warn!("Exit of VTXO {} stalled: {}", exit.outpoint, e);
+                e.to_string()
+            });
+            exit_store.save_exit(&self.wallet_id, exit).await?;
+        }
+
+        Ok(exits)
+    }
+
+    /// Take one exit as far as it can go now. Tree transactions with an
+    /// anchor output pay no fee, so each is broadcast in a package with a
+    /// child paying for both from the on-chain `wallet`.
+    async fn advance_exit(&self, exit: &mut UnilateralExit, wallet: &BitcoinService) -> Result<()> {
+        let exit_store = ExitStore::new(&self.storage);
+
+        loop {
+            match exit.stage {
+                ExitStage::Broadcasting => {
+                    let vtxo = self
+                        .get_all_vtxos()
+                        .await?
+                        .into_iter()
+                        .find(|v| v.outpoint == exit.outpoint.to_string())
+                        .ok_or_else(|| ArkiveError::internal("VTXO not found in storage"))?;
+                    let branch = exit::exit_branch(&vtxo, &exit.outpoint)?;
+
+                    while exit.next_tx < branch.len() {
+                        let tx = &branch[exit.next_tx];
+                        let txid = tx.compute_txid();
+
+                        // Exits of other VTXOs in the same tree may have
+                        // broadcast the upper part of the branch already
+                        if self.chain.get_tx(&txid).await?.is_none() {
+                            match exit::anchor(tx) {
+                                Some(anchor) => {
+                                    let fee_rate = self.exit_fee_rate().await?;
+                                    wallet.broadcast_with_child(tx, anchor, fee_rate).await?;
+                                }
+                                None => self.chain.broadcast(tx).await?,
+                            }
+                            tracing::info!(
+                                "Broadcast exit transaction {} ({} of {}) for VTXO {}",
+                                txid,
+                                exit.next_tx + 1,
+                                branch.len(),
+                                exit.outpoint
+                            );
+                        }
+
+                        exit.next_tx += 1;
+                        exit_store.save_exit(&self.wallet_id, exit).await?;
+                    }
+
+                    exit.stage = ExitStage::AwaitingConfirmation;
+                }
+                ExitStage::AwaitingConfirmation | ExitStage::Timelocked => {
+                    let status = self.chain.get_tx_status(&exit.outpoint.txid).await?;
+                    let (Some(height), Some(time)) = (status.block_height, status.block_time)
+                    else {
+                        exit.confirmed_height = None;
+                        exit.confirmed_time = None;
+
+                        // Evicted from the mempool, or reorganized out and
+                        // not returned to it
+                        if self.chain.get_tx(&exit.outpoint.txid).await?.is_none() {
+                            tracing::warn!(
+                                "Exit branch of VTXO {} is gone from the chain, broadcasting it again",
+                                exit.outpoint
+                            );
+                            exit.stage = ExitStage::Broadcasting;
+                            exit.next_tx = 0;
+                            continue;
+                        }
+
+                        exit.stage = ExitStage::AwaitingConfirmation;
+                        return Ok(());
+                    };
+
+                    exit.stage = ExitStage::Timelocked;
+                    exit.confirmed_height = Some(height);
+                    exit.confirmed_time = Some(time);
+
+                    let tip_height = self.chain.tip_height().await?;
+                    let claimable = exit::is_claimable(
+                        bitcoin::Sequence::from_consensus(exit.exit_delay),
+                        height,
+                        time,
+                        tip_height,
+                        Utc::now().timestamp() as u64,
+                    );
+                    if !claimable {
+                        return Ok(());
+                    }
+
+                    exit.claim_txid = Some(self.claim_exit(exit).await?);
+                    exit.stage = ExitStage::Claimed;
+                }
+                ExitStage::Claimed => return Ok(()),
+            }
+
+            exit_store.save_exit(&self.wallet_id, exit).await?;
+        }
+    }
+
+    /// Fee rate for exit transactions at the wallet's default priority
+    async fn exit_fee_rate(&self) -> Result<bitcoin::FeeRate> {
+        let estimates = self.chain.fee_estimates().await?;
+        Ok(crate::bitcoin::fees::fee_rate_for(
+            &estimates,
+            self.config.fee_policy.default_priority,
+            &self.config.fee_policy,
+        ))
+    }
+
+    /// Sweep a VTXO whose exit delay has passed to the exit's destination
+    async fn claim_exit(&self, exit: &UnilateralExit) -> Result<bitcoin::Txid> {
+        // Swept before an interruption
+        if let Some(txid) = self.chain.get_output_spend(&exit.outpoint).await? {
+            return Ok(txid);
+        }
+
+        self.session.ensure_unlocked()?;
+        let server_pk = bitcoin::XOnlyPublicKey::from_str(&exit.server_pubkey)
+            .map_err(|e| ArkiveError::internal(format!("Invalid server key: {}", e)))?;
+        let exit_delay = bitcoin::Sequence::from_consensus(exit.exit_delay);
+
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let vtxo = ark_core::Vtxo::new_default(
+            &secp,
+            server_pk,
+            self.public_keys.ark.x_only_public_key().0,
+            exit_delay,
+            self.config.network,
+        )
+        .map_err(|e| ArkiveError::internal(format!("Failed to create VTXO: {}", e)))?;
+
+        let destination = bitcoin::Address::from_str(&exit.destination)
+            .map_err(|e| ArkiveError::InvalidAddress(format!("{}: {}", exit.destination, e)))?
+            .require_network(self.config.network)
+            .map_err(|e| ArkiveError::InvalidAddress(format!("{}: {}", exit.destination, e)))?;
+
+        let fee_rate = self.exit_fee_rate().await?;
+        let tx = self.session.with_keys(|keys| {
+            exit::build_claim_tx(
+                &vtxo,
+                exit.outpoint,
+                exit.amount,
+                exit_delay,
+                destination.script_pubkey(),
+                fee_rate,
+                &keys.ark,
+            )
+        })??;
+        self.chain.broadcast(&tx).await?;
+
+        let txid = tx.compute_txid();
+        let claimed = tx.output[0].value;
+        self.tx_manager
+            .record_transaction_if_new(
+                &txid.to_string(),
+                claimed.to_sat() as i64,
+                TransactionType::Exit,
+                TransactionSource::Blockchain,
+            )
+            .await?;
+        self.tx_manager
+            .update_transaction_details(
+                &txid.to_string(),
+                exit.amount - claimed,
+                &bitcoin::consensus::encode::serialize_hex(&tx),
+            )
+            .await?;
+
+        let vtxo_store = VtxoStore::new(&self.storage);
+        if let Some(mut vtxo_state) = self
+            .get_all_vtxos()
+            .await?
+            .into_iter()
+            .find(|v| v.outpoint == exit.outpoint.to_string())
+        {
+            vtxo_state.status = VtxoStatus::Spent;
+            vtxo_store
+                .save_vtxo_state(&self.wallet_id, &vtxo_state)
+                .await?;
+        }
+
+        tracing::info!(
+            "Claimed exited VTXO {}: {} sats to {} in {}",
+            exit.outpoint,
+            claimed.to_sat(),
+            exit.destination,
+            txid
+        );
+        Ok(txid)
+    }
 }
 
 pub struct TransactionManager {
@@ -1112,6 +1948,213 @@ impl TransactionManager {
         Ok(rows_affected > 0)
     }
 
+    /// Attach the fee and serialized transaction of a transaction this wallet built
+    pub async fn update_transaction_details(
+        &self,
+        txid: &str,
+        fee: Amount,
+        raw_tx_hex: &str,
+    ) -> Result<()> {
+        let conn = self.storage.get_connection().await;
+
+        conn.execute(
+            "UPDATE transactions
+             SET fee = ?1, raw_data = ?2, last_updated = ?3
+             WHERE wallet_id = ?4 AND txid = ?5",
+            params![
+                fee.to_sat() as i64,
+                raw_tx_hex,
+                Utc::now().timestamp(),
+                self.wallet_id,
+                txid,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Correct the amount and fee of a recorded transaction once what it
+    /// cost is known
+    pub async fn update_transaction_cost(
+        &self,
+        txid: &str,
+        amount: i64,
+        fee: Amount,
+    ) -> Result<()> {
+        let conn = self.storage.get_connection().await;
+
+        conn.execute(
+            "UPDATE transactions
+             SET amount = ?1, fee = ?2, last_updated = ?3
+             WHERE wallet_id = ?4 AND txid = ?5",
+            params![
+                amount,
+                fee.to_sat() as i64,
+                Utc::now().timestamp(),
+                self.wallet_id,
+                txid,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Record a transaction seen on chain, or refresh the amount, type,
+    /// fee and confirmation block of a known one. Statuses set elsewhere
+    /// (spent into a round, replaced, failed) are kept.
+    pub async fn upsert_chain_transaction(
+        &self,
+        txid: &str,
+        amount: i64,
+        tx_type: TransactionType,
+        fee: Option<Amount>,
+        chain_status: &TxStatus,
+    ) -> Result<()> {
+        let conn = self.storage.get_connection().await;
+
+        let status = if chain_status.is_confirmed() {
+            TransactionStatus::Confirmed
+        } else {
+            TransactionStatus::Pending
+        };
+        let timestamp = chain_status
+            .block_time
+            .map_or_else(|| Utc::now().timestamp(), |t| t as i64);
+
+        conn.execute(
+            "INSERT INTO transactions
+             (wallet_id, txid, amount, timestamp, tx_type, status, source, last_updated, fee,
+              block_height, block_hash)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?12, ?13)
+             ON CONFLICT(wallet_id, txid) DO UPDATE SET
+                amount = excluded.amount,
+                tx_type = CASE WHEN transactions.tx_type = ?14
+                    THEN transactions.tx_type ELSE excluded.tx_type END,
+                fee = COALESCE(excluded.fee, transactions.fee),
+                status = CASE WHEN transactions.status IN (?10, ?11)
+                    THEN excluded.status ELSE transactions.status END,
+                block_height = excluded.block_height,
+                block_hash = excluded.block_hash,
+                last_updated = excluded.last_updated",
+            params![
+                self.wallet_id,
+                txid,
+                amount,
+                timestamp,
+                serde_json::to_string(&tx_type)?,
+                serde_json::to_string(&status)?,
+                serde_json::to_string(&TransactionSource::Blockchain)?,
+                Utc::now().timestamp(),
+                fee.map(|f| f.to_sat() as i64),
+                serde_json::to_string(&TransactionStatus::Pending)?,
+                serde_json::to_string(&TransactionStatus::Confirmed)?,
+                chain_status.block_height,
+                chain_status.block_hash.map(|hash| hash.to_string()),
+                // Exit sweeps look like plain receives on chain
+                serde_json::to_string(&TransactionType::Exit)?,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Update the confirmation block of a recorded transaction as seen by
+    /// an on-chain sync. Transactions that aren't recorded, or whose status
+    /// was set elsewhere, are left alone.
+    pub async fn refresh_confirmation(&self, txid: &str, chain_status: &TxStatus) -> Result<bool> {
+        let conn = self.storage.get_connection().await;
+
+        let status = if chain_status.is_confirmed() {
+            TransactionStatus::Confirmed
+        } else {
+            TransactionStatus::Pending
+        };
+
+        let rows_affected = conn.execute(
+            "UPDATE transactions
+             SET status = ?1, block_height = ?2, block_hash = ?3, last_updated = ?4
+             WHERE wallet_id = ?5 AND txid = ?6 AND status IN (?7, ?8)",
+            params![
+                serde_json::to_string(&status)?,
+                chain_status.block_height,
+                chain_status.block_hash.map(|hash| hash.to_string()),
+                Utc::now().timestamp(),
+                self.wallet_id,
+                txid,
+                serde_json::to_string(&TransactionStatus::Pending)?,
+                serde_json::to_string(&TransactionStatus::Confirmed)?,
+            ],
+        )?;
+
+        Ok(rows_affected > 0)
+    }
+
+    /// Move transactions confirmed at `fork_height` or above back to pending
+    /// after those blocks were reorganized out. Returns how many were
+    /// affected.
+    pub async fn rollback(&self, fork_height: u32) -> Result<usize> {
+        let conn = self.storage.get_connection().await;
+
+        let rows_affected = conn.execute(
+            "UPDATE transactions
+             SET block_height = NULL, block_hash = NULL, last_updated = ?1,
+                 status = CASE WHEN status = ?2 THEN ?3 ELSE status END
+             WHERE wallet_id = ?4 AND block_height >= ?5",
+            params![
+                Utc::now().timestamp(),
+                serde_json::to_string(&TransactionStatus::Confirmed)?,
+                serde_json::to_string(&TransactionStatus::Pending)?,
+                self.wallet_id,
+                fork_height,
+            ],
+        )?;
+
+        if rows_affected > 0 {
+            tracing::info!(
+                "Rolled back {} transactions confirmed from block {}",
+                rows_affected,
+                fork_height
+            );
+        }
+
+        Ok(rows_affected)
+    }
+
+    /// Height and hash of the blocks holding confirmed transactions at or
+    /// above `min_height`
+    pub async fn load_confirmation_blocks(&self, min_height: u32) -> Result<Vec<(u32, String)>> {
+        let conn = self.storage.get_connection().await;
+
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT block_height, block_hash FROM transactions
+             WHERE wallet_id = ?1 AND block_height >= ?2 AND block_hash IS NOT NULL",
+        )?;
+        let blocks = stmt
+            .query_map(params![self.wallet_id, min_height], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Ok(blocks)
+    }
+
+    /// Serialized transaction recorded for a transaction this wallet built
+    pub async fn get_raw_transaction(&self, txid: &str) -> Result<Option<String>> {
+        let conn = self.storage.get_connection().await;
+
+        let result = conn.query_row(
+            "SELECT raw_data FROM transactions WHERE wallet_id = ?1 AND txid = ?2",
+            params![self.wallet_id, txid],
+            |row| row.get::<_, Option<String>>(0),
+        );
+
+        match result {
+            Ok(raw_data) => Ok(raw_data),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(ArkiveError::Storage(e)),
+        }
+    }
+
     // Mark boarding outputs as spent in round
     pub async fn mark_boarding_outputs_spent(
         &self,
@@ -1137,7 +2180,8 @@ impl TransactionManager {
         let conn = self.storage.get_connection().await;
 
         let mut stmt = conn.prepare(
-            "SELECT txid, amount, timestamp, tx_type, status, fee, source, ark_round_id
+            "SELECT txid, amount, timestamp, tx_type, status, fee, source, ark_round_id,
+                    block_height, block_hash
              FROM transactions 
              WHERE wallet_id = ?1 AND tx_type = ?2
              ORDER BY timestamp DESC",
@@ -1187,6 +2231,8 @@ impl TransactionManager {
                             )
                         })?,
                         ark_round_id: row.get::<_, Option<String>>(7)?,
+                        block_height: row.get(8)?,
+                        block_hash: row.get(9)?,
                     })
                 },
             )?
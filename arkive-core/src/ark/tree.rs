@@ -0,0 +1,218 @@
+//! Presigned VTXO trees of batches and the exit branches taken from them
+//!
+//! A batch's tree spends an output of its commitment transaction and fans
+//! out level by level down to the leaves creating the VTXOs. Exiting a VTXO
+//! means broadcasting the path from the root down to its leaf.
+
+use crate::error::{ArkiveError, Result};
+
+use bitcoin::{Psbt, Transaction, Txid, Witness};
+use serde::{Deserialize, Serialize};
+
+/// A transaction of a VTXO tree, as signed by the batch's cosigners
+#[derive(Debug, Clone)]
+pub struct TreeNode {
+    pub txid: Txid,
+    pub parent_txid: Txid,
+    pub psbt: Psbt,
+}
+
+/// The VTXO tree of one batch, root level first
+#[derive(Debug, Clone)]
+pub struct BatchTree {
+    pub commitment_txid: Txid,
+    pub levels: Vec<Vec<TreeNode>>,
+}
+
+/// Position of a node in the tree, kept next to the presigned transactions
+#[derive(Debug, Serialize, Deserialize)]
+struct NodeRef {
+    txid: Txid,
+    parent_txid: Txid,
+}
+
+impl BatchTree {
+    /// Path from the root down to the leaf `leaf_txid`: the index of each
+    /// node within its level, and the finalized transactions themselves
+    pub fn branch(&self, leaf_txid: Txid) -> Result<(Vec<u32>, Vec<Transaction>)> {
+        let not_found = || {
+            ArkiveError::ark(format!(
+                "Transaction {} is not in the VTXO tree of batch {}",
+                leaf_txid, self.commitment_txid
+            ))
+        };
+
+        let leaf_level = self
+            .levels
+            .iter()
+            .rposition(|level| level.iter().any(|node| node.txid == leaf_txid))
+            .ok_or_else(not_found)?;
+
+        let mut path = Vec::with_capacity(leaf_level + 1);
+        let mut branch = Vec::with_capacity(leaf_level + 1);
+        let mut txid = leaf_txid;
+        for level in self.levels[..=leaf_level].iter().rev() {
+            let index = level
+                .iter()
+                .position(|node| node.txid == txid)
+                .ok_or_else(not_found)?;
+            let node = &level[index];
+
+            path.push(index as u32);
+            branch.push(finalize(&node.psbt)?);
+            txid = node.parent_txid;
+        }
+
+        if txid != self.commitment_txid {
+            return Err(ArkiveError::ark(format!(
+                "VTXO tree of batch {} is rooted in {} instead",
+                self.commitment_txid, txid
+            )));
+        }
+
+        path.reverse();
+        branch.reverse();
+        Ok((path, branch))
+    }
+
+    /// Every finalized transaction of the tree, consensus-encoded
+    pub fn presigned_transactions(&self) -> Result<Vec<Vec<u8>>> {
+        self.levels
+            .iter()
+            .flatten()
+            .map(|node| Ok(bitcoin::consensus::serialize(&finalize(&node.psbt)?)))
+            .collect()
+    }
+
+    /// Shape of the tree as JSON, to go with [`Self::presigned_transactions`]
+    pub fn structure(&self) -> Result<Vec<u8>> {
+        let levels: Vec<Vec<NodeRef>> = self
+            .levels
+            .iter()
+            .map(|level| {
+                level
+                    .iter()
+                    .map(|node| NodeRef {
+                        txid: node.txid,
+                        parent_txid: node.parent_txid,
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Ok(serde_json::to_vec(&levels)?)
+    }
+}
+
+/// Turn a tree transaction into its broadcastable form. Tree transactions
+/// spend their parent's output through the cosigners' aggregate key.
+fn finalize(psbt: &Psbt) -> Result<Transaction> {
+    let mut tx = psbt.unsigned_tx.clone();
+
+    for (i, input) in psbt.inputs.iter().enumerate() {
+        let witness = match (&input.final_script_witness, input.tap_key_sig) {
+            (Some(witness), _) => witness.clone(),
+            (None, Some(signature)) => Witness::p2tr_key_spend(&signature),
+            (None, None) => {
+                return Err(ArkiveError::ark(format!(
+                    "Tree transaction {} is missing the signature of input {}",
+                    tx.compute_txid(),
+                    i
+                )))
+            }
+        };
+        tx.input[i].witness = witness;
+    }
+
+    Ok(tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::key::Keypair;
+    use bitcoin::secp256k1::{Message, Secp256k1};
+    use bitcoin::transaction::Version;
+    use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, TxIn, TxOut};
+
+    fn node(parent_txid: Txid, vout: u32, outputs: usize) -> TreeNode {
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(parent_txid, vout),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![
+                TxOut {
+                    value: Amount::from_sat(1_000),
+                    script_pubkey: ScriptBuf::new(),
+                };
+                outputs
+            ],
+        };
+
+        let secp = Secp256k1::new();
+        let keypair = Keypair::from_seckey_slice(&secp, &[1; 32]).unwrap();
+        let signature = secp.sign_schnorr_no_aux_rand(&Message::from_digest([2; 32]), &keypair);
+
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].tap_key_sig = Some(bitcoin::taproot::Signature {
+            signature,
+            sighash_type: bitcoin::TapSighashType::Default,
+        });
+
+        TreeNode {
+            txid: psbt.unsigned_tx.compute_txid(),
+            parent_txid,
+            psbt,
+        }
+    }
+
+    #[test]
+    fn test_branch() {
+        let commitment_txid = Txid::from_byte_array([9; 32]);
+        let root = node(commitment_txid, 0, 2);
+        let left = node(root.txid, 0, 1);
+        let right = node(root.txid, 1, 1);
+
+        let tree = BatchTree {
+            commitment_txid,
+            levels: vec![vec![root.clone()], vec![left, right.clone()]],
+        };
+
+        let (path, branch) = tree.branch(right.txid).unwrap();
+        assert_eq!(path, vec![0, 1]);
+        assert_eq!(branch.len(), 2);
+        assert_eq!(branch[0].compute_txid(), root.txid);
+        assert_eq!(branch[1].compute_txid(), right.txid);
+        assert_eq!(branch[1].input[0].witness.len(), 1);
+
+        assert!(tree.branch(Txid::from_byte_array([3; 32])).is_err());
+        assert_eq!(tree.presigned_transactions().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_branch_rejects_unsigned_and_foreign_trees() {
+        let commitment_txid = Txid::from_byte_array([9; 32]);
+        let mut root = node(commitment_txid, 0, 1);
+        root.psbt.inputs[0].tap_key_sig = None;
+        let tree = BatchTree {
+            commitment_txid,
+            levels: vec![vec![root.clone()]],
+        };
+        assert!(tree.branch(root.txid).is_err());
+
+        // Rooted in some other commitment transaction
+        let root = node(Txid::from_byte_array([8; 32]), 0, 1);
+        let tree = BatchTree {
+            commitment_txid,
+            levels: vec![vec![root.clone()]],
+        };
+        assert!(tree.branch(root.txid).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use crate::wallet::config::{FeePolicy, FeePriority};
+
+use bitcoin::{Amount, FeeRate};
+use std::collections::HashMap;
+
+/// Fee for a concrete draft transaction
+#[derive(Debug, Clone)]
+pub struct FeeQuote {
+    pub priority: FeePriority,
+    pub fee_rate: FeeRate,
+    pub vsize: u64,
+    pub fee: Amount,
+}
+
+impl FeeQuote {
+    /// Fee rate in sat/vB, rounded up
+    pub fn sat_per_vb(&self) -> u64 {
+        self.fee_rate.to_sat_per_vb_ceil()
+    }
+}
+
+/// Pick a fee rate for `priority` from the chain backend's fee estimates
+/// (sat/vB keyed by confirmation target), capped at the policy's
+/// `max_fee_rate`.
+pub fn fee_rate_for(
+    estimates: &HashMap<u16, f64>,
+    priority: FeePriority,
+    policy: &FeePolicy,
+) -> FeeRate {
+    let target = priority.confirmation_target();
+
+    // Use the estimate of the slowest target that still meets ours, falling
+    // back to the fastest one available when every target is slower
+    let sat_per_vb = estimates
+        .iter()
+        .filter(|(t, _)| **t <= target)
+        .max_by_key(|(t, _)| **t)
+        .or_else(|| estimates.iter().min_by_key(|(t, _)| **t))
+        .map(|(_, rate)| *rate);
+
+    let fee_rate = match sat_per_vb {
+        Some(rate) => FeeRate::from_sat_per_kwu((rate * 250.0).ceil() as u64),
+        None => FeeRate::BROADCAST_MIN,
+    };
+
+    let max_fee_rate = FeeRate::from_sat_per_vb(policy.max_fee_rate).unwrap_or(FeeRate::MAX);
+    if fee_rate > max_fee_rate {
+        tracing::warn!(
+            "Estimated fee rate of {} sat/vB exceeds the maximum of {} sat/vB, capping it",
+            fee_rate.to_sat_per_vb_ceil(),
+            policy.max_fee_rate
+        );
+    }
+
+    fee_rate.min(max_fee_rate).max(FeeRate::BROADCAST_MIN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn estimates() -> HashMap<u16, f64> {
+        HashMap::from([(2, 40.5), (3, 30.0), (6, 12.0), (144, 2.0), (1008, 1.0)])
+    }
+
+    fn policy(max_fee_rate: u64) -> FeePolicy {
+        FeePolicy {
+            default_priority: FeePriority::Normal,
+            max_fee_rate,
+        }
+    }
+
+    #[test]
+    fn test_fee_rate_for_priority() {
+        let estimates = estimates();
+        let policy = policy(100);
+
+        let rate = |priority| fee_rate_for(&estimates, priority, &policy).to_sat_per_vb_ceil();
+        assert_eq!(rate(FeePriority::Slow), 2);
+        assert_eq!(rate(FeePriority::Normal), 12);
+        assert_eq!(rate(FeePriority::Fast), 30);
+        // No estimate for the next block, use the fastest there is
+        assert_eq!(rate(FeePriority::Fastest), 41);
+    }
+
+    #[test]
+    fn test_fee_rate_capped_by_policy() {
+        let rate = fee_rate_for(&estimates(), FeePriority::Fastest, &policy(20));
+        assert_eq!(rate, FeeRate::from_sat_per_vb(20).unwrap());
+
+        let rate = fee_rate_for(&HashMap::new(), FeePriority::Normal, &policy(20));
+        assert_eq!(rate, FeeRate::BROADCAST_MIN);
+    }
+}
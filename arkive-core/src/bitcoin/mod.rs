@@ -1,164 +1,1566 @@
+pub mod fees;
+pub mod tx_builder;
+
+pub use fees::FeeQuote;
+pub use tx_builder::{TxPlan, WalletUtxo};
+
 use crate::ark::TransactionManager;
+use crate::chain::{self, ChainBackend, ChainTx};
 use crate::error::{ArkiveError, Result};
-use crate::storage::Storage;
-use crate::types::{Transaction, TransactionSource, TransactionStatus, TransactionType};
-use crate::wallet::WalletConfig;
+use crate::storage::{
+    AddressRecord, AddressStore, BoardingStore, ChainSyncState, Storage, UtxoSpend, UtxoStore,
+};
+use crate::types::{
+    Keychain, Transaction, TransactionSource, TransactionStatus, TransactionType, UtxoInfo,
+};
+use crate::wallet::{FeePriority, KeySession, WalletConfig, WalletPublicKeys};
 
-use bitcoin::key::Keypair;
-use bitcoin::Amount;
-use esplora_client::AsyncClient;
+use bitcoin::bip32::{ChildNumber, DerivationPath, Xpub};
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::{Amount, FeeRate, OutPoint, Script, ScriptBuf, TxOut, Txid, Weight};
+use chrono::Utc;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::str::FromStr;
 use std::sync::Arc;
 
+/// Blocks below the tip checked for reorganizations on each sync
+pub(crate) const REORG_DEPTH: u32 = 100;
+
 pub struct BitcoinService {
-    keypair: Keypair,
+    session: KeySession,
+    account_xpub: Xpub,
+    account_path: DerivationPath,
     config: WalletConfig,
-    client: AsyncClient,
+    chain: Arc<dyn ChainBackend>,
+    storage: Arc<Storage>,
+    wallet_id: String,
     tx_manager: TransactionManager,
 }
 
+/// Result of a gap-limit scan over both keychains
+struct WalletScan {
+    /// Used wallet scripts with their keychain and index
+    scripts: HashMap<ScriptBuf, (Keychain, u32)>,
+    txs: Vec<ChainTx>,
+    /// Last address index scanned on the external and internal keychains
+    last_index: [u32; 2],
+}
+
+impl WalletScan {
+    /// Every output of the wallet's history paying to a wallet script
+    fn outputs(&self) -> impl Iterator<Item = WalletUtxo> + '_ {
+        self.txs.iter().flat_map(move |tx| {
+            tx.tx
+                .output
+                .iter()
+                .enumerate()
+                .filter_map(move |(vout, output)| {
+                    let &(keychain, index) = self.scripts.get(&output.script_pubkey)?;
+                    Some(WalletUtxo {
+                        outpoint: OutPoint::new(tx.txid, vout as u32),
+                        txout: output.clone(),
+                        keychain,
+                        index,
+                        confirmation_height: tx.status.block_height,
+                    })
+                })
+        })
+    }
+
+    /// Wallet outputs not spent by any transaction in the wallet's history.
+    /// Every spend of a wallet output shows up in that history.
+    fn unspent(&self) -> Vec<WalletUtxo> {
+        let spent: HashSet<OutPoint> = self
+            .txs
+            .iter()
+            .flat_map(|tx| tx.tx.input.iter())
+            .map(|input| input.previous_output)
+            .collect();
+
+        self.outputs()
+            .filter(|utxo| !spent.contains(&utxo.outpoint))
+            .collect()
+    }
+}
+
+/// How an on-chain transaction moves value in and out of the wallet, in sats
+#[derive(Debug, Default)]
+struct TxFlow {
+    /// Spent from wallet outputs
+    sent: u64,
+    /// Paid to wallet addresses
+    received: u64,
+    /// Paid to the Ark boarding address
+    boarding: u64,
+    /// Paid to anyone else
+    external: u64,
+}
+
+impl TxFlow {
+    /// Type and net amount of the transaction, or `None` if it doesn't touch
+    /// the on-chain wallet. Boarding outputs still belong to the wallet, so a
+    /// self-funded deposit only costs the fee.
+    fn classify(&self) -> Option<(TransactionType, i64)> {
+        if self.sent == 0 {
+            return (self.received > 0).then_some((TransactionType::OnChain, self.received as i64));
+        }
+
+        let net = (self.received + self.boarding) as i64 - self.sent as i64;
+        let tx_type = if self.boarding > 0 {
+            TransactionType::Boarding
+        } else if self.external == 0 {
+            TransactionType::SelfTransfer
+        } else {
+            TransactionType::OnChain
+        };
+
+        Some((tx_type, net))
+    }
+}
+
 impl BitcoinService {
     pub async fn new(
-        keypair: Keypair,
+        session: KeySession,
+        public_keys: WalletPublicKeys,
         config: WalletConfig,
+        chain: Arc<dyn ChainBackend>,
         storage: Arc<Storage>,
         wallet_id: String,
     ) -> Result<Self> {
-        let client = esplora_client::Builder::new(&config.esplora_url)
-            .build_async()
-            .map_err(|e| ArkiveError::esplora(format!("Failed to create esplora client: {}", e)))?;
-
-        let tx_manager = TransactionManager::new(storage, wallet_id.clone());
+        let account_path = config.key_derivation.onchain_account_path()?;
+        let tx_manager = TransactionManager::new(storage.clone(), wallet_id.clone());
 
         Ok(Self {
-            keypair,
+            session,
+            account_xpub: public_keys.onchain_account,
+            account_path,
             config,
-            client,
+            chain,
+            storage,
+            wallet_id,
             tx_manager,
         })
     }
 
+    /// Next unused receive address, deriving a new one once every revealed
+    /// address has received funds
     pub async fn get_address(&self) -> Result<String> {
-        let pubkey = self.keypair.public_key();
-        let pubkey_bytes = pubkey.serialize();
-        let wpkh = bitcoin::key::CompressedPublicKey::from_slice(&pubkey_bytes)
-            .map_err(|e| ArkiveError::internal(format!("Failed to create WPKH: {}", e)))?;
-        let address = bitcoin::Address::p2wpkh(&wpkh, self.config.network);
-        Ok(address.to_string())
+        self.next_unused_address(Keychain::External).await
     }
 
-    pub async fn get_balance(&self) -> Result<Amount> {
-        let address_str = self.get_address().await?;
-        let address = bitcoin::Address::from_str(&address_str)
-            .map_err(|e| ArkiveError::bitcoin(format!("Invalid address: {}", e)))?
-            .assume_checked();
+    /// Derive a fresh receive address
+    pub async fn new_address(&self) -> Result<String> {
+        Ok(self.reveal_next_address(Keychain::External).await?.address)
+    }
+
+    /// Next unused change address
+    pub async fn get_change_address(&self) -> Result<String> {
+        self.next_unused_address(Keychain::Internal).await
+    }
 
-        let script_pubkey = address.script_pubkey();
+    /// Change script for a draft: the next unused change address, derived
+    /// but not revealed if every revealed one was used. Only broadcasting
+    /// reveals it, see [`Self::record_sent`], so quoting fees doesn't burn
+    /// through the change keychain.
+    async fn peek_change_script(&self) -> Result<ScriptBuf> {
+        let addresses = AddressStore::new(&self.storage)
+            .load_addresses(&self.wallet_id, Keychain::Internal)
+            .await?;
 
-        // Get UTXOs for this address
-        let txs = self
-            .client
-            .scripthash_txs(&script_pubkey, None)
-            .await
-            .map_err(|e| ArkiveError::esplora(format!("Failed to get transactions: {}", e)))?;
-
-        let mut balance = Amount::ZERO;
-
-        for tx in txs {
-            for (vout, output) in tx.vout.iter().enumerate() {
-                if output.scriptpubkey == script_pubkey {
-                    // Check if this output is unspent
-                    let is_spent = match self.client.get_output_status(&tx.txid, vout as u64).await
-                    {
-                        Ok(Some(status)) => status.spent,
-                        Ok(None) => false,
-                        Err(_) => false, // Assume unspent if we can't check
-                    };
-
-                    if !is_spent {
-                        balance += Amount::from_sat(output.value);
+        let address = match addresses.iter().find(|a| !a.used) {
+            Some(record) => record.address.clone(),
+            None => {
+                let next_index = addresses.last().map(|a| a.index + 1).unwrap_or(0);
+                self.address_record(Keychain::Internal, next_index, false)?
+                    .address
+            }
+        };
+
+        Ok(self.parse_address(&address)?.script_pubkey())
+    }
+
+    /// Mark the change address paid by a broadcast transaction used,
+    /// revealing it first if the draft only peeked at it
+    async fn reveal_change(&self, script_pubkey: &Script) -> Result<()> {
+        let address = bitcoin::Address::from_script(script_pubkey, self.config.network)
+            .map_err(|e| ArkiveError::bitcoin(format!("Invalid change script: {}", e)))?
+            .to_string();
+
+        let address_store = AddressStore::new(&self.storage);
+        let addresses = address_store
+            .load_addresses(&self.wallet_id, Keychain::Internal)
+            .await?;
+        if addresses.iter().any(|a| a.address == address) {
+            return address_store.mark_used(&self.wallet_id, &address).await;
+        }
+
+        let next_index = addresses.last().map(|a| a.index + 1).unwrap_or(0);
+        let record = self.address_record(Keychain::Internal, next_index, true)?;
+        if record.address != address {
+            // Another send revealed the peeked index in the meantime
+            tracing::warn!(
+                "Change address {} is not the next one of the change keychain",
+                address
+            );
+            return Ok(());
+        }
+        address_store.save_address(&self.wallet_id, &record).await
+    }
+
+    /// Every address revealed so far, receive addresses first
+    pub async fn list_addresses(&self) -> Result<Vec<AddressRecord>> {
+        let address_store = AddressStore::new(&self.storage);
+        let mut addresses = address_store
+            .load_addresses(&self.wallet_id, Keychain::External)
+            .await?;
+        addresses.extend(
+            address_store
+                .load_addresses(&self.wallet_id, Keychain::Internal)
+                .await?,
+        );
+        Ok(addresses)
+    }
+
+    async fn next_unused_address(&self, keychain: Keychain) -> Result<String> {
+        let address_store = AddressStore::new(&self.storage);
+        let addresses = address_store
+            .load_addresses(&self.wallet_id, keychain)
+            .await?;
+
+        if let Some(record) = addresses.into_iter().find(|a| !a.used) {
+            return Ok(record.address);
+        }
+
+        Ok(self.reveal_next_address(keychain).await?.address)
+    }
+
+    async fn reveal_next_address(&self, keychain: Keychain) -> Result<AddressRecord> {
+        let address_store = AddressStore::new(&self.storage);
+        let next_index = address_store
+            .load_addresses(&self.wallet_id, keychain)
+            .await?
+            .last()
+            .map(|a| a.index + 1)
+            .unwrap_or(0);
+
+        let record = self.address_record(keychain, next_index, false)?;
+        address_store.save_address(&self.wallet_id, &record).await?;
+
+        tracing::debug!(
+            "Revealed {:?} address {} at {}",
+            keychain,
+            record.address,
+            record.derivation_path
+        );
+        Ok(record)
+    }
+
+    fn address_record(&self, keychain: Keychain, index: u32, used: bool) -> Result<AddressRecord> {
+        let path = self.relative_path(keychain, index)?;
+        let address = self.derive_address(&path)?;
+
+        Ok(AddressRecord {
+            address: address.to_string(),
+            keychain,
+            index,
+            derivation_path: format!("m/{}", self.account_path.extend(&path)),
+            used,
+            created_at: Utc::now(),
+        })
+    }
+
+    fn relative_path(&self, keychain: Keychain, index: u32) -> Result<[ChildNumber; 2]> {
+        let to_child = |i: u32| {
+            ChildNumber::from_normal_idx(i)
+                .map_err(|e| ArkiveError::internal(format!("Invalid address index: {}", e)))
+        };
+        Ok([to_child(keychain.index())?, to_child(index)?])
+    }
+
+    fn derive_address(&self, path: &[ChildNumber]) -> Result<bitcoin::Address> {
+        let secp = Secp256k1::verification_only();
+        let child = self
+            .account_xpub
+            .derive_pub(&secp, path)
+            .map_err(|e| ArkiveError::internal(format!("Failed to derive address: {}", e)))?;
+
+        Ok(self
+            .config
+            .onchain_script
+            .address(&child.public_key, self.config.network))
+    }
+
+    /// Walk both keychains until `gap_limit` consecutive addresses past the
+    /// last used (and last revealed) one have no history. Used addresses are
+    /// recorded so later address requests skip them.
+    async fn scan_addresses(&self) -> Result<WalletScan> {
+        self.scan_addresses_since(None).await
+    }
+
+    /// Like [`Self::scan_addresses`], but addresses covered by `since` only
+    /// fetch activity newer than its height
+    async fn scan_addresses_since(&self, since: Option<&ChainSyncState>) -> Result<WalletScan> {
+        let address_store = AddressStore::new(&self.storage);
+        let mut scripts = HashMap::new();
+        let mut txs: HashMap<Txid, ChainTx> = HashMap::new();
+        let mut last_index = [0; 2];
+
+        for keychain in [Keychain::External, Keychain::Internal] {
+            let records = address_store
+                .load_addresses(&self.wallet_id, keychain)
+                .await?;
+            let last_revealed = records.last().map(|a| a.index);
+            let used: HashSet<u32> = records.iter().filter(|a| a.used).map(|a| a.index).collect();
+
+            let mut index = 0;
+            let mut gap = 0;
+            loop {
+                let script_pubkey = self
+                    .derive_address(&self.relative_path(keychain, index)?)?
+                    .script_pubkey();
+
+                let since_height = since
+                    .filter(|state| index <= state.last_index(keychain))
+                    .map(|state| state.height);
+                let history = self
+                    .chain
+                    .script_history(&script_pubkey, since_height)
+                    .await?;
+
+                if history.is_empty() && !used.contains(&index) {
+                    gap += 1;
+                } else {
+                    gap = 0;
+                    if !used.contains(&index) {
+                        let record = self.address_record(keychain, index, true)?;
+                        address_store.save_address(&self.wallet_id, &record).await?;
+                    }
+                    scripts.insert(script_pubkey, (keychain, index));
+                    for tx in history {
+                        txs.insert(tx.txid, tx);
                     }
                 }
+
+                index += 1;
+                let past_revealed = last_revealed.map_or(true, |last| index > last);
+                if gap >= self.config.gap_limit && past_revealed {
+                    break;
+                }
+            }
+
+            last_index[keychain.index() as usize] = index - 1;
+            tracing::debug!("Scanned {} {:?} addresses", index, keychain);
+        }
+
+        Ok(WalletScan {
+            scripts,
+            txs: txs.into_values().collect(),
+            last_index,
+        })
+    }
+
+    /// Height of the first block after the last recorded one still in the
+    /// best chain, if any recorded block was reorganized out. Blocks more
+    /// than [`REORG_DEPTH`] below the tip are assumed final.
+    async fn find_reorg(
+        &self,
+        since: Option<&ChainSyncState>,
+        tip_height: u32,
+    ) -> Result<Option<u32>> {
+        let min_height = tip_height.saturating_sub(REORG_DEPTH);
+
+        let mut recorded: BTreeMap<u32, HashSet<String>> = BTreeMap::new();
+        let blocks = self
+            .tx_manager
+            .load_confirmation_blocks(min_height)
+            .await?
+            .into_iter()
+            .chain(
+                BoardingStore::new(&self.storage)
+                    .load_confirmation_blocks(&self.wallet_id, min_height)
+                    .await?,
+            )
+            .chain(
+                UtxoStore::new(&self.storage)
+                    .load_blocks(&self.wallet_id, min_height)
+                    .await?,
+            )
+            .chain(
+                since
+                    .filter(|state| state.height >= min_height)
+                    .and_then(|state| Some((state.height, state.tip_hash?.to_string()))),
+            );
+        for (height, hash) in blocks {
+            recorded.entry(height).or_default().insert(hash);
+        }
+
+        find_fork(self.chain.as_ref(), &recorded, min_height).await
+    }
+
+    /// Bring the local UTXO set up to date. Only activity since the last
+    /// synced height is fetched for addresses a previous sync covered.
+    ///
+    /// Confirmations recorded in blocks that were reorganized out are rolled
+    /// back first: transactions return to pending, boarding outputs become
+    /// ineligible and the sync resumes from below the fork.
+    pub async fn sync(&self) -> Result<()> {
+        let utxo_store = UtxoStore::new(&self.storage);
+        let mut since = utxo_store.load_sync_state(&self.wallet_id).await?;
+
+        // Read the tip first so blocks found during the scan are fetched again
+        let tip_height = self.chain.tip_height().await?;
+
+        if let Some(fork_height) = self.find_reorg(since.as_ref(), tip_height).await? {
+            tracing::warn!(
+                "Blocks from height {} were reorganized out, rolling back confirmations",
+                fork_height
+            );
+            self.tx_manager.rollback(fork_height).await?;
+            BoardingStore::new(&self.storage)
+                .rollback(&self.wallet_id, fork_height)
+                .await?;
+            utxo_store.rollback(&self.wallet_id, fork_height).await?;
+            since = utxo_store.load_sync_state(&self.wallet_id).await?;
+        }
+
+        let tip_hash = self.chain.block_hash(tip_height).await?;
+        let scan = self.scan_addresses_since(since.as_ref()).await?;
+
+        let mut blocks = Vec::new();
+        for tx in &scan.txs {
+            if let (Some(height), Some(hash)) = (tx.status.block_height, tx.status.block_hash) {
+                blocks.push((height, hash));
             }
+            // Keep the confirmation block of recorded transactions current
+            // so a later reorg of it is noticed
+            self.tx_manager
+                .refresh_confirmation(&tx.txid.to_string(), &tx.status)
+                .await?;
+        }
+
+        let outputs: Vec<WalletUtxo> = scan.outputs().collect();
+        let spends: Vec<UtxoSpend> = scan
+            .txs
+            .iter()
+            .flat_map(|tx| {
+                tx.tx.input.iter().map(move |input| UtxoSpend {
+                    outpoint: input.previous_output,
+                    spent_by: tx.txid,
+                    height: tx.status.block_height,
+                })
+            })
+            .collect();
+
+        let state = ChainSyncState {
+            height: tip_height,
+            tip_hash,
+            external_index: scan.last_index[0].max(since.map_or(0, |s| s.external_index)),
+            internal_index: scan.last_index[1].max(since.map_or(0, |s| s.internal_index)),
+        };
+        utxo_store
+            .apply_sync(&self.wallet_id, &outputs, &spends, &blocks, &state)
+            .await?;
+
+        tracing::info!(
+            "Synced on-chain wallet to height {}: {} new or unconfirmed transactions",
+            tip_height,
+            scan.txs.len()
+        );
+        Ok(())
+    }
+
+    /// Unspent outputs from the local UTXO set, syncing first if the wallet
+    /// has never been synced
+    pub async fn list_unspent(&self) -> Result<Vec<WalletUtxo>> {
+        let utxo_store = UtxoStore::new(&self.storage);
+        if utxo_store.load_sync_state(&self.wallet_id).await?.is_none() {
+            self.sync().await?;
+        }
+
+        utxo_store.load_unspent(&self.wallet_id).await
+    }
+
+    /// Height of the best block on the wallet's chain backend
+    pub async fn tip_height(&self) -> Result<u32> {
+        self.chain.tip_height().await
+    }
+
+    /// Unspent outputs with their confirmation count, largest first
+    pub async fn list_utxos(&self) -> Result<Vec<UtxoInfo>> {
+        let mut utxos = self.list_unspent().await?;
+        let frozen = self.frozen_outpoints().await?;
+        let tip_height = self.chain.tip_height().await?;
+
+        utxos.sort_by(|a, b| b.txout.value.cmp(&a.txout.value));
+        utxos
+            .into_iter()
+            .map(|utxo| {
+                let address = bitcoin::Address::from_script(
+                    &utxo.txout.script_pubkey,
+                    self.config.network,
+                )
+                .map_err(|e| ArkiveError::bitcoin(format!("Invalid wallet script: {}", e)))?;
+
+                Ok(UtxoInfo {
+                    outpoint: utxo.outpoint.to_string(),
+                    value: utxo.txout.value,
+                    confirmations: chain::confirmations(utxo.confirmation_height, tip_height),
+                    address: address.to_string(),
+                    frozen: frozen.contains(&utxo.outpoint),
+                })
+            })
+            .collect()
+    }
+
+    /// Exclude an unspent output from automatic coin selection
+    pub async fn freeze_utxo(&self, outpoint: &str) -> Result<()> {
+        let outpoint = parse_outpoint(outpoint)?;
+        let utxos = self.list_unspent().await?;
+        if !utxos.iter().any(|utxo| utxo.outpoint == outpoint) {
+            return Err(ArkiveError::bitcoin(format!(
+                "{} is not an unspent output of this wallet",
+                outpoint
+            )));
+        }
+
+        UtxoStore::new(&self.storage)
+            .freeze_utxo(&self.wallet_id, &outpoint)
+            .await?;
+        tracing::info!("Froze UTXO {}", outpoint);
+        Ok(())
+    }
+
+    pub async fn unfreeze_utxo(&self, outpoint: &str) -> Result<()> {
+        let outpoint = parse_outpoint(outpoint)?;
+        let unfrozen = UtxoStore::new(&self.storage)
+            .unfreeze_utxo(&self.wallet_id, &outpoint)
+            .await?;
+        if !unfrozen {
+            return Err(ArkiveError::bitcoin(format!("{} is not frozen", outpoint)));
+        }
+
+        tracing::info!("Unfroze UTXO {}", outpoint);
+        Ok(())
+    }
+
+    async fn frozen_outpoints(&self) -> Result<HashSet<OutPoint>> {
+        UtxoStore::new(&self.storage)
+            .load_frozen_outpoints(&self.wallet_id)
+            .await
+    }
+
+    pub async fn get_balance(&self) -> Result<Amount> {
+        let utxos = self.list_unspent().await?;
+        Ok(utxos.iter().map(|u| u.txout.value).sum())
+    }
+
+    /// Balance available to automatic coin selection, i.e. without frozen
+    /// outputs
+    pub async fn get_unfrozen_balance(&self) -> Result<Amount> {
+        let frozen = self.frozen_outpoints().await?;
+        let utxos = self.list_unspent().await?;
+        Ok(utxos
+            .iter()
+            .filter(|u| !frozen.contains(&u.outpoint))
+            .map(|u| u.txout.value)
+            .sum())
+    }
+
+    /// Send `amount` to `address`, spending exactly `inputs` when given and
+    /// selecting from the unfrozen outputs otherwise
+    pub async fn send(
+        &self,
+        address: &str,
+        amount: Amount,
+        inputs: Option<&[OutPoint]>,
+    ) -> Result<String> {
+        self.session.ensure_unlocked()?;
+
+        let fee_rate = self
+            .fee_rate(self.config.fee_policy.default_priority)
+            .await?;
+        let plan = self.draft_send(address, amount, fee_rate, inputs).await?;
+
+        let psbt = tx_builder::build_psbt(&plan, &self.account_xpub)?;
+        let tx = self
+            .session
+            .with_keys(|keys| tx_builder::sign_psbt(psbt, &keys.onchain_account))??;
+        let txid = tx.compute_txid();
+
+        self.chain.broadcast(&tx).await?;
+
+        tracing::info!(
+            "Broadcast on-chain transaction {} sending {} sats with {} sats fee",
+            txid,
+            amount.to_sat(),
+            plan.fee.to_sat()
+        );
+
+        self.record_sent(&tx, &plan, amount).await
+    }
+
+    /// Pay every recipient from one transaction with a single change output
+    pub async fn send_batch(&self, recipients: &[(String, Amount)]) -> Result<String> {
+        self.session.ensure_unlocked()?;
+
+        let fee_rate = self
+            .fee_rate(self.config.fee_policy.default_priority)
+            .await?;
+        let plan = self.draft_batch(recipients, fee_rate, None).await?;
+        let amount = recipients.iter().map(|(_, amount)| *amount).sum::<Amount>();
+
+        let psbt = tx_builder::build_psbt(&plan, &self.account_xpub)?;
+        let tx = self
+            .session
+            .with_keys(|keys| tx_builder::sign_psbt(psbt, &keys.onchain_account))??;
+        let txid = tx.compute_txid();
+
+        self.chain.broadcast(&tx).await?;
+
+        tracing::info!(
+            "Broadcast on-chain transaction {} paying {} recipients {} sats with {} sats fee",
+            txid,
+            recipients.len(),
+            amount.to_sat(),
+            plan.fee.to_sat()
+        );
+
+        self.record_sent(&tx, &plan, amount).await
+    }
+
+    /// Send every unfrozen output to `address` with the fee deducted from
+    /// the amount, returning the txid and the amount received. Outputs
+    /// worth less than the fee of spending them are left behind.
+    pub async fn send_max(&self, address: &str) -> Result<(String, Amount)> {
+        self.session.ensure_unlocked()?;
+
+        let fee_rate = self
+            .fee_rate(self.config.fee_policy.default_priority)
+            .await?;
+        let (plan, amount) = self.draft_sweep(address, fee_rate).await?;
+
+        let psbt = tx_builder::build_psbt(&plan, &self.account_xpub)?;
+        let tx = self
+            .session
+            .with_keys(|keys| tx_builder::sign_psbt(psbt, &keys.onchain_account))??;
+        let txid = tx.compute_txid();
+
+        self.chain.broadcast(&tx).await?;
+
+        tracing::info!(
+            "Broadcast on-chain transaction {} sweeping {} inputs, {} sats with {} sats fee",
+            txid,
+            plan.inputs.len(),
+            amount.to_sat(),
+            plan.fee.to_sat()
+        );
+
+        let txid = self.record_sent(&tx, &plan, amount).await?;
+        Ok((txid, amount))
+    }
+
+    /// Replace an unconfirmed send with one paying `fee_rate`, spending the
+    /// same inputs plus extra confirmed coins if the original change can't
+    /// cover the higher fee
+    pub async fn bump_fee(&self, txid: &str, fee_rate: FeeRate) -> Result<String> {
+        self.session.ensure_unlocked()?;
+
+        let original_txid = Txid::from_str(txid)
+            .map_err(|e| ArkiveError::bitcoin(format!("Invalid txid {}: {}", txid, e)))?;
+        let raw_tx = self
+            .tx_manager
+            .get_raw_transaction(txid)
+            .await?
+            .ok_or_else(|| {
+                ArkiveError::bitcoin(format!("Transaction {} was not sent by this wallet", txid))
+            })?;
+        let original: bitcoin::Transaction =
+            bitcoin::consensus::encode::deserialize_hex(&raw_tx)
+                .map_err(|e| ArkiveError::bitcoin(format!("Invalid stored transaction: {}", e)))?;
+
+        let status = self.chain.get_tx_status(&original_txid).await?;
+        if status.is_confirmed() {
+            return Err(ArkiveError::bitcoin(format!(
+                "Transaction {} is already confirmed",
+                txid
+            )));
         }
+        if !original.is_explicitly_rbf() {
+            return Err(ArkiveError::bitcoin(format!(
+                "Transaction {} does not signal replaceability",
+                txid
+            )));
+        }
+
+        let scan = self.scan_addresses().await?;
+        let wallet_outputs: HashMap<OutPoint, WalletUtxo> =
+            scan.outputs().map(|utxo| (utxo.outpoint, utxo)).collect();
+
+        let inputs = original
+            .input
+            .iter()
+            .map(|input| {
+                wallet_outputs
+                    .get(&input.previous_output)
+                    .cloned()
+                    .ok_or_else(|| {
+                        ArkiveError::bitcoin(format!(
+                            "Input {} is not controlled by this wallet",
+                            input.previous_output
+                        ))
+                    })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let input_total = inputs.iter().map(|u| u.txout.value).sum::<Amount>();
+        let output_total = original.output.iter().map(|o| o.value).sum::<Amount>();
+        let original_fee = input_total
+            .checked_sub(output_total)
+            .ok_or_else(|| ArkiveError::bitcoin("Original transaction has a negative fee"))?;
+
+        // Outputs to the internal keychain are change, the rest is paid again
+        let (change, recipients): (Vec<TxOut>, Vec<TxOut>) =
+            original.output.iter().cloned().partition(|output| {
+                matches!(
+                    scan.scripts.get(&output.script_pubkey),
+                    Some((Keychain::Internal, _))
+                )
+            });
+        let change_script = match change.into_iter().next() {
+            Some(output) => output.script_pubkey,
+            None => self.peek_change_script().await?,
+        };
+
+        // Replacements may not add unconfirmed inputs
+        let frozen = self.frozen_outpoints().await?;
+        let extra_utxos = scan
+            .unspent()
+            .into_iter()
+            .filter(|utxo| utxo.is_confirmed() && !frozen.contains(&utxo.outpoint))
+            .collect();
+
+        let recipient_total = recipients.iter().map(|o| o.value).sum::<Amount>();
+        let plan = tx_builder::select_coins_with(
+            inputs,
+            extra_utxos,
+            recipients,
+            change_script,
+            fee_rate,
+        )?;
+
+        // The replacement must also pay for its own relay on top of the
+        // original fee
+        let min_fee = original_fee
+            + FeeRate::BROADCAST_MIN
+                .fee_vb(plan.vsize())
+                .ok_or_else(|| ArkiveError::bitcoin("Fee overflow"))?;
+        if plan.fee < min_fee {
+            return Err(ArkiveError::bitcoin(format!(
+                "Fee rate of {} sat/vB is too low to replace {}: the replacement pays {} sats but needs at least {} sats",
+                fee_rate.to_sat_per_vb_ceil(),
+                txid,
+                plan.fee.to_sat(),
+                min_fee.to_sat()
+            )));
+        }
+
+        let psbt = tx_builder::build_psbt(&plan, &self.account_xpub)?;
+        let tx = self
+            .session
+            .with_keys(|keys| tx_builder::sign_psbt(psbt, &keys.onchain_account))??;
+        let replacement_txid = tx.compute_txid();
+
+        self.chain.broadcast(&tx).await?;
+
+        tracing::info!(
+            "Replaced on-chain transaction {} with {}, raising the fee from {} to {} sats",
+            txid,
+            replacement_txid,
+            original_fee.to_sat(),
+            plan.fee.to_sat()
+        );
+
+        let replacement_txid = self.record_sent(&tx, &plan, recipient_total).await?;
+        self.tx_manager
+            .update_transaction_status(txid, TransactionStatus::Replaced, None)
+            .await?;
+
+        Ok(replacement_txid)
+    }
+
+    /// Accelerate an unconfirmed transaction paying this wallet by spending
+    /// its outputs in a child paying `fee_rate` for the whole package.
+    ///
+    /// Boarding outputs can't be spent alone before the exit delay passes, so
+    /// a boarding deposit can only be accelerated through another output of
+    /// the same transaction that belongs to the on-chain wallet.
+    pub async fn cpfp(&self, parent_txid: &str, fee_rate: FeeRate) -> Result<String> {
+        self.session.ensure_unlocked()?;
+
+        let txid = Txid::from_str(parent_txid)
+            .map_err(|e| ArkiveError::bitcoin(format!("Invalid txid {}: {}", parent_txid, e)))?;
 
-        Ok(balance)
+        let scan = self.scan_addresses().await?;
+        let parent = scan.txs.iter().find(|tx| tx.txid == txid).ok_or_else(|| {
+            ArkiveError::bitcoin(format!(
+                "Transaction {} does not pay this wallet",
+                parent_txid
+            ))
+        })?;
+        if parent.status.is_confirmed() {
+            return Err(ArkiveError::bitcoin(format!(
+                "Transaction {} is already confirmed",
+                parent_txid
+            )));
+        }
+
+        let inputs: Vec<WalletUtxo> = scan
+            .unspent()
+            .into_iter()
+            .filter(|utxo| utxo.outpoint.txid == txid)
+            .collect();
+        if inputs.is_empty() {
+            return Err(ArkiveError::bitcoin(format!(
+                "Transaction {} has no unspent outputs this wallet can spend alone",
+                parent_txid
+            )));
+        }
+
+        // The child makes up what the parent is missing at the package rate
+        let parent_fee = parent.fee().ok_or_else(|| {
+            ArkiveError::bitcoin(format!("Fee of transaction {} is unknown", parent_txid))
+        })?;
+        let extra_fee = fee_rate
+            .fee_vb(parent.vsize())
+            .ok_or_else(|| ArkiveError::bitcoin("Fee overflow"))?
+            .checked_sub(parent_fee)
+            .filter(|fee| *fee > Amount::ZERO)
+            .ok_or_else(|| {
+                ArkiveError::bitcoin(format!(
+                    "Transaction {} already pays at least {} sat/vB",
+                    parent_txid,
+                    fee_rate.to_sat_per_vb_ceil()
+                ))
+            })?;
+
+        let destination = self.peek_change_script().await?;
+        let mut plan = tx_builder::sweep(inputs, destination.clone(), fee_rate, extra_fee)?;
+        // The child pays back to the wallet's change keychain, which marks
+        // the address used once broadcast
+        plan.change_index = plan
+            .outputs
+            .iter()
+            .position(|output| output.script_pubkey == destination);
+
+        let psbt = tx_builder::build_psbt(&plan, &self.account_xpub)?;
+        let tx = self
+            .session
+            .with_keys(|keys| tx_builder::sign_psbt(psbt, &keys.onchain_account))??;
+
+        self.chain.broadcast(&tx).await?;
+
+        tracing::info!(
+            "Broadcast child {} paying {} sats to accelerate {}",
+            tx.compute_txid(),
+            plan.fee.to_sat(),
+            parent_txid
+        );
+
+        self.record_sent(&tx, &plan, Amount::ZERO).await
     }
 
-    #[allow(unused_variables)]
-    pub async fn send(&self, address: &str, amount: Amount) -> Result<String> {
-        // This is a simplified implementation
-        // In a real implementation, you would:
-        // 1. Build a proper transaction with UTXO selection
-        // 2. Sign the transaction
-        // 3. Broadcast it
+    /// Broadcast `parent` together with a child spending its pay-to-anchor
+    /// output `anchor` and confirmed wallet coins, paying `fee_rate` for both.
+    /// The parent is assumed to pay no fee of its own, as is the case for
+    /// the presigned transactions of a VTXO tree.
+    pub async fn broadcast_with_child(
+        &self,
+        parent: &bitcoin::Transaction,
+        anchor: (OutPoint, TxOut),
+        fee_rate: FeeRate,
+    ) -> Result<String> {
+        self.session.ensure_unlocked()?;
 
-        // For now, return a placeholder
-        Err(ArkiveError::internal("Bitcoin sending not yet implemented"))
+        self.sync().await?;
+        let frozen = self.frozen_outpoints().await?;
+        let utxos: Vec<WalletUtxo> = self
+            .list_unspent()
+            .await?
+            .into_iter()
+            .filter(|utxo| !frozen.contains(&utxo.outpoint))
+            .collect();
+
+        let (anchor_outpoint, anchor_txout) = anchor;
+        let parent_weight = parent.weight() + Weight::from_wu(tx_builder::ANCHOR_INPUT_WEIGHT);
+        let extra_fee = fee_rate
+            .fee_wu(parent_weight)
+            .ok_or_else(|| ArkiveError::bitcoin("Fee overflow"))?;
+
+        let destination = self.peek_change_script().await?;
+        let plan = tx_builder::fund_child(utxos, destination, fee_rate, extra_fee)?;
+
+        let mut psbt = tx_builder::build_psbt(&plan, &self.account_xpub)?;
+        tx_builder::add_anchor_input(&mut psbt, anchor_outpoint, anchor_txout);
+        // A child of a v3 parent has to be v3 itself
+        psbt.unsigned_tx.version = psbt.unsigned_tx.version.max(parent.version);
+        let tx = self
+            .session
+            .with_keys(|keys| tx_builder::sign_psbt(psbt, &keys.onchain_account))??;
+
+        self.chain
+            .broadcast_package(&[parent.clone(), tx.clone()])
+            .await?;
+
+        tracing::info!(
+            "Broadcast {} with child {} paying {} sats for both",
+            parent.compute_txid(),
+            tx.compute_txid(),
+            plan.fee.to_sat()
+        );
+
+        self.record_sent(&tx, &plan, Amount::ZERO).await
     }
 
-    pub async fn get_transaction_history(&self) -> Result<Vec<Transaction>> {
-        let address_str = self.get_address().await?;
-        let address = bitcoin::Address::from_str(&address_str)
-            .map_err(|e| ArkiveError::bitcoin(format!("Invalid address: {}", e)))?
-            .assume_checked();
+    /// Record a broadcast transaction built from `plan` that sent `amount`
+    /// out of the wallet, and reveal its change address as used
+    async fn record_sent(
+        &self,
+        tx: &bitcoin::Transaction,
+        plan: &TxPlan,
+        amount: Amount,
+    ) -> Result<String> {
+        if let Some(change) = plan.change() {
+            self.reveal_change(&change.script_pubkey).await?;
+        }
+
+        let txid = tx.compute_txid().to_string();
+        self.tx_manager
+            .record_transaction_if_new(
+                &txid,
+                -((amount + plan.fee).to_sat() as i64),
+                TransactionType::OnChain,
+                TransactionSource::Blockchain,
+            )
+            .await?;
+        self.tx_manager
+            .update_transaction_details(
+                &txid,
+                plan.fee,
+                &bitcoin::consensus::encode::serialize_hex(tx),
+            )
+            .await?;
 
-        let script_pubkey = address.script_pubkey();
+        Ok(txid)
+    }
 
-        let txs = self
-            .client
-            .scripthash_txs(&script_pubkey, None)
+    /// Select coins and a change output for paying `amount` to `address`
+    async fn draft_send(
+        &self,
+        address: &str,
+        amount: Amount,
+        fee_rate: FeeRate,
+        inputs: Option<&[OutPoint]>,
+    ) -> Result<TxPlan> {
+        self.draft_batch(&[(address.to_string(), amount)], fee_rate, inputs)
             .await
-            .map_err(|e| ArkiveError::esplora(format!("Failed to get transactions: {}", e)))?;
+    }
 
-        // Record tx using tx manager
-        for tx in &txs {
-            let mut net_amount = 0i64;
+    /// Select coins and a change output for paying every recipient, in
+    /// order, from one transaction
+    async fn draft_batch(
+        &self,
+        recipients: &[(String, Amount)],
+        fee_rate: FeeRate,
+        inputs: Option<&[OutPoint]>,
+    ) -> Result<TxPlan> {
+        if recipients.is_empty() {
+            return Err(ArkiveError::bitcoin("No recipients given"));
+        }
 
-            // Calculate net amount for this tx
-            for output in &tx.vout {
-                if output.scriptpubkey == script_pubkey {
-                    net_amount += output.value as i64;
+        let mut outputs = Vec::with_capacity(recipients.len());
+        for (address, amount) in recipients {
+            let script_pubkey = self.parse_address(address)?.script_pubkey();
+            let dust_limit = script_pubkey.minimal_non_dust();
+            if *amount < dust_limit {
+                return Err(ArkiveError::bitcoin(format!(
+                    "Amount {} sats to {} is below the dust limit of {} sats",
+                    amount.to_sat(),
+                    address,
+                    dust_limit.to_sat()
+                )));
+            }
+            outputs.push(TxOut {
+                value: *amount,
+                script_pubkey,
+            });
+        }
+
+        self.sync().await?;
+        let utxos = self.list_unspent().await?;
+        let frozen = self.frozen_outpoints().await?;
+        let (required, candidates) = match inputs {
+            Some(outpoints) => {
+                let mut required: Vec<WalletUtxo> = Vec::new();
+                for outpoint in outpoints {
+                    // Spending an output twice makes the transaction invalid
+                    if required.iter().any(|utxo| utxo.outpoint == *outpoint) {
+                        continue;
+                    }
+                    if frozen.contains(outpoint) {
+                        return Err(ArkiveError::bitcoin(format!(
+                            "{} is frozen, unfreeze it before spending",
+                            outpoint
+                        )));
+                    }
+                    let utxo = utxos
+                        .iter()
+                        .find(|utxo| utxo.outpoint == *outpoint)
+                        .ok_or_else(|| {
+                            ArkiveError::bitcoin(format!(
+                                "{} is not an unspent output of this wallet",
+                                outpoint
+                            ))
+                        })?;
+                    required.push(utxo.clone());
                 }
+                (required, Vec::new())
             }
+            None => (
+                Vec::new(),
+                utxos
+                    .into_iter()
+                    .filter(|utxo| !frozen.contains(&utxo.outpoint))
+                    .collect(),
+            ),
+        };
+        let change_script = self.peek_change_script().await?;
 
-            if net_amount != 0 {
-                // Use tx manager to record if new
-                self.tx_manager
-                    .record_transaction_if_new(
-                        &tx.txid.to_string(),
-                        net_amount,
-                        TransactionType::OnChain,
-                        TransactionSource::Blockchain,
-                    )
-                    .await?;
+        tx_builder::select_coins_with(required, candidates, outputs, change_script, fee_rate)
+    }
+
+    /// Spend every unfrozen output worth more than its own fee to `address`
+    /// in a single output, returning the plan and the amount received
+    async fn draft_sweep(&self, address: &str, fee_rate: FeeRate) -> Result<(TxPlan, Amount)> {
+        let recipient = self.parse_address(address)?;
+
+        self.sync().await?;
+        let frozen = self.frozen_outpoints().await?;
+        let (inputs, uneconomical): (Vec<WalletUtxo>, Vec<WalletUtxo>) = self
+            .list_unspent()
+            .await?
+            .into_iter()
+            .filter(|utxo| !frozen.contains(&utxo.outpoint))
+            .partition(|utxo| tx_builder::is_economical(utxo, fee_rate));
+        if inputs.is_empty() {
+            return Err(ArkiveError::InsufficientFunds {
+                need: recipient.script_pubkey().minimal_non_dust().to_sat(),
+                available: uneconomical
+                    .iter()
+                    .map(|u| u.txout.value)
+                    .sum::<Amount>()
+                    .to_sat(),
+            });
+        }
+        if !uneconomical.is_empty() {
+            tracing::debug!(
+                "Leaving {} outputs worth less than their fee out of the sweep",
+                uneconomical.len()
+            );
+        }
+
+        let plan = tx_builder::sweep(inputs, recipient.script_pubkey(), fee_rate, Amount::ZERO)?;
+        let amount = plan
+            .outputs
+            .first()
+            .map(|output| output.value)
+            .ok_or_else(|| ArkiveError::internal("Sweep has no output"))?;
+        Ok((plan, amount))
+    }
 
-                // Update status based on confirmation
-                let status = if tx.status.confirmed {
-                    TransactionStatus::Confirmed
+    fn parse_address(&self, address: &str) -> Result<bitcoin::Address> {
+        bitcoin::Address::from_str(address)
+            .map_err(|e| ArkiveError::InvalidAddress(format!("{}: {}", address, e)))?
+            .require_network(self.config.network)
+            .map_err(|e| ArkiveError::InvalidAddress(format!("{}: {}", address, e)))
+    }
+
+    /// Fee rate for `priority` from the chain backend's estimates, capped by
+    /// the wallet's fee policy
+    pub async fn fee_rate(&self, priority: FeePriority) -> Result<FeeRate> {
+        let estimates = self.chain.fee_estimates().await?;
+
+        Ok(fees::fee_rate_for(
+            &estimates,
+            priority,
+            &self.config.fee_policy,
+        ))
+    }
+
+    /// Record the wallet's on-chain transactions with their net effect on
+    /// the wallet and return them. Payments to `boarding_script` funded by
+    /// the wallet are recorded as boarding deposits.
+    pub async fn get_transaction_history(
+        &self,
+        boarding_script: Option<&Script>,
+    ) -> Result<Vec<Transaction>> {
+        let scan = self.scan_addresses().await?;
+        let mut txids = HashSet::new();
+
+        for tx in &scan.txs {
+            let mut flow = TxFlow::default();
+            for prevout in tx.prevouts.iter().flatten() {
+                if scan.scripts.contains_key(&prevout.script_pubkey) {
+                    flow.sent += prevout.value.to_sat();
+                }
+            }
+            for output in &tx.tx.output {
+                let value = output.value.to_sat();
+                if scan.scripts.contains_key(&output.script_pubkey) {
+                    flow.received += value;
+                } else if Some(output.script_pubkey.as_script()) == boarding_script {
+                    flow.boarding += value;
                 } else {
-                    TransactionStatus::Pending
-                };
+                    flow.external += value;
+                }
+            }
+
+            let Some((tx_type, amount)) = flow.classify() else {
+                continue;
+            };
+            // The fee is ours to report only if we funded the transaction
+            let fee = if flow.sent > 0 { tx.fee() } else { None };
 
+            self.tx_manager
+                .upsert_chain_transaction(&tx.txid.to_string(), amount, tx_type, fee, &tx.status)
+                .await?;
+            txids.insert(tx.txid.to_string());
+        }
+
+        let mut transactions = Vec::new();
+        for tx_type in [
+            TransactionType::OnChain,
+            TransactionType::SelfTransfer,
+            TransactionType::Boarding,
+        ] {
+            transactions.extend(
                 self.tx_manager
-                    .update_transaction_status(&tx.txid.to_string(), status, None)
-                    .await?;
-            }
+                    .get_transaction_history_by_type(tx_type)
+                    .await?
+                    .into_iter()
+                    .filter(|tx| txids.contains(&tx.txid)),
+            );
         }
+        transactions.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
 
-        self.tx_manager
-            .get_transaction_history_by_type(TransactionType::OnChain)
-            .await
+        Ok(transactions)
     }
 
-    pub async fn sync(&self) -> Result<()> {
-        // [TODO] Placeholder for sync logic
-        Ok(())
+    pub async fn estimate_fee(&self, address: &str, amount: Amount) -> Result<Amount> {
+        let priority = self.config.fee_policy.default_priority;
+        Ok(self.quote_fee(address, amount, priority).await?.fee)
+    }
+
+    /// Quote the fee of sending `amount` to `address` from the coins the
+    /// wallet would select right now
+    pub async fn quote_fee(
+        &self,
+        address: &str,
+        amount: Amount,
+        priority: FeePriority,
+    ) -> Result<FeeQuote> {
+        let fee_rate = self.fee_rate(priority).await?;
+        let plan = self.draft_send(address, amount, fee_rate, None).await?;
+
+        Ok(FeeQuote {
+            priority,
+            fee_rate,
+            vsize: plan.vsize(),
+            fee: plan.fee,
+        })
+    }
+
+    /// Quote the fee of paying every recipient from one transaction
+    pub async fn quote_batch(
+        &self,
+        recipients: &[(String, Amount)],
+        priority: FeePriority,
+    ) -> Result<FeeQuote> {
+        let fee_rate = self.fee_rate(priority).await?;
+        let plan = self.draft_batch(recipients, fee_rate, None).await?;
+
+        Ok(FeeQuote {
+            priority,
+            fee_rate,
+            vsize: plan.vsize(),
+            fee: plan.fee,
+        })
     }
 
-    pub async fn estimate_fee(&self, _address: &str, _amount: Amount) -> Result<Amount> {
-        // [TODO] Placeholder fee estimation
-        Ok(Amount::from_sat(1000))
+    /// Quote sending every unfrozen output to `address`, returning the
+    /// amount the recipient would get along with the fee
+    pub async fn quote_send_max(
+        &self,
+        address: &str,
+        priority: FeePriority,
+    ) -> Result<(Amount, FeeQuote)> {
+        let fee_rate = self.fee_rate(priority).await?;
+        let (plan, amount) = self.draft_sweep(address, fee_rate).await?;
+
+        let quote = FeeQuote {
+            priority,
+            fee_rate,
+            vsize: plan.vsize(),
+            fee: plan.fee,
+        };
+        Ok((amount, quote))
     }
 }
 
-use std::str::FromStr;
+/// Walk back from the highest recorded block until one is still in the best
+/// chain and return the height just above it, or `None` if the highest one
+/// already is. When none of them are, everything from `min_height` is taken
+/// as reorganized out.
+async fn find_fork(
+    chain: &dyn ChainBackend,
+    recorded: &BTreeMap<u32, HashSet<String>>,
+    min_height: u32,
+) -> Result<Option<u32>> {
+    let mut stale = false;
+    for (&height, hashes) in recorded.range(min_height..).rev() {
+        let current = chain.block_hash(height).await?.map(|h| h.to_string());
+        let in_best_chain = current.is_some_and(|current| hashes.iter().all(|h| *h == current));
+        if in_best_chain {
+            return Ok(stale.then_some(height + 1));
+        }
+        stale = true;
+    }
+
+    Ok(stale.then_some(min_height))
+}
+
+pub(crate) fn parse_outpoint(outpoint: &str) -> Result<OutPoint> {
+    OutPoint::from_str(outpoint)
+        .map_err(|e| ArkiveError::bitcoin(format!("Invalid outpoint {}: {}", outpoint, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::mock::{block, MockChain};
+    use crate::wallet::WalletKeys;
+    use bitcoin::bip32::Xpriv;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{BlockHash, Network, WPubkeyHash};
+
+    /// On-chain wallet on `chain`, with a fresh database in the returned
+    /// directory
+    async fn service(chain: Arc<MockChain>) -> (tempfile::TempDir, BitcoinService) {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let storage = Arc::new(
+            Storage::new(&temp_dir.path().join("arkive.db"))
+                .await
+                .unwrap(),
+        );
+
+        let config = WalletConfig::new(Network::Regtest);
+        let master = Xpriv::new_master(Network::Regtest, &[7; 32]).unwrap();
+        let keys = WalletKeys::derive(&master, &config.key_derivation).unwrap();
+        let public_keys = keys.public_keys();
+        let service = BitcoinService::new(
+            KeySession::unlocked(keys, None),
+            public_keys,
+            config,
+            chain,
+            storage,
+            "wallet".to_string(),
+        )
+        .await
+        .unwrap();
+
+        (temp_dir, service)
+    }
+
+    fn recipient(byte: u8) -> String {
+        let script_pubkey = ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([byte; 20]));
+        bitcoin::Address::from_script(&script_pubkey, Network::Regtest)
+            .unwrap()
+            .to_string()
+    }
+
+    /// Pay `sats` to the wallet's next receive address in a block at `height`
+    async fn fund(chain: &MockChain, service: &BitcoinService, sats: u64, height: u32) -> OutPoint {
+        let address = service.new_address().await.unwrap();
+        let script_pubkey = service.parse_address(&address).unwrap().script_pubkey();
+        chain.receive(script_pubkey, Amount::from_sat(sats), height)
+    }
+
+    #[tokio::test]
+    async fn test_draft_batch() {
+        let chain = Arc::new(MockChain::new(200, None));
+        let (_temp_dir, service) = service(chain.clone()).await;
+        fund(&chain, &service, 100_000, 150).await;
+
+        let mut recipients = vec![
+            (recipient(1), Amount::from_sat(10_000)),
+            (recipient(2), Amount::from_sat(20_000)),
+            (recipient(3), Amount::from_sat(30_000)),
+        ];
+        let fee_rate = FeeRate::from_sat_per_vb(2).unwrap();
+        let plan = service
+            .draft_batch(&recipients, fee_rate, None)
+            .await
+            .unwrap();
+
+        // Every recipient in order, then the change
+        assert_eq!(plan.inputs.len(), 1);
+        assert_eq!(plan.outputs.len(), 4);
+        for ((address, amount), output) in recipients.iter().zip(&plan.outputs) {
+            assert_eq!(output.value, *amount);
+            assert_eq!(
+                output.script_pubkey,
+                service.parse_address(address).unwrap().script_pubkey()
+            );
+        }
+        assert_eq!(plan.change_index, Some(3));
+        assert_eq!(
+            plan.change().unwrap().value,
+            Amount::from_sat(40_000) - plan.fee
+        );
+        assert_eq!(plan.fee, fee_rate.fee_vb(plan.vsize()).unwrap());
+
+        // One dust output fails the whole batch
+        recipients[1].1 = Amount::from_sat(100);
+        assert!(service
+            .draft_batch(&recipients, fee_rate, None)
+            .await
+            .is_err());
+        assert!(service.draft_batch(&[], fee_rate, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_draft_send_with_inputs() {
+        let chain = Arc::new(MockChain::new(200, None));
+        let (_temp_dir, service) = service(chain.clone()).await;
+        let small = fund(&chain, &service, 20_000, 150).await;
+        let large = fund(&chain, &service, 100_000, 150).await;
+        let fee_rate = FeeRate::from_sat_per_vb(2).unwrap();
+
+        // Exactly the given inputs, each spent once
+        let plan = service
+            .draft_send(
+                &recipient(1),
+                Amount::from_sat(10_000),
+                fee_rate,
+                Some(&[small, small]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(plan.inputs.len(), 1);
+        assert_eq!(plan.inputs[0].outpoint, small);
+
+        // Not enough in the given inputs, even though the wallet has it
+        assert!(service
+            .draft_send(
+                &recipient(1),
+                Amount::from_sat(50_000),
+                fee_rate,
+                Some(&[small])
+            )
+            .await
+            .is_err());
+
+        // Frozen outputs are neither spent as given inputs nor counted
+        service.freeze_utxo(&large.to_string()).await.unwrap();
+        assert!(service
+            .draft_send(
+                &recipient(1),
+                Amount::from_sat(10_000),
+                fee_rate,
+                Some(&[large])
+            )
+            .await
+            .is_err());
+        assert_eq!(
+            service.get_unfrozen_balance().await.unwrap(),
+            Amount::from_sat(20_000)
+        );
+        assert_eq!(
+            service.get_balance().await.unwrap(),
+            Amount::from_sat(120_000)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_draft_sweep() {
+        let chain = Arc::new(MockChain::new(200, None));
+        let (_temp_dir, service) = service(chain.clone()).await;
+        let fee_rate = FeeRate::from_sat_per_vb(10).unwrap();
+        assert!(service.draft_sweep(&recipient(1), fee_rate).await.is_err());
+
+        let kept = fund(&chain, &service, 50_000, 150).await;
+        let frozen = fund(&chain, &service, 30_000, 150).await;
+        // Costs more to spend than it is worth at 10 sat/vB
+        fund(&chain, &service, 500, 150).await;
+        service.sync().await.unwrap();
+        service.freeze_utxo(&frozen.to_string()).await.unwrap();
+
+        let (plan, amount) = service.draft_sweep(&recipient(1), fee_rate).await.unwrap();
+        assert_eq!(plan.inputs.len(), 1);
+        assert_eq!(plan.inputs[0].outpoint, kept);
+        assert_eq!(plan.outputs.len(), 1);
+        assert_eq!(amount, Amount::from_sat(50_000) - plan.fee);
+    }
+
+    #[tokio::test]
+    async fn test_quotes_leave_change_keychain_alone() {
+        let chain = Arc::new(MockChain::new(200, None));
+        let (_temp_dir, service) = service(chain.clone()).await;
+        fund(&chain, &service, 100_000, 150).await;
+
+        let priority = FeePriority::Normal;
+        let first = service
+            .quote_fee(&recipient(1), Amount::from_sat(10_000), priority)
+            .await
+            .unwrap();
+        let second = service
+            .quote_batch(&[(recipient(2), Amount::from_sat(10_000))], priority)
+            .await
+            .unwrap();
+        assert_eq!(first.fee, second.fee);
+
+        let change_addresses = |addresses: Vec<AddressRecord>| -> Vec<AddressRecord> {
+            addresses
+                .into_iter()
+                .filter(|a| a.keychain == Keychain::Internal)
+                .collect()
+        };
+        assert!(change_addresses(service.list_addresses().await.unwrap()).is_empty());
+
+        // Sending reveals the change address the drafts were peeking at
+        let peeked = service.peek_change_script().await.unwrap();
+        service
+            .send(&recipient(1), Amount::from_sat(10_000), None)
+            .await
+            .unwrap();
+        let revealed = change_addresses(service.list_addresses().await.unwrap());
+        assert_eq!(revealed.len(), 1);
+        assert!(revealed[0].used);
+        assert_eq!(
+            service
+                .parse_address(&revealed[0].address)
+                .unwrap()
+                .script_pubkey(),
+            peeked
+        );
+        assert_ne!(service.peek_change_script().await.unwrap(), peeked);
+    }
+
+    fn recorded(heights: &[u32]) -> BTreeMap<u32, HashSet<String>> {
+        heights
+            .iter()
+            .map(|&height| (height, HashSet::from([block(height).to_string()])))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_find_fork() {
+        let recorded = recorded(&[100, 105, 110]);
+
+        // Nothing changed
+        let chain = MockChain::new(110, None);
+        assert_eq!(find_fork(&chain, &recorded, 10).await.unwrap(), None);
+
+        // Only the tip was replaced, but the fork is found right above the
+        // last block still in the chain rather than at the old tip
+        let chain = MockChain::new(112, Some((107, 0xee)));
+        assert_eq!(find_fork(&chain, &recorded, 12).await.unwrap(), Some(106));
+
+        // The chain got shorter than the recorded tip
+        let chain = MockChain::new(108, Some((103, 0xee)));
+        assert_eq!(find_fork(&chain, &recorded, 8).await.unwrap(), Some(101));
+
+        // Nothing recorded is left within reach
+        let chain = MockChain::new(110, Some((0, 0xee)));
+        assert_eq!(find_fork(&chain, &recorded, 10).await.unwrap(), Some(10));
+        assert_eq!(find_fork(&chain, &BTreeMap::new(), 10).await.unwrap(), None);
+
+        // Two records disagreeing about a height count as a reorg there
+        let mut conflicting = recorded.clone();
+        conflicting
+            .get_mut(&105)
+            .unwrap()
+            .insert(BlockHash::from_byte_array([0xdd; 32]).to_string());
+        let chain = MockChain::new(110, Some((106, 0xee)));
+        assert_eq!(
+            find_fork(&chain, &conflicting, 10).await.unwrap(),
+            Some(101)
+        );
+    }
+
+    #[test]
+    fn test_classify_tx_flow() {
+        // Incoming payment
+        let flow = TxFlow {
+            received: 50_000,
+            external: 20_000,
+            ..Default::default()
+        };
+        assert!(matches!(
+            flow.classify(),
+            Some((TransactionType::OnChain, 50_000))
+        ));
+
+        // Payment with change: amount sent plus fee
+        let flow = TxFlow {
+            sent: 100_000,
+            received: 39_000,
+            external: 60_000,
+            ..Default::default()
+        };
+        assert!(matches!(
+            flow.classify(),
+            Some((TransactionType::OnChain, -61_000))
+        ));
+
+        // Spend without change is no longer dropped
+        let flow = TxFlow {
+            sent: 100_000,
+            external: 99_000,
+            ..Default::default()
+        };
+        assert!(matches!(
+            flow.classify(),
+            Some((TransactionType::OnChain, -100_000))
+        ));
+
+        // Moving coins between own addresses only costs the fee
+        let flow = TxFlow {
+            sent: 100_000,
+            received: 99_500,
+            ..Default::default()
+        };
+        assert!(matches!(
+            flow.classify(),
+            Some((TransactionType::SelfTransfer, -500))
+        ));
+
+        let flow = TxFlow {
+            sent: 100_000,
+            received: 29_500,
+            boarding: 70_000,
+            ..Default::default()
+        };
+        assert!(matches!(
+            flow.classify(),
+            Some((TransactionType::Boarding, -500))
+        ));
+
+        // Deposits to the boarding address by others are left to the Ark side
+        let flow = TxFlow {
+            boarding: 70_000,
+            external: 10_000,
+            ..Default::default()
+        };
+        assert!(flow.classify().is_none());
+    }
+}
@@ -0,0 +1,634 @@
+use crate::error::{ArkiveError, Result};
+use crate::types::Keychain;
+
+use bitcoin::absolute::LockTime;
+use bitcoin::bip32::{ChildNumber, DerivationPath, Xpriv, Xpub};
+use bitcoin::psbt::Psbt;
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::transaction::Version;
+use bitcoin::{
+    Amount, FeeRate, OutPoint, Script, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Weight,
+    Witness,
+};
+
+/// Witness weight of a P2WPKH spend: item count, DER signature with sighash
+/// byte (at most 72 bytes) and compressed public key, each length-prefixed
+const P2WPKH_SATISFACTION_WEIGHT: u64 = 1 + 1 + 72 + 1 + 33;
+
+/// Witness weight of a taproot key-path spend with the default sighash
+const P2TR_SATISFACTION_WEIGHT: u64 = 1 + 1 + 64;
+
+/// Segwit marker and flag bytes
+const SEGWIT_HEADER_WEIGHT: u64 = 2;
+
+/// Non-witness weight of an input: outpoint, empty script and sequence
+const TXIN_BASE_WEIGHT: u64 = (32 + 4 + 1 + 4) * 4;
+
+/// Weight of an input spending a pay-to-anchor output: outpoint, empty
+/// script, sequence and an empty witness
+pub const ANCHOR_INPUT_WEIGHT: u64 = TXIN_BASE_WEIGHT + 1;
+
+/// Pay-to-anchor output script, `OP_1 <0x4e73>`, spendable by anyone
+/// without a signature
+const ANCHOR_SCRIPT: [u8; 4] = [0x51, 0x02, 0x4e, 0x73];
+
+pub fn is_anchor(script_pubkey: &Script) -> bool {
+    script_pubkey.as_bytes() == ANCHOR_SCRIPT
+}
+
+/// Spendable output of the wallet's HD account
+#[derive(Debug, Clone)]
+pub struct WalletUtxo {
+    pub outpoint: OutPoint,
+    pub txout: TxOut,
+    pub keychain: Keychain,
+    pub index: u32,
+    /// Height of the block confirming the output, if any
+    pub confirmation_height: Option<u32>,
+}
+
+impl WalletUtxo {
+    pub fn is_confirmed(&self) -> bool {
+        self.confirmation_height.is_some()
+    }
+}
+
+/// Inputs, outputs and fee of a transaction ready to be turned into a PSBT
+#[derive(Debug, Clone)]
+pub struct TxPlan {
+    pub inputs: Vec<WalletUtxo>,
+    pub outputs: Vec<TxOut>,
+    /// Index of the change output in `outputs`, if one was added
+    pub change_index: Option<usize>,
+    pub fee: Amount,
+}
+
+impl TxPlan {
+    pub fn weight(&self) -> Weight {
+        estimate_weight(&self.inputs, &self.outputs)
+    }
+
+    pub fn vsize(&self) -> u64 {
+        self.weight().to_vbytes_ceil()
+    }
+
+    pub fn change(&self) -> Option<&TxOut> {
+        self.change_index.map(|i| &self.outputs[i])
+    }
+}
+
+/// Pick inputs paying for `recipients` at `fee_rate`, adding a change output
+/// to `change_script` unless the leftover would be dust.
+///
+/// Confirmed coins are preferred, largest first, to keep the input count low.
+pub fn select_coins(
+    utxos: Vec<WalletUtxo>,
+    recipients: Vec<TxOut>,
+    change_script: ScriptBuf,
+    fee_rate: FeeRate,
+) -> Result<TxPlan> {
+    select_coins_with(Vec::new(), utxos, recipients, change_script, fee_rate)
+}
+
+/// Like [`select_coins`], but always spending `required` and only adding
+/// coins from `utxos` while those fall short
+pub fn select_coins_with(
+    required: Vec<WalletUtxo>,
+    mut utxos: Vec<WalletUtxo>,
+    recipients: Vec<TxOut>,
+    change_script: ScriptBuf,
+    fee_rate: FeeRate,
+) -> Result<TxPlan> {
+    let target = recipients.iter().map(|o| o.value).sum::<Amount>();
+
+    utxos.sort_by(|a, b| {
+        b.is_confirmed()
+            .cmp(&a.is_confirmed())
+            .then(b.txout.value.cmp(&a.txout.value))
+    });
+
+    let mut total = required.iter().map(|u| u.txout.value).sum::<Amount>();
+    let mut selected = required;
+    let mut fee = Amount::ZERO;
+    let mut candidates = utxos.into_iter();
+
+    loop {
+        if !selected.is_empty() {
+            fee = fee_for(&selected, &recipients, fee_rate)?;
+            if total >= target + fee {
+                return finish_plan(selected, recipients, change_script, fee_rate, total);
+            }
+        }
+
+        match candidates.next() {
+            Some(utxo) => {
+                total += utxo.txout.value;
+                selected.push(utxo);
+            }
+            None => break,
+        }
+    }
+
+    Err(ArkiveError::InsufficientFunds {
+        need: (target + fee).to_sat(),
+        available: total.to_sat(),
+    })
+}
+
+/// Spend all of `inputs` to a single output paying `destination`, with a fee
+/// at `fee_rate` plus `extra_fee`, e.g. to pay for an unconfirmed parent
+pub fn sweep(
+    inputs: Vec<WalletUtxo>,
+    destination: ScriptBuf,
+    fee_rate: FeeRate,
+    extra_fee: Amount,
+) -> Result<TxPlan> {
+    let total = inputs.iter().map(|u| u.txout.value).sum::<Amount>();
+    let dust_limit = destination.minimal_non_dust();
+
+    let mut outputs = vec![TxOut {
+        value: Amount::ZERO,
+        script_pubkey: destination,
+    }];
+    let fee = fee_for(&inputs, &outputs, fee_rate)? + extra_fee;
+
+    if total < fee + dust_limit {
+        return Err(ArkiveError::InsufficientFunds {
+            need: (fee + dust_limit).to_sat(),
+            available: total.to_sat(),
+        });
+    }
+
+    outputs[0].value = total - fee;
+    Ok(TxPlan {
+        inputs,
+        outputs,
+        change_index: None,
+        fee,
+    })
+}
+
+/// Spend the fewest confirmed `utxos`, largest first, to a single output
+/// paying `destination` with a fee at `fee_rate` plus `extra_fee`. Used for
+/// children that pay for a parent through its anchor, which only accept
+/// confirmed inputs besides the parent.
+pub fn fund_child(
+    mut utxos: Vec<WalletUtxo>,
+    destination: ScriptBuf,
+    fee_rate: FeeRate,
+    extra_fee: Amount,
+) -> Result<TxPlan> {
+    utxos.retain(WalletUtxo::is_confirmed);
+    utxos.sort_by(|a, b| b.txout.value.cmp(&a.txout.value));
+
+    let mut selected = Vec::new();
+    let mut last_error = ArkiveError::InsufficientFunds {
+        need: extra_fee.to_sat(),
+        available: 0,
+    };
+    for utxo in utxos {
+        selected.push(utxo);
+        match sweep(selected.clone(), destination.clone(), fee_rate, extra_fee) {
+            Ok(plan) => {
+                return Ok(TxPlan {
+                    change_index: Some(0),
+                    ..plan
+                })
+            }
+            Err(e) => last_error = e,
+        }
+    }
+
+    Err(last_error)
+}
+
+fn finish_plan(
+    inputs: Vec<WalletUtxo>,
+    mut outputs: Vec<TxOut>,
+    change_script: ScriptBuf,
+    fee_rate: FeeRate,
+    total: Amount,
+) -> Result<TxPlan> {
+    let target = outputs.iter().map(|o| o.value).sum::<Amount>();
+    let change_dust = change_script.minimal_non_dust();
+
+    outputs.push(TxOut {
+        value: Amount::ZERO,
+        script_pubkey: change_script,
+    });
+    let fee_with_change = fee_for(&inputs, &outputs, fee_rate)?;
+
+    if total >= target + fee_with_change + change_dust {
+        let change_index = outputs.len() - 1;
+        outputs[change_index].value = total - target - fee_with_change;
+        return Ok(TxPlan {
+            inputs,
+            outputs,
+            change_index: Some(change_index),
+            fee: fee_with_change,
+        });
+    }
+
+    // Leftover too small for a change output goes to the fee
+    outputs.pop();
+    Ok(TxPlan {
+        inputs,
+        outputs,
+        change_index: None,
+        fee: total - target,
+    })
+}
+
+fn fee_for(inputs: &[WalletUtxo], outputs: &[TxOut], fee_rate: FeeRate) -> Result<Amount> {
+    fee_rate
+        .fee_vb(estimate_weight(inputs, outputs).to_vbytes_ceil())
+        .ok_or_else(|| ArkiveError::bitcoin("Fee overflow"))
+}
+
+/// Weight of the signed transaction spending `inputs` to `outputs`
+pub fn estimate_weight(inputs: &[WalletUtxo], outputs: &[TxOut]) -> Weight {
+    let tx = unsigned_tx(inputs, outputs);
+    let witness_weight: u64 = inputs
+        .iter()
+        .map(|u| satisfaction_weight(&u.txout.script_pubkey))
+        .sum();
+
+    tx.weight() + Weight::from_wu(SEGWIT_HEADER_WEIGHT + witness_weight)
+}
+
+/// Whether `utxo` is worth more than the fee of spending it at `fee_rate`
+pub fn is_economical(utxo: &WalletUtxo, fee_rate: FeeRate) -> bool {
+    let weight = Weight::from_wu(TXIN_BASE_WEIGHT + satisfaction_weight(&utxo.txout.script_pubkey));
+    fee_rate
+        .fee_wu(weight)
+        .is_some_and(|fee| utxo.txout.value > fee)
+}
+
+fn satisfaction_weight(script_pubkey: &Script) -> u64 {
+    if script_pubkey.is_p2tr() {
+        P2TR_SATISFACTION_WEIGHT
+    } else {
+        P2WPKH_SATISFACTION_WEIGHT
+    }
+}
+
+/// Unsigned transaction with every input signaling replaceability
+fn unsigned_tx(inputs: &[WalletUtxo], outputs: &[TxOut]) -> Transaction {
+    Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: inputs
+            .iter()
+            .map(|u| TxIn {
+                previous_output: u.outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output: outputs.to_vec(),
+    }
+}
+
+/// Build a PSBT for `plan` carrying the key origins needed to sign every
+/// input from the account key
+pub fn build_psbt(plan: &TxPlan, account_xpub: &Xpub) -> Result<Psbt> {
+    let secp = Secp256k1::verification_only();
+    let fingerprint = account_xpub.fingerprint();
+
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx(&plan.inputs, &plan.outputs))
+        .map_err(|e| ArkiveError::bitcoin(format!("Failed to create PSBT: {}", e)))?;
+
+    for (input, utxo) in psbt.inputs.iter_mut().zip(&plan.inputs) {
+        let path: DerivationPath = vec![
+            ChildNumber::from_normal_idx(utxo.keychain.index())?,
+            ChildNumber::from_normal_idx(utxo.index)?,
+        ]
+        .into();
+        let public_key = account_xpub.derive_pub(&secp, &path)?.public_key;
+
+        input.witness_utxo = Some(utxo.txout.clone());
+        if utxo.txout.script_pubkey.is_p2tr() {
+            let (internal_key, _) = public_key.x_only_public_key();
+            input.tap_internal_key = Some(internal_key);
+            input
+                .tap_key_origins
+                .insert(internal_key, (vec![], (fingerprint, path)));
+        } else {
+            input
+                .bip32_derivation
+                .insert(public_key, (fingerprint, path));
+        }
+    }
+
+    Ok(psbt)
+}
+
+/// Sign every input with keys derived from `account_key` and extract the
+/// final transaction
+pub fn sign_psbt(mut psbt: Psbt, account_key: &Xpriv) -> Result<Transaction> {
+    let secp = Secp256k1::new();
+
+    if let Err((_, errors)) = psbt.sign(account_key, &secp) {
+        // Anchors have no key to sign with
+        let anchors_only = errors
+            .keys()
+            .all(|&index| is_anchor_input(&psbt.inputs[index]));
+        if !anchors_only {
+            return Err(ArkiveError::bitcoin(format!(
+                "Failed to sign: {:?}",
+                errors
+            )));
+        }
+    }
+
+    for (index, input) in psbt.inputs.iter_mut().enumerate() {
+        let witness = if is_anchor_input(input) {
+            Witness::new()
+        } else if let Some(signature) = &input.tap_key_sig {
+            Witness::p2tr_key_spend(signature)
+        } else if let Some((public_key, signature)) = input.partial_sigs.iter().next() {
+            Witness::p2wpkh(signature, &public_key.inner)
+        } else {
+            return Err(ArkiveError::bitcoin(format!(
+                "Input {} is not controlled by this wallet",
+                index
+            )));
+        };
+        input.final_script_witness = Some(witness);
+    }
+
+    psbt.extract_tx()
+        .map_err(|e| ArkiveError::bitcoin(format!("Failed to extract transaction: {}", e)))
+}
+
+/// Add an input spending the pay-to-anchor output `anchor` to a PSBT built
+/// from a plan, to be signed along with the wallet's inputs
+pub fn add_anchor_input(psbt: &mut Psbt, outpoint: OutPoint, anchor: TxOut) {
+    psbt.unsigned_tx.input.push(TxIn {
+        previous_output: outpoint,
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+        witness: Witness::new(),
+    });
+    psbt.inputs.push(bitcoin::psbt::Input {
+        witness_utxo: Some(anchor),
+        ..Default::default()
+    });
+}
+
+fn is_anchor_input(input: &bitcoin::psbt::Input) -> bool {
+    input
+        .witness_utxo
+        .as_ref()
+        .is_some_and(|txout| is_anchor(&txout.script_pubkey))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Network, Txid};
+
+    fn account_key() -> Xpriv {
+        Xpriv::new_master(Network::Regtest, &[7u8; 32]).unwrap()
+    }
+
+    fn utxo(account_xpub: &Xpub, index: u32, sats: u64) -> WalletUtxo {
+        let secp = Secp256k1::verification_only();
+        let path = [
+            ChildNumber::from_normal_idx(0).unwrap(),
+            ChildNumber::from_normal_idx(index).unwrap(),
+        ];
+        let public_key = account_xpub.derive_pub(&secp, &path).unwrap().to_pub();
+
+        WalletUtxo {
+            outpoint: OutPoint::new(Txid::from_byte_array([index as u8; 32]), 0),
+            txout: TxOut {
+                value: Amount::from_sat(sats),
+                script_pubkey: ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash()),
+            },
+            keychain: Keychain::External,
+            index,
+            confirmation_height: Some(100),
+        }
+    }
+
+    fn recipient(sats: u64) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(sats),
+            script_pubkey: ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::all_zeros()),
+        }
+    }
+
+    #[test]
+    fn test_select_coins_adds_change() {
+        let secp = Secp256k1::new();
+        let account_xpub = Xpub::from_priv(&secp, &account_key());
+        let change_script = utxo(&account_xpub, 99, 0).txout.script_pubkey;
+        let fee_rate = FeeRate::from_sat_per_vb(2).unwrap();
+
+        let plan = select_coins(
+            vec![
+                utxo(&account_xpub, 0, 10_000),
+                utxo(&account_xpub, 1, 50_000),
+            ],
+            vec![recipient(30_000)],
+            change_script,
+            fee_rate,
+        )
+        .unwrap();
+
+        // The larger coin alone covers the payment
+        assert_eq!(plan.inputs.len(), 1);
+        assert_eq!(plan.fee, fee_rate.fee_vb(plan.vsize()).unwrap());
+        let change = plan.change().unwrap();
+        assert_eq!(change.value, Amount::from_sat(50_000 - 30_000) - plan.fee);
+    }
+
+    #[test]
+    fn test_select_coins_insufficient_funds() {
+        let secp = Secp256k1::new();
+        let account_xpub = Xpub::from_priv(&secp, &account_key());
+        let change_script = utxo(&account_xpub, 99, 0).txout.script_pubkey;
+
+        let result = select_coins(
+            vec![utxo(&account_xpub, 0, 10_000)],
+            vec![recipient(10_000)],
+            change_script,
+            FeeRate::from_sat_per_vb(1).unwrap(),
+        );
+
+        assert!(matches!(
+            result,
+            Err(ArkiveError::InsufficientFunds {
+                available: 10_000,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_sign_psbt() {
+        let secp = Secp256k1::new();
+        let account_key = account_key();
+        let account_xpub = Xpub::from_priv(&secp, &account_key);
+        let change_script = utxo(&account_xpub, 99, 0).txout.script_pubkey;
+
+        let plan = select_coins(
+            vec![utxo(&account_xpub, 0, 50_000)],
+            vec![recipient(20_000)],
+            change_script,
+            FeeRate::from_sat_per_vb(1).unwrap(),
+        )
+        .unwrap();
+
+        let psbt = build_psbt(&plan, &account_xpub).unwrap();
+        let tx = sign_psbt(psbt, &account_key).unwrap();
+
+        assert_eq!(tx.input[0].witness.len(), 2);
+        assert!(tx.weight() <= plan.weight());
+    }
+
+    #[test]
+    fn test_select_coins_with_required_inputs() {
+        let secp = Secp256k1::new();
+        let account_xpub = Xpub::from_priv(&secp, &account_key());
+        let change_script = utxo(&account_xpub, 99, 0).txout.script_pubkey;
+        let fee_rate = FeeRate::from_sat_per_vb(10).unwrap();
+
+        let plan = select_coins_with(
+            vec![utxo(&account_xpub, 0, 20_000)],
+            vec![utxo(&account_xpub, 1, 50_000)],
+            vec![recipient(19_000)],
+            change_script,
+            fee_rate,
+        )
+        .unwrap();
+
+        // The required coin stays first and the extra coin covers the fee
+        assert_eq!(plan.inputs.len(), 2);
+        assert_eq!(plan.inputs[0].outpoint, utxo(&account_xpub, 0, 0).outpoint);
+        assert!(plan.change().is_some());
+    }
+
+    #[test]
+    fn test_sweep_pays_extra_fee() {
+        let secp = Secp256k1::new();
+        let account_xpub = Xpub::from_priv(&secp, &account_key());
+        let destination = utxo(&account_xpub, 99, 0).txout.script_pubkey;
+        let fee_rate = FeeRate::from_sat_per_vb(5).unwrap();
+        let extra_fee = Amount::from_sat(700);
+
+        let plan = sweep(
+            vec![
+                utxo(&account_xpub, 0, 10_000),
+                utxo(&account_xpub, 1, 20_000),
+            ],
+            destination,
+            fee_rate,
+            extra_fee,
+        )
+        .unwrap();
+
+        assert_eq!(plan.outputs.len(), 1);
+        assert_eq!(plan.fee, fee_rate.fee_vb(plan.vsize()).unwrap() + extra_fee);
+        assert_eq!(plan.outputs[0].value, Amount::from_sat(30_000) - plan.fee);
+    }
+
+    #[test]
+    fn test_sweep_skips_uneconomical_inputs() {
+        let secp = Secp256k1::new();
+        let account_xpub = Xpub::from_priv(&secp, &account_key());
+        let destination = utxo(&account_xpub, 99, 0).txout.script_pubkey;
+        let fee_rate = FeeRate::from_sat_per_vb(10).unwrap();
+
+        // A P2WPKH input weighs 273 WU, about 683 sats at 10 sat/vB
+        let inputs = vec![
+            utxo(&account_xpub, 0, 20_000),
+            utxo(&account_xpub, 1, 600),
+            utxo(&account_xpub, 2, 680),
+            utxo(&account_xpub, 3, 700),
+        ];
+        let economical: Vec<WalletUtxo> = inputs
+            .into_iter()
+            .filter(|utxo| is_economical(utxo, fee_rate))
+            .collect();
+        let outpoints: Vec<OutPoint> = economical.iter().map(|u| u.outpoint).collect();
+        assert_eq!(
+            outpoints,
+            vec![
+                utxo(&account_xpub, 0, 0).outpoint,
+                utxo(&account_xpub, 3, 0).outpoint
+            ]
+        );
+
+        let plan = sweep(economical, destination, fee_rate, Amount::ZERO).unwrap();
+        assert_eq!(plan.inputs.len(), 2);
+        assert_eq!(plan.outputs[0].value, Amount::from_sat(20_700) - plan.fee);
+
+        // Nothing worth spending at a high enough rate
+        let fee_rate = FeeRate::from_sat_per_vb(500).unwrap();
+        assert!(!is_economical(&utxo(&account_xpub, 0, 20_000), fee_rate));
+    }
+
+    #[test]
+    fn test_fund_child_spends_anchor() {
+        let secp = Secp256k1::new();
+        let account_key = account_key();
+        let account_xpub = Xpub::from_priv(&secp, &account_key);
+        let destination = utxo(&account_xpub, 99, 0).txout.script_pubkey;
+        let fee_rate = FeeRate::from_sat_per_vb(4).unwrap();
+        let extra_fee = Amount::from_sat(2_000);
+
+        // The unconfirmed coin is the largest but can't be used
+        let mut unconfirmed = utxo(&account_xpub, 2, 100_000);
+        unconfirmed.confirmation_height = None;
+        let plan = fund_child(
+            vec![
+                utxo(&account_xpub, 0, 1_000),
+                utxo(&account_xpub, 1, 30_000),
+                unconfirmed,
+            ],
+            destination,
+            fee_rate,
+            extra_fee,
+        )
+        .unwrap();
+        assert_eq!(plan.inputs.len(), 1);
+        assert_eq!(plan.inputs[0].txout.value, Amount::from_sat(30_000));
+        assert_eq!(plan.change_index, Some(0));
+
+        let anchor = TxOut {
+            value: Amount::ZERO,
+            script_pubkey: ScriptBuf::from_bytes(ANCHOR_SCRIPT.to_vec()),
+        };
+        assert!(is_anchor(&anchor.script_pubkey));
+        let anchor_outpoint = OutPoint::new(Txid::from_byte_array([9; 32]), 1);
+
+        let mut psbt = build_psbt(&plan, &account_xpub).unwrap();
+        add_anchor_input(&mut psbt, anchor_outpoint, anchor);
+        let tx = sign_psbt(psbt, &account_key).unwrap();
+
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.input[0].witness.len(), 2);
+        assert_eq!(tx.input[1].previous_output, anchor_outpoint);
+        assert!(tx.input[1].witness.is_empty());
+        assert!(tx.weight() <= plan.weight() + Weight::from_wu(ANCHOR_INPUT_WEIGHT));
+
+        // Nothing confirmed is large enough
+        let result = fund_child(
+            vec![utxo(&account_xpub, 0, 1_000)],
+            utxo(&account_xpub, 99, 0).txout.script_pubkey,
+            fee_rate,
+            extra_fee,
+        );
+        assert!(matches!(
+            result,
+            Err(ArkiveError::InsufficientFunds {
+                available: 1_000,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,567 @@
+use super::{ChainBackend, ChainTx, TxStatus};
+use crate::error::{ArkiveError, Result};
+use crate::wallet::{BitcoindConfig, RpcAuth};
+
+use async_trait::async_trait;
+use bitcoin::consensus::encode::{deserialize_hex, serialize_hex};
+use bitcoin::{Amount, BlockHash, OutPoint, Script, ScriptBuf, Transaction, TxOut, Txid};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, MutexGuard};
+
+/// RPC_INVALID_ADDRESS_OR_KEY, returned for unknown transactions and blocks
+const RPC_NOT_FOUND: i64 = -5;
+
+/// How long the indexed mempool is served before the node is asked again
+const MEMPOOL_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Confirmation targets to ask `estimatesmartfee` for
+const FEE_TARGETS: [u16; 4] = [1, 3, 6, 144];
+
+/// Chain backend on a Bitcoin Core node's JSON-RPC interface.
+///
+/// Bitcoin Core has no address index, so the backend indexes every block of
+/// the best chain in memory on first use and follows the tip from there.
+/// That suits regtest and small test chains, not mainnet. Requires Bitcoin
+/// Core 25 or newer for the spent outputs in `getblock` and
+/// `getrawtransaction`.
+pub struct BitcoindBackend {
+    http: reqwest::Client,
+    url: String,
+    auth: RpcAuth,
+    next_id: AtomicU64,
+    index: Mutex<ChainIndex>,
+}
+
+/// Transactions of the indexed blocks and the mempool, by the scripts they
+/// pay to or spend from
+#[derive(Default)]
+struct ChainIndex {
+    /// Hash and transactions of every indexed block, by height
+    blocks: Vec<(BlockHash, Vec<Txid>)>,
+    mempool: HashSet<Txid>,
+    /// When the mempool was last read, `None` to read it on the next
+    /// request
+    mempool_read_at: Option<Instant>,
+    txs: HashMap<Txid, ChainTx>,
+    by_script: HashMap<ScriptBuf, Vec<Txid>>,
+    spends: HashMap<OutPoint, Txid>,
+}
+
+impl ChainIndex {
+    /// Whether the index still reflects a node whose best block is
+    /// `best_hash`
+    fn is_fresh(&self, best_hash: BlockHash, now: Instant) -> bool {
+        let same_tip = self.blocks.last().map(|(hash, _)| *hash) == Some(best_hash);
+        let mempool_recent = self
+            .mempool_read_at
+            .is_some_and(|read_at| now.duration_since(read_at) < MEMPOOL_REFRESH_INTERVAL);
+        same_tip && mempool_recent
+    }
+
+    fn scripts(tx: &ChainTx) -> HashSet<ScriptBuf> {
+        tx.tx
+            .output
+            .iter()
+            .map(|output| output.script_pubkey.clone())
+            .chain(
+                tx.prevouts
+                    .iter()
+                    .flatten()
+                    .map(|prevout| prevout.script_pubkey.clone()),
+            )
+            .collect()
+    }
+
+    fn insert(&mut self, tx: ChainTx) {
+        self.remove(&tx.txid);
+
+        for script in Self::scripts(&tx) {
+            self.by_script.entry(script).or_default().push(tx.txid);
+        }
+        if !tx.tx.is_coinbase() {
+            for input in &tx.tx.input {
+                self.spends.insert(input.previous_output, tx.txid);
+            }
+        }
+        self.txs.insert(tx.txid, tx);
+    }
+
+    fn remove(&mut self, txid: &Txid) {
+        let Some(tx) = self.txs.remove(txid) else {
+            return;
+        };
+
+        for script in Self::scripts(&tx) {
+            if let Some(txids) = self.by_script.get_mut(&script) {
+                txids.retain(|t| t != txid);
+            }
+        }
+        for input in &tx.tx.input {
+            if self.spends.get(&input.previous_output) == Some(txid) {
+                self.spends.remove(&input.previous_output);
+            }
+        }
+    }
+
+    fn disconnect_tip(&mut self) {
+        if let Some((_, txids)) = self.blocks.pop() {
+            for txid in txids {
+                self.remove(&txid);
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    result: Option<Value>,
+    error: Option<RpcError>,
+}
+
+#[derive(Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct RpcBlock {
+    time: u64,
+    tx: Vec<RpcTx>,
+}
+
+#[derive(Deserialize)]
+struct RpcTx {
+    hex: String,
+    vin: Vec<RpcInput>,
+}
+
+#[derive(Deserialize)]
+struct RpcInput {
+    prevout: Option<RpcPrevout>,
+}
+
+#[derive(Deserialize)]
+struct RpcPrevout {
+    value: f64,
+    #[serde(rename = "scriptPubKey")]
+    script_pubkey: RpcScript,
+}
+
+#[derive(Deserialize)]
+struct RpcScript {
+    hex: String,
+}
+
+#[derive(Deserialize)]
+struct RpcFeeEstimate {
+    feerate: Option<f64>,
+}
+
+impl BitcoindBackend {
+    pub fn new(config: &BitcoindConfig) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .build()
+            .map_err(|e| ArkiveError::rpc(format!("Failed to create RPC client: {}", e)))?;
+
+        Ok(Self {
+            http,
+            url: config.url.clone(),
+            auth: config.auth.clone(),
+            next_id: AtomicU64::new(0),
+            index: Mutex::new(ChainIndex::default()),
+        })
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<RpcResponse> {
+        let body = json!({
+            "jsonrpc": "1.0",
+            "id": self.next_id.fetch_add(1, Ordering::Relaxed),
+            "method": method,
+            "params": params,
+        });
+
+        let request = self.http.post(&self.url).json(&body);
+        let request = match &self.auth {
+            RpcAuth::None => request,
+            RpcAuth::UserPass { username, password } => {
+                request.basic_auth(username, Some(password))
+            }
+            RpcAuth::CookieFile(path) => {
+                let cookie = tokio::fs::read_to_string(path).await.map_err(|e| {
+                    ArkiveError::config(format!(
+                        "Failed to read RPC cookie {}: {}",
+                        path.display(),
+                        e
+                    ))
+                })?;
+                let (username, password) = cookie.trim().split_once(':').ok_or_else(|| {
+                    ArkiveError::config(format!("Invalid RPC cookie {}", path.display()))
+                })?;
+                request.basic_auth(username, Some(password))
+            }
+        };
+
+        let response = request.send().await.map_err(|e| {
+            ArkiveError::network_connection(format!(
+                "Failed to reach bitcoind at {}: {}",
+                self.url, e
+            ))
+        })?;
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            return Err(ArkiveError::config("bitcoind rejected the RPC credentials"));
+        }
+
+        // RPC errors come with an error status but still carry a JSON body
+        response
+            .json()
+            .await
+            .map_err(|e| ArkiveError::rpc(format!("Invalid response to {}: {}", method, e)))
+    }
+
+    async fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T> {
+        self.call_optional(method, params)
+            .await?
+            .ok_or_else(|| ArkiveError::rpc(format!("{} failed: not found", method)))
+    }
+
+    /// Like [`Self::call`], but `None` when the node doesn't know the
+    /// requested transaction or block
+    async fn call_optional<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Result<Option<T>> {
+        let response = self.request(method, params).await?;
+        match response.error {
+            Some(error) if error.code == RPC_NOT_FOUND => Ok(None),
+            Some(error) => Err(ArkiveError::rpc(format!(
+                "{} failed: {} (code {})",
+                method, error.message, error.code
+            ))),
+            None => serde_json::from_value(response.result.unwrap_or(Value::Null))
+                .map(Some)
+                .map_err(|e| ArkiveError::rpc(format!("Unexpected response to {}: {}", method, e))),
+        }
+    }
+
+    async fn rpc_block_hash(&self, height: u32) -> Result<BlockHash> {
+        let hash: String = self.call("getblockhash", json!([height])).await?;
+        parse_block_hash(&hash)
+    }
+
+    /// Bring the index up to the node's best chain and mempool. Nothing
+    /// more than the best block hash is fetched while the tip is unchanged
+    /// and the mempool was read within [`MEMPOOL_REFRESH_INTERVAL`].
+    async fn refresh(&self) -> Result<MutexGuard<'_, ChainIndex>> {
+        let mut index = self.index.lock().await;
+
+        let best_hash: String = self.call("getbestblockhash", json!([])).await?;
+        let best_hash = parse_block_hash(&best_hash)?;
+        if index.is_fresh(best_hash, Instant::now()) {
+            return Ok(index);
+        }
+
+        let tip_height: u32 = self.call("getblockcount", json!([])).await?;
+
+        // Drop blocks that are no longer part of the best chain
+        while let Some(hash) = index.blocks.last().map(|(hash, _)| *hash) {
+            let height = index.blocks.len() as u32 - 1;
+            if height <= tip_height && self.rpc_block_hash(height).await? == hash {
+                break;
+            }
+            tracing::info!("Block {} at height {} was reorganized out", hash, height);
+            index.disconnect_tip();
+        }
+
+        let start = index.blocks.len() as u32;
+        if start == 0 {
+            tracing::info!("Indexing {} blocks from bitcoind", tip_height + 1);
+        }
+        for height in start..=tip_height {
+            let hash = self.rpc_block_hash(height).await?;
+            let block: RpcBlock = self.call("getblock", json!([hash.to_string(), 3])).await?;
+            let status = TxStatus {
+                block_height: Some(height),
+                block_hash: Some(hash),
+                block_time: Some(block.time),
+            };
+
+            let mut txids = Vec::with_capacity(block.tx.len());
+            for tx in block.tx {
+                let tx = to_chain_tx(tx, status)?;
+                txids.push(tx.txid);
+                index.insert(tx);
+            }
+            index.blocks.push((hash, txids));
+        }
+
+        let mempool = self
+            .call::<Vec<String>>("getrawmempool", json!([]))
+            .await?
+            .iter()
+            .map(|txid| parse_txid(txid))
+            .collect::<Result<HashSet<Txid>>>()?;
+
+        // Forget transactions that left the mempool without confirming
+        let evicted: Vec<Txid> = index.mempool.difference(&mempool).copied().collect();
+        for txid in evicted {
+            if !index
+                .txs
+                .get(&txid)
+                .is_some_and(|tx| tx.status.is_confirmed())
+            {
+                index.remove(&txid);
+            }
+        }
+        for txid in &mempool {
+            if index.txs.contains_key(txid) {
+                continue;
+            }
+            // The transaction may have left the mempool since it was listed
+            let tx: Option<RpcTx> = self
+                .call_optional("getrawtransaction", json!([txid.to_string(), 2]))
+                .await?;
+            if let Some(tx) = tx {
+                index.insert(to_chain_tx(tx, TxStatus::default())?);
+            }
+        }
+        index.mempool = mempool;
+        index.mempool_read_at = Some(Instant::now());
+
+        Ok(index)
+    }
+}
+
+fn parse_block_hash(hash: &str) -> Result<BlockHash> {
+    BlockHash::from_str(hash)
+        .map_err(|e| ArkiveError::rpc(format!("Invalid block hash {}: {}", hash, e)))
+}
+
+fn parse_txid(txid: &str) -> Result<Txid> {
+    Txid::from_str(txid).map_err(|e| ArkiveError::rpc(format!("Invalid txid {}: {}", txid, e)))
+}
+
+fn to_chain_tx(tx: RpcTx, status: TxStatus) -> Result<ChainTx> {
+    let transaction: Transaction = deserialize_hex(&tx.hex)
+        .map_err(|e| ArkiveError::rpc(format!("Invalid transaction: {}", e)))?;
+
+    let mut prevouts = Vec::with_capacity(tx.vin.len());
+    for input in tx.vin {
+        let prevout = match input.prevout {
+            Some(prevout) => Some(TxOut {
+                value: Amount::from_btc(prevout.value)
+                    .map_err(|e| ArkiveError::rpc(format!("Invalid prevout value: {}", e)))?,
+                script_pubkey: ScriptBuf::from_hex(&prevout.script_pubkey.hex)
+                    .map_err(|e| ArkiveError::rpc(format!("Invalid prevout script: {}", e)))?,
+            }),
+            None => None,
+        };
+        prevouts.push(prevout);
+    }
+
+    Ok(ChainTx {
+        txid: transaction.compute_txid(),
+        tx: transaction,
+        prevouts,
+        status,
+    })
+}
+
+#[async_trait]
+impl ChainBackend for BitcoindBackend {
+    async fn tip_height(&self) -> Result<u32> {
+        self.call("getblockcount", json!([])).await
+    }
+
+    async fn block_hash(&self, height: u32) -> Result<Option<BlockHash>> {
+        let index = self.refresh().await?;
+        Ok(index.blocks.get(height as usize).map(|(hash, _)| *hash))
+    }
+
+    async fn get_tx(&self, txid: &Txid) -> Result<Option<Transaction>> {
+        let index = self.refresh().await?;
+        Ok(index.txs.get(txid).map(|tx| tx.tx.clone()))
+    }
+
+    async fn get_tx_status(&self, txid: &Txid) -> Result<TxStatus> {
+        let index = self.refresh().await?;
+        Ok(index.txs.get(txid).map(|tx| tx.status).unwrap_or_default())
+    }
+
+    async fn get_output_spend(&self, outpoint: &OutPoint) -> Result<Option<Txid>> {
+        let index = self.refresh().await?;
+        Ok(index.spends.get(outpoint).copied())
+    }
+
+    async fn script_history(
+        &self,
+        script: &Script,
+        since_height: Option<u32>,
+    ) -> Result<Vec<ChainTx>> {
+        let index = self.refresh().await?;
+        let Some(txids) = index.by_script.get(script) else {
+            return Ok(Vec::new());
+        };
+
+        Ok(txids
+            .iter()
+            .filter_map(|txid| index.txs.get(txid))
+            .filter(|tx| match (since_height, tx.status.block_height) {
+                (Some(since), Some(height)) => height > since,
+                _ => true,
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn fee_estimates(&self) -> Result<HashMap<u16, f64>> {
+        let mut estimates = HashMap::new();
+        for target in FEE_TARGETS {
+            // Without enough fee data the node reports errors and no rate
+            let estimate: RpcFeeEstimate = self.call("estimatesmartfee", json!([target])).await?;
+            if let Some(btc_per_kvb) = estimate.feerate {
+                estimates.insert(target, btc_per_kvb * 100_000.0);
+            }
+        }
+        Ok(estimates)
+    }
+
+    async fn broadcast(&self, tx: &Transaction) -> Result<()> {
+        let _: String = self
+            .call("sendrawtransaction", json!([serialize_hex(tx)]))
+            .await?;
+        self.index.lock().await.mempool_read_at = None;
+        Ok(())
+    }
+
+    async fn broadcast_package(&self, txs: &[Transaction]) -> Result<()> {
+        let txs: Vec<String> = txs.iter().map(serialize_hex).collect();
+        let result: Value = self.call("submitpackage", json!([txs])).await?;
+
+        self.index.lock().await.mempool_read_at = None;
+        match result.get("package_msg").and_then(Value::as_str) {
+            Some("success") => Ok(()),
+            message => Err(ArkiveError::rpc(format!(
+                "submitpackage failed: {}",
+                message.map_or_else(|| result.to_string(), str::to_string)
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Sequence, TxIn, WPubkeyHash, Witness};
+
+    fn script(byte: u8) -> ScriptBuf {
+        ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([byte; 20]))
+    }
+
+    /// Transaction spending `previous_output`, from `from`, to `to`
+    fn chain_tx(previous_output: OutPoint, from: u8, to: u8, height: Option<u32>) -> ChainTx {
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: script(to),
+            }],
+        };
+
+        ChainTx {
+            txid: tx.compute_txid(),
+            tx,
+            prevouts: vec![Some(TxOut {
+                value: Amount::from_sat(2_000),
+                script_pubkey: script(from),
+            })],
+            status: TxStatus {
+                block_height: height,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_chain_index() {
+        let mut index = ChainIndex::default();
+        let funding = OutPoint::new(Txid::from_byte_array([1; 32]), 0);
+
+        let first = chain_tx(funding, 1, 2, Some(0));
+        let second = chain_tx(OutPoint::new(first.txid, 0), 2, 3, Some(1));
+        index.insert(first.clone());
+        index
+            .blocks
+            .push((BlockHash::from_byte_array([0; 32]), vec![first.txid]));
+        index.insert(second.clone());
+        index
+            .blocks
+            .push((BlockHash::from_byte_array([1; 32]), vec![second.txid]));
+
+        // Indexed by the scripts paid to and spent from
+        assert_eq!(index.by_script[&script(1)], vec![first.txid]);
+        assert_eq!(index.by_script[&script(2)], vec![first.txid, second.txid]);
+        assert_eq!(index.spends[&funding], first.txid);
+        assert_eq!(index.spends[&OutPoint::new(first.txid, 0)], second.txid);
+
+        // Inserting again replaces the entry instead of duplicating it
+        index.insert(second.clone());
+        assert_eq!(index.by_script[&script(3)], vec![second.txid]);
+
+        // Disconnecting the tip forgets its transactions only
+        index.disconnect_tip();
+        assert_eq!(index.blocks.len(), 1);
+        assert!(!index.txs.contains_key(&second.txid));
+        assert!(index.by_script[&script(3)].is_empty());
+        assert_eq!(index.by_script[&script(2)], vec![first.txid]);
+        assert!(!index.spends.contains_key(&OutPoint::new(first.txid, 0)));
+        assert_eq!(index.spends[&funding], first.txid);
+
+        // A conflicting spend keeps the spend entry of the remaining one
+        let replacement = chain_tx(OutPoint::new(first.txid, 0), 2, 4, None);
+        index.insert(second.clone());
+        index.insert(replacement.clone());
+        index.remove(&second.txid);
+        assert_eq!(
+            index.spends[&OutPoint::new(first.txid, 0)],
+            replacement.txid
+        );
+    }
+
+    #[test]
+    fn test_chain_index_freshness() {
+        let now = Instant::now();
+        let tip = BlockHash::from_byte_array([7; 32]);
+        let mut index = ChainIndex::default();
+        assert!(!index.is_fresh(tip, now));
+
+        index.blocks.push((tip, Vec::new()));
+        index.mempool_read_at = Some(now);
+        assert!(index.is_fresh(tip, now + Duration::from_secs(1)));
+
+        // A new block or a stale mempool means refreshing
+        assert!(!index.is_fresh(BlockHash::from_byte_array([8; 32]), now));
+        assert!(!index.is_fresh(tip, now + MEMPOOL_REFRESH_INTERVAL));
+
+        // Broadcasting clears the mempool timestamp
+        index.mempool_read_at = None;
+        assert!(!index.is_fresh(tip, now));
+    }
+}
@@ -0,0 +1,448 @@
+use super::{ChainBackend, ChainTx, TxStatus};
+use crate::bitcoin::tx_builder;
+use crate::error::{ArkiveError, Result};
+use crate::wallet::ElectrumConfig;
+
+use async_trait::async_trait;
+use bitcoin::{BlockHash, OutPoint, Script, ScriptBuf, Transaction, Txid};
+use electrum_client::{Client, ConfigBuilder, ElectrumApi};
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+
+/// Confirmation targets to ask `blockchain.estimatefee` for
+const FEE_TARGETS: [u16; 4] = [1, 3, 6, 144];
+
+/// Seconds before an Electrum request is abandoned
+const ELECTRUM_TIMEOUT: u8 = 30;
+
+/// Chain backend on the Electrum protocol, as served by electrs and Fulcrum.
+///
+/// The connection is opened on first use. Electrum only indexes
+/// transactions by script, so lookups by txid go through the history of one
+/// of the transaction's output scripts.
+pub struct ElectrumBackend {
+    config: ElectrumConfig,
+    client: OnceCell<Arc<Client>>,
+    /// Transactions fetched so far, which never change once known
+    txs: Mutex<HashMap<Txid, Transaction>>,
+}
+
+impl ElectrumBackend {
+    pub fn new(config: &ElectrumConfig) -> Self {
+        Self {
+            config: config.clone(),
+            client: OnceCell::new(),
+            txs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Run a blocking Electrum request on the blocking thread pool
+    async fn request<T, F>(&self, what: &'static str, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Client) -> std::result::Result<T, electrum_client::Error> + Send + 'static,
+    {
+        let client = self.client().await?;
+        tokio::task::spawn_blocking(move || f(&client))
+            .await
+            .map_err(|e| ArkiveError::internal(format!("Electrum request panicked: {}", e)))?
+            .map_err(|e| ArkiveError::electrum(format!("Failed to {}: {}", what, e)))
+    }
+
+    async fn client(&self) -> Result<Arc<Client>> {
+        self.client
+            .get_or_try_init(|| async {
+                let url = self.config.url.clone();
+                let config = ConfigBuilder::new()
+                    .validate_domain(self.config.validate_domain)
+                    .timeout(Some(ELECTRUM_TIMEOUT))
+                    .build();
+
+                let client = tokio::task::spawn_blocking(move || Client::from_config(&url, config))
+                    .await
+                    .map_err(|e| {
+                        ArkiveError::internal(format!("Electrum connect panicked: {}", e))
+                    })?
+                    .map_err(|e| {
+                        ArkiveError::network_connection(format!(
+                            "Failed to connect to Electrum server {}: {}",
+                            self.config.url, e
+                        ))
+                    })?;
+                Ok(Arc::new(client))
+            })
+            .await
+            .cloned()
+    }
+
+    /// Fetch transactions, serving known ones from the cache
+    async fn get_txs(&self, txids: Vec<Txid>) -> Result<HashMap<Txid, Transaction>> {
+        let missing: Vec<Txid> = {
+            let cache = self.txs.lock();
+            txids
+                .iter()
+                .filter(|txid| !cache.contains_key(txid))
+                .copied()
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect()
+        };
+
+        if !missing.is_empty() {
+            let fetched = self
+                .request("get transactions", move |client| {
+                    client.batch_transaction_get(&missing)
+                })
+                .await?;
+            let mut cache = self.txs.lock();
+            for tx in fetched {
+                cache.insert(tx.compute_txid(), tx);
+            }
+        }
+
+        let cache = self.txs.lock();
+        Ok(txids
+            .into_iter()
+            .filter_map(|txid| cache.get(&txid).map(|tx| (txid, tx.clone())))
+            .collect())
+    }
+
+    /// Block hash and time of each confirmed height
+    async fn statuses(&self, heights: HashSet<u32>) -> Result<HashMap<u32, TxStatus>> {
+        let heights: Vec<u32> = heights.into_iter().collect();
+        if heights.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let query = heights.clone();
+        let headers = self
+            .request("get block headers", move |client| {
+                client.batch_block_header(query)
+            })
+            .await?;
+
+        Ok(heights
+            .into_iter()
+            .zip(headers)
+            .map(|(height, header)| {
+                let status = TxStatus {
+                    block_height: Some(height),
+                    block_hash: Some(header.block_hash()),
+                    block_time: Some(header.time as u64),
+                };
+                (height, status)
+            })
+            .collect())
+    }
+
+    /// Height `txid` has in a script's history, `None` if it isn't part of
+    /// it. Zero and below mean unconfirmed.
+    async fn history_height(&self, script: ScriptBuf, txid: Txid) -> Result<Option<i32>> {
+        let history = self
+            .request("get script history", move |client| {
+                client.script_get_history(&script)
+            })
+            .await?;
+
+        Ok(history
+            .into_iter()
+            .find(|entry| entry.tx_hash == txid)
+            .map(|entry| entry.height))
+    }
+}
+
+/// Whether a protocol error is the server's answer for a transaction it
+/// doesn't know, as opposed to a failure to look it up
+fn is_unknown_tx(error: &serde_json::Value) -> bool {
+    let message = error.to_string().to_lowercase();
+    [
+        "no such mempool or blockchain transaction",
+        "missing transaction",
+        "transaction not found",
+    ]
+    .iter()
+    .any(|marker| message.contains(marker))
+}
+
+/// Output scripts whose history can tell where `tx` stands, in order.
+/// Unspendable outputs aren't indexed, and anchor scripts are shared by so
+/// many transactions that their history is useless.
+fn history_scripts(tx: &Transaction) -> impl Iterator<Item = &ScriptBuf> {
+    tx.output
+        .iter()
+        .map(|output| &output.script_pubkey)
+        .filter(|script| {
+            !script.is_empty() && !script.is_op_return() && !tx_builder::is_anchor(script)
+        })
+}
+
+#[async_trait]
+impl ChainBackend for ElectrumBackend {
+    /// Subscribes to headers on every call, since a subscription doesn't
+    /// survive the client reconnecting, and drops queued notifications
+    async fn tip_height(&self) -> Result<u32> {
+        let header = self
+            .request("follow block headers", |client| {
+                let header = client.block_headers_subscribe()?;
+                while client.block_headers_pop()?.is_some() {}
+                Ok(header)
+            })
+            .await?;
+
+        Ok(header.height as u32)
+    }
+
+    async fn block_hash(&self, height: u32) -> Result<Option<BlockHash>> {
+        if height > self.tip_height().await? {
+            return Ok(None);
+        }
+
+        let header = self
+            .request("get block header", move |client| {
+                client.block_header(height as usize)
+            })
+            .await?;
+        Ok(Some(header.block_hash()))
+    }
+
+    async fn get_tx(&self, txid: &Txid) -> Result<Option<Transaction>> {
+        if let Some(tx) = self.txs.lock().get(txid) {
+            return Ok(Some(tx.clone()));
+        }
+
+        // Electrum servers report unknown transactions as a protocol error
+        let query = *txid;
+        let tx = self
+            .request("get transaction", move |client| {
+                match client.transaction_get(&query) {
+                    Ok(tx) => Ok(Some(tx)),
+                    Err(electrum_client::Error::Protocol(error)) if is_unknown_tx(&error) => {
+                        Ok(None)
+                    }
+                    Err(e) => Err(e),
+                }
+            })
+            .await?;
+
+        if let Some(tx) = &tx {
+            self.txs.lock().insert(*txid, tx.clone());
+        }
+        Ok(tx)
+    }
+
+    async fn get_tx_status(&self, txid: &Txid) -> Result<TxStatus> {
+        let Some(tx) = self.get_tx(txid).await? else {
+            return Ok(TxStatus::default());
+        };
+
+        for script in history_scripts(&tx) {
+            match self.history_height(script.clone(), *txid).await? {
+                Some(height) if height > 0 => {
+                    let height = height as u32;
+                    return Ok(self
+                        .statuses(HashSet::from([height]))
+                        .await?
+                        .remove(&height)
+                        .unwrap_or_default());
+                }
+                Some(_) => return Ok(TxStatus::default()),
+                // Not indexed under this script, try the next one
+                None => continue,
+            }
+        }
+
+        Ok(TxStatus::default())
+    }
+
+    async fn get_output_spend(&self, outpoint: &OutPoint) -> Result<Option<Txid>> {
+        let Some(tx) = self.get_tx(&outpoint.txid).await? else {
+            return Ok(None);
+        };
+        let Some(output) = tx.output.get(outpoint.vout as usize) else {
+            return Ok(None);
+        };
+
+        // A spend of the output shows up in the history of its script
+        let script = output.script_pubkey.clone();
+        let history = self
+            .request("get script history", move |client| {
+                client.script_get_history(&script)
+            })
+            .await?;
+        let txids = history
+            .into_iter()
+            .map(|entry| entry.tx_hash)
+            .filter(|txid| *txid != outpoint.txid)
+            .collect();
+
+        Ok(self
+            .get_txs(txids)
+            .await?
+            .into_iter()
+            .find(|(_, tx)| {
+                tx.input
+                    .iter()
+                    .any(|input| input.previous_output == *outpoint)
+            })
+            .map(|(txid, _)| txid))
+    }
+
+    async fn script_history(
+        &self,
+        script: &Script,
+        since_height: Option<u32>,
+    ) -> Result<Vec<ChainTx>> {
+        let query = script.to_owned();
+        let history = self
+            .request("get script history", move |client| {
+                client.script_get_history(&query)
+            })
+            .await?;
+
+        // Heights of zero and below mark mempool transactions
+        let entries: Vec<(Txid, Option<u32>)> = history
+            .into_iter()
+            .map(|entry| {
+                let height = u32::try_from(entry.height).ok().filter(|h| *h > 0);
+                (entry.tx_hash, height)
+            })
+            .filter(|(_, height)| match (since_height, height) {
+                (Some(since), Some(height)) => *height > since,
+                _ => true,
+            })
+            .collect();
+        if entries.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let txs = self
+            .get_txs(entries.iter().map(|(txid, _)| *txid).collect())
+            .await?;
+        let prev_txs = self
+            .get_txs(
+                txs.values()
+                    .filter(|tx| !tx.is_coinbase())
+                    .flat_map(|tx| tx.input.iter().map(|input| input.previous_output.txid))
+                    .collect(),
+            )
+            .await?;
+        let statuses = self
+            .statuses(entries.iter().filter_map(|(_, height)| *height).collect())
+            .await?;
+
+        Ok(entries
+            .into_iter()
+            .filter_map(|(txid, height)| {
+                let tx = txs.get(&txid)?.clone();
+                let prevouts = tx
+                    .input
+                    .iter()
+                    .map(|input| {
+                        prev_txs
+                            .get(&input.previous_output.txid)
+                            .and_then(|prev| prev.output.get(input.previous_output.vout as usize))
+                            .cloned()
+                    })
+                    .collect();
+                let status = height
+                    .and_then(|height| statuses.get(&height).copied())
+                    .unwrap_or_default();
+
+                Some(ChainTx {
+                    txid,
+                    tx,
+                    prevouts,
+                    status,
+                })
+            })
+            .collect())
+    }
+
+    async fn fee_estimates(&self) -> Result<HashMap<u16, f64>> {
+        let estimates = self
+            .request("get fee estimates", |client| {
+                FEE_TARGETS
+                    .iter()
+                    .map(|target| client.estimate_fee(*target as usize))
+                    .collect::<std::result::Result<Vec<f64>, _>>()
+            })
+            .await?;
+
+        // Rates come in BTC/kB, negative when the server has no estimate
+        Ok(FEE_TARGETS
+            .into_iter()
+            .zip(estimates)
+            .filter(|(_, btc_per_kb)| *btc_per_kb > 0.0)
+            .map(|(target, btc_per_kb)| (target, btc_per_kb * 100_000.0))
+            .collect())
+    }
+
+    async fn broadcast(&self, tx: &Transaction) -> Result<()> {
+        let tx = tx.clone();
+        self.request("broadcast", move |client| client.transaction_broadcast(&tx))
+            .await?;
+        Ok(())
+    }
+
+    async fn broadcast_package(&self, _txs: &[Transaction]) -> Result<()> {
+        Err(ArkiveError::electrum(
+            "Electrum servers don't relay transaction packages, use an Esplora or bitcoind backend",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Amount, TxOut, WPubkeyHash};
+    use serde_json::json;
+
+    #[test]
+    fn test_is_unknown_tx() {
+        assert!(is_unknown_tx(&json!({
+            "code": 2,
+            "message": "daemon error: DaemonError({'code': -5, 'message': 'No such mempool or blockchain transaction. Use gettransaction for wallet transactions.'})"
+        })));
+        assert!(is_unknown_tx(&json!("missing transaction")));
+
+        // Other server errors are failures, not a missing transaction
+        assert!(!is_unknown_tx(&json!({
+            "code": -32600,
+            "message": "excessive resource usage"
+        })));
+        assert!(!is_unknown_tx(
+            &json!({"code": 1, "message": "invalid tx hash"})
+        ));
+    }
+
+    #[test]
+    fn test_history_scripts() {
+        let output = |script_pubkey: ScriptBuf| TxOut {
+            value: Amount::from_sat(1_000),
+            script_pubkey,
+        };
+        let payment = ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([1; 20]));
+        let change = ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([2; 20]));
+
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: Vec::new(),
+            output: vec![
+                output(ScriptBuf::from_bytes(vec![0x6a, 0x01, 0x00])),
+                output(ScriptBuf::from_bytes(vec![0x51, 0x02, 0x4e, 0x73])),
+                output(ScriptBuf::new()),
+                output(payment.clone()),
+                output(change.clone()),
+            ],
+        };
+
+        let scripts: Vec<&ScriptBuf> = history_scripts(&tx).collect();
+        assert_eq!(scripts, vec![&payment, &change]);
+    }
+}
@@ -0,0 +1,442 @@
+use super::{ChainBackend, ChainTx, TxStatus};
+use crate::error::{ArkiveError, Result};
+use crate::wallet::EsploraPolicy;
+
+use async_trait::async_trait;
+use bitcoin::consensus::encode::serialize_hex;
+use bitcoin::{BlockHash, OutPoint, Script, Transaction, Txid};
+use esplora_client::AsyncClient;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// Confirmed transactions returned per page by Esplora's address history
+const ESPLORA_PAGE_SIZE: usize = 25;
+
+/// Consecutive failures after which an endpoint is tried last
+const UNHEALTHY_AFTER: u32 = 3;
+
+/// How long an unhealthy endpoint stays at the back of the queue
+const UNHEALTHY_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// Chain backend on the Esplora HTTP API.
+///
+/// Requests go to the first healthy endpoint and fail over to the next one
+/// on network errors, rate limiting and server errors. Every endpoint is
+/// tried before backing off for another round, up to the policy's retry
+/// limit. Rejections such as an invalid broadcast are returned right away.
+pub struct EsploraBackend {
+    endpoints: Vec<Endpoint>,
+    policy: EsploraPolicy,
+}
+
+struct Endpoint {
+    url: String,
+    client: AsyncClient,
+    health: Mutex<EndpointHealth>,
+}
+
+#[derive(Default)]
+struct EndpointHealth {
+    consecutive_failures: u32,
+    unhealthy_until: Option<Instant>,
+    /// Earliest time the next request may be sent
+    next_slot: Option<Instant>,
+}
+
+impl EndpointHealth {
+    fn is_healthy(&self, now: Instant) -> bool {
+        self.unhealthy_until.map_or(true, |until| now >= until)
+    }
+
+    /// Take the next request slot at `interval` after the previous one,
+    /// returning when it starts
+    fn reserve_slot(&mut self, now: Instant, interval: Duration) -> Instant {
+        let slot = self.next_slot.map_or(now, |next| next.max(now));
+        self.next_slot = Some(slot + interval);
+        slot
+    }
+
+    /// Returns whether the endpoint was unhealthy until now
+    fn record_success(&mut self) -> bool {
+        self.consecutive_failures = 0;
+        self.unhealthy_until.take().is_some()
+    }
+
+    /// Returns whether this failure made the endpoint unhealthy. Every
+    /// further failure restarts the cooldown.
+    fn record_failure(&mut self, now: Instant) -> bool {
+        self.consecutive_failures += 1;
+        if self.consecutive_failures < UNHEALTHY_AFTER {
+            return false;
+        }
+        let was_healthy = self.unhealthy_until.is_none();
+        self.unhealthy_until = Some(now + UNHEALTHY_COOLDOWN);
+        was_healthy
+    }
+}
+
+impl Endpoint {
+    fn is_healthy(&self) -> bool {
+        self.health.lock().is_healthy(Instant::now())
+    }
+
+    /// Wait for the endpoint's next request slot under the rate limit
+    async fn throttle(&self, interval: Duration) {
+        let slot = self.health.lock().reserve_slot(Instant::now(), interval);
+        tokio::time::sleep_until(slot.into()).await;
+    }
+
+    fn record_success(&self) {
+        if self.health.lock().record_success() {
+            tracing::info!("Esplora endpoint {} recovered", self.url);
+        }
+    }
+
+    fn record_failure(&self) {
+        let mut health = self.health.lock();
+        if health.record_failure(Instant::now()) {
+            tracing::warn!(
+                "Esplora endpoint {} failed {} times in a row, trying it last",
+                self.url,
+                health.consecutive_failures
+            );
+        }
+    }
+}
+
+/// Indexes of endpoints in the order to try them: healthy ones first, each
+/// group in configured order
+fn failover_order(healthy: &[bool]) -> Vec<usize> {
+    let (first, last): (Vec<usize>, Vec<usize>) = (0..healthy.len()).partition(|&i| healthy[i]);
+    first.into_iter().chain(last).collect()
+}
+
+/// Spacing between requests to one endpoint under the policy's rate limit
+fn request_interval(policy: &EsploraPolicy) -> Duration {
+    Duration::from_secs(1) / policy.max_requests_per_second.max(1)
+}
+
+/// Wait before retry round `round`, doubling from the policy's backoff
+fn retry_backoff(policy: &EsploraPolicy, round: u32) -> Duration {
+    policy.retry_backoff * 2u32.saturating_pow(round.saturating_sub(1))
+}
+
+/// Whether another endpoint, or the same one later, may answer differently
+fn is_transient(error: &esplora_client::Error) -> bool {
+    match error {
+        esplora_client::Error::Reqwest(_) => true,
+        esplora_client::Error::HttpResponse { status, .. } => *status == 429 || *status >= 500,
+        _ => false,
+    }
+}
+
+impl EsploraBackend {
+    pub fn new(urls: &[String], policy: &EsploraPolicy) -> Result<Self> {
+        if urls.is_empty() {
+            return Err(ArkiveError::config("No Esplora endpoints configured"));
+        }
+
+        let endpoints = urls
+            .iter()
+            .map(|url| {
+                // Retries are handled here, across endpoints
+                let client = esplora_client::Builder::new(url)
+                    .timeout(policy.request_timeout.as_secs())
+                    .max_retries(0)
+                    .build_async()
+                    .map_err(|e| {
+                        ArkiveError::esplora(format!("Failed to create esplora client: {}", e))
+                    })?;
+
+                Ok(Endpoint {
+                    url: url.clone(),
+                    client,
+                    health: Mutex::new(EndpointHealth::default()),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            endpoints,
+            policy: policy.clone(),
+        })
+    }
+
+    /// Endpoints in the order to try them, see [`failover_order`]
+    fn endpoints_by_health(&self) -> Vec<&Endpoint> {
+        let healthy: Vec<bool> = self.endpoints.iter().map(Endpoint::is_healthy).collect();
+        failover_order(&healthy)
+            .into_iter()
+            .map(|i| &self.endpoints[i])
+            .collect()
+    }
+
+    async fn request<T, F, Fut>(&self, what: &str, f: F) -> Result<T>
+    where
+        F: Fn(AsyncClient) -> Fut,
+        Fut: Future<Output = std::result::Result<T, esplora_client::Error>>,
+    {
+        let interval = request_interval(&self.policy);
+        let mut last_error = String::new();
+
+        for round in 0..=self.policy.max_retries {
+            if round > 0 {
+                let backoff = retry_backoff(&self.policy, round);
+                tracing::debug!("Retrying to {} in {:?}", what, backoff);
+                tokio::time::sleep(backoff).await;
+            }
+
+            for endpoint in self.endpoints_by_health() {
+                endpoint.throttle(interval).await;
+
+                match f(endpoint.client.clone()).await {
+                    Ok(result) => {
+                        endpoint.record_success();
+                        return Ok(result);
+                    }
+                    Err(e) if is_transient(&e) => {
+                        tracing::warn!(
+                            "Esplora endpoint {} failed to {}: {}",
+                            endpoint.url,
+                            what,
+                            e
+                        );
+                        endpoint.record_failure();
+                        last_error = format!("{}: {}", endpoint.url, e);
+                    }
+                    Err(e) => {
+                        return Err(ArkiveError::esplora(format!("Failed to {}: {}", what, e)));
+                    }
+                }
+            }
+        }
+
+        Err(ArkiveError::esplora(format!(
+            "Failed to {} after {} attempts on every endpoint, last error from {}",
+            what,
+            self.policy.max_retries + 1,
+            last_error
+        )))
+    }
+}
+
+fn to_status(status: &esplora_client::TxStatus) -> TxStatus {
+    TxStatus {
+        block_height: status.block_height.filter(|_| status.confirmed),
+        block_hash: status.block_hash.filter(|_| status.confirmed),
+        block_time: status.block_time.filter(|_| status.confirmed),
+    }
+}
+
+fn to_chain_tx(tx: esplora_client::Tx) -> ChainTx {
+    ChainTx {
+        txid: tx.txid,
+        tx: tx.to_tx(),
+        prevouts: tx.previous_outputs(),
+        status: to_status(&tx.status),
+    }
+}
+
+#[async_trait]
+impl ChainBackend for EsploraBackend {
+    async fn tip_height(&self) -> Result<u32> {
+        self.request("get tip height", |client| async move {
+            client.get_height().await
+        })
+        .await
+    }
+
+    async fn block_hash(&self, height: u32) -> Result<Option<BlockHash>> {
+        self.request("get block hash", |client| async move {
+            match client.get_block_hash(height).await {
+                Ok(hash) => Ok(Some(hash)),
+                Err(esplora_client::Error::HttpResponse { status: 404, .. }) => Ok(None),
+                Err(e) => Err(e),
+            }
+        })
+        .await
+    }
+
+    async fn get_tx(&self, txid: &Txid) -> Result<Option<Transaction>> {
+        self.request("get transaction", |client| async move {
+            client.get_tx(txid).await
+        })
+        .await
+    }
+
+    async fn get_tx_status(&self, txid: &Txid) -> Result<TxStatus> {
+        let status = self
+            .request("get tx status", |client| async move {
+                client.get_tx_status(txid).await
+            })
+            .await?;
+        Ok(to_status(&status))
+    }
+
+    async fn get_output_spend(&self, outpoint: &OutPoint) -> Result<Option<Txid>> {
+        let status = self
+            .request("get output status", |client| async move {
+                client
+                    .get_output_status(&outpoint.txid, outpoint.vout as u64)
+                    .await
+            })
+            .await?;
+        Ok(status.and_then(|s| s.txid))
+    }
+
+    /// Follows Esplora's pagination, which lists unconfirmed transactions
+    /// first and confirmed ones newest first, so paging stops once
+    /// `since_height` is reached
+    async fn script_history(
+        &self,
+        script: &Script,
+        since_height: Option<u32>,
+    ) -> Result<Vec<ChainTx>> {
+        let is_new = |tx: &esplora_client::Tx| match (since_height, tx.status.block_height) {
+            (Some(since), Some(height)) => height > since,
+            _ => true,
+        };
+
+        let mut txs = Vec::new();
+        let mut last_seen = None;
+
+        loop {
+            let page = self
+                .request("get transactions", |client| async move {
+                    client.scripthash_txs(script, last_seen).await
+                })
+                .await?;
+
+            let confirmed: Vec<Txid> = page
+                .iter()
+                .filter(|tx| tx.status.confirmed)
+                .map(|tx| tx.txid)
+                .collect();
+            let reached_synced = page.iter().any(|tx| !is_new(tx));
+            txs.extend(page.into_iter().filter(|tx| is_new(tx)).map(to_chain_tx));
+
+            if confirmed.len() < ESPLORA_PAGE_SIZE || reached_synced {
+                break;
+            }
+            last_seen = confirmed.last().copied();
+        }
+
+        Ok(txs)
+    }
+
+    async fn fee_estimates(&self) -> Result<HashMap<u16, f64>> {
+        self.request("get fee estimates", |client| async move {
+            client.get_fee_estimates().await
+        })
+        .await
+    }
+
+    async fn broadcast(&self, tx: &Transaction) -> Result<()> {
+        self.request(
+            "broadcast",
+            |client| async move { client.broadcast(tx).await },
+        )
+        .await
+    }
+
+    async fn broadcast_package(&self, txs: &[Transaction]) -> Result<()> {
+        let txs: Vec<String> = txs.iter().map(serialize_hex).collect();
+        self.request("broadcast package", |client| {
+            let txs = &txs;
+            async move {
+                let response = client
+                    .client()
+                    .post(format!("{}/txs/package", client.url()))
+                    .json(txs)
+                    .send()
+                    .await
+                    .map_err(esplora_client::Error::Reqwest)?;
+                if !response.status().is_success() {
+                    return Err(esplora_client::Error::HttpResponse {
+                        status: response.status().as_u16(),
+                        message: response.text().await.unwrap_or_default(),
+                    });
+                }
+                Ok(())
+            }
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failover_order() {
+        assert_eq!(failover_order(&[true, true, true]), vec![0, 1, 2]);
+        assert_eq!(
+            failover_order(&[false, true, false, true]),
+            vec![1, 3, 0, 2]
+        );
+        assert_eq!(failover_order(&[false, false]), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_endpoint_health() {
+        let now = Instant::now();
+        let mut health = EndpointHealth::default();
+
+        // Unhealthy only after enough failures in a row
+        for _ in 1..UNHEALTHY_AFTER {
+            assert!(!health.record_failure(now));
+            assert!(health.is_healthy(now));
+        }
+        assert!(health.record_failure(now));
+        assert!(!health.is_healthy(now));
+
+        // Further failures don't report again but extend the cooldown
+        let later = now + UNHEALTHY_COOLDOWN / 2;
+        assert!(!health.record_failure(later));
+        assert!(!health.is_healthy(now + UNHEALTHY_COOLDOWN));
+        assert!(health.is_healthy(later + UNHEALTHY_COOLDOWN));
+
+        // A success clears the failure count
+        assert!(health.record_success());
+        assert!(health.is_healthy(now));
+        assert!(!health.record_success());
+        assert!(!health.record_failure(now));
+        assert!(health.is_healthy(now));
+    }
+
+    #[test]
+    fn test_rate_limit_and_backoff() {
+        let policy = EsploraPolicy {
+            max_requests_per_second: 4,
+            retry_backoff: Duration::from_millis(500),
+            ..Default::default()
+        };
+        let interval = request_interval(&policy);
+        assert_eq!(interval, Duration::from_millis(250));
+
+        // Requests in a burst get evenly spaced slots
+        let now = Instant::now();
+        let mut health = EndpointHealth::default();
+        assert_eq!(health.reserve_slot(now, interval), now);
+        assert_eq!(health.reserve_slot(now, interval), now + interval);
+        assert_eq!(health.reserve_slot(now, interval), now + interval * 2);
+
+        // After a quiet period the next request goes out right away
+        let later = now + Duration::from_secs(5);
+        assert_eq!(health.reserve_slot(later, interval), later);
+
+        // A zero limit is taken as one request per second
+        let zero = EsploraPolicy {
+            max_requests_per_second: 0,
+            ..Default::default()
+        };
+        assert_eq!(request_interval(&zero), Duration::from_secs(1));
+
+        assert_eq!(retry_backoff(&policy, 1), Duration::from_millis(500));
+        assert_eq!(retry_backoff(&policy, 2), Duration::from_secs(1));
+        assert_eq!(retry_backoff(&policy, 3), Duration::from_secs(2));
+    }
+}
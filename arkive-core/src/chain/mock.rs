@@ -0,0 +1,150 @@
+//! In-memory chain backend for tests
+
+use super::{ChainBackend, ChainTx, TxStatus};
+use crate::error::Result;
+
+use async_trait::async_trait;
+use bitcoin::absolute::LockTime;
+use bitcoin::hashes::Hash;
+use bitcoin::transaction::Version;
+use bitcoin::{
+    Amount, BlockHash, OutPoint, Script, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
+    Witness,
+};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+
+/// Chain of made-up blocks with a mempool that accepts anything broadcast
+pub struct MockChain {
+    blocks: Vec<BlockHash>,
+    txs: Mutex<HashMap<Txid, Transaction>>,
+    /// Confirmed transactions returned by script history lookups
+    history: Mutex<Vec<ChainTx>>,
+    /// Txids of every broadcast, one entry per transaction or package
+    pub broadcasts: Mutex<Vec<Vec<Txid>>>,
+}
+
+impl MockChain {
+    /// Blocks up to `tip_height`, those from `fork.0` on replaced by blocks
+    /// hashing to `fork.1` repeated
+    pub fn new(tip_height: u32, fork: Option<(u32, u8)>) -> Self {
+        let blocks = (0..=tip_height)
+            .map(|height| match fork {
+                Some((fork_height, byte)) if height >= fork_height => {
+                    BlockHash::from_byte_array([byte; 32])
+                }
+                _ => block(height),
+            })
+            .collect();
+
+        Self {
+            blocks,
+            txs: Mutex::new(HashMap::new()),
+            history: Mutex::new(Vec::new()),
+            broadcasts: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Confirm a payment of `value` to `script_pubkey` at `height`
+    pub fn receive(&self, script_pubkey: ScriptBuf, value: Amount, height: u32) -> OutPoint {
+        let mut history = self.history.lock();
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(
+                    Txid::from_byte_array([0xff; 32]),
+                    history.len() as u32,
+                ),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value,
+                script_pubkey,
+            }],
+        };
+        let txid = tx.compute_txid();
+
+        history.push(ChainTx {
+            txid,
+            tx,
+            prevouts: vec![None],
+            status: TxStatus {
+                block_height: Some(height),
+                block_hash: self.blocks.get(height as usize).copied(),
+                block_time: Some(1_700_000_000),
+            },
+        });
+        OutPoint::new(txid, 0)
+    }
+}
+
+/// Hash of the original block at `height`
+pub fn block(height: u32) -> BlockHash {
+    let mut bytes = [0; 32];
+    bytes[..4].copy_from_slice(&height.to_le_bytes());
+    BlockHash::from_byte_array(bytes)
+}
+
+#[async_trait]
+impl ChainBackend for MockChain {
+    async fn tip_height(&self) -> Result<u32> {
+        Ok(self.blocks.len() as u32 - 1)
+    }
+
+    async fn block_hash(&self, height: u32) -> Result<Option<BlockHash>> {
+        Ok(self.blocks.get(height as usize).copied())
+    }
+
+    async fn get_tx(&self, txid: &Txid) -> Result<Option<Transaction>> {
+        Ok(self.txs.lock().get(txid).cloned())
+    }
+
+    async fn get_tx_status(&self, _txid: &Txid) -> Result<TxStatus> {
+        Ok(TxStatus::default())
+    }
+
+    async fn get_output_spend(&self, outpoint: &OutPoint) -> Result<Option<Txid>> {
+        Ok(self.txs.lock().values().find_map(|tx| {
+            tx.input
+                .iter()
+                .any(|input| input.previous_output == *outpoint)
+                .then(|| tx.compute_txid())
+        }))
+    }
+
+    async fn script_history(
+        &self,
+        script: &Script,
+        _since_height: Option<u32>,
+    ) -> Result<Vec<ChainTx>> {
+        Ok(self
+            .history
+            .lock()
+            .iter()
+            .filter(|tx| tx.tx.output.iter().any(|o| o.script_pubkey == *script))
+            .cloned()
+            .collect())
+    }
+
+    async fn fee_estimates(&self) -> Result<HashMap<u16, f64>> {
+        Ok(HashMap::from([(1, 10.0), (6, 5.0), (144, 1.0)]))
+    }
+
+    async fn broadcast(&self, tx: &Transaction) -> Result<()> {
+        self.broadcast_package(std::slice::from_ref(tx)).await
+    }
+
+    async fn broadcast_package(&self, txs: &[Transaction]) -> Result<()> {
+        let mut mempool = self.txs.lock();
+        for tx in txs {
+            mempool.insert(tx.compute_txid(), tx.clone());
+        }
+        self.broadcasts
+            .lock()
+            .push(txs.iter().map(Transaction::compute_txid).collect());
+        Ok(())
+    }
+}
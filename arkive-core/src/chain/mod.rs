@@ -0,0 +1,111 @@
+pub mod bitcoind;
+pub mod electrum;
+pub mod esplora;
+#[cfg(test)]
+pub(crate) mod mock;
+
+pub use bitcoind::BitcoindBackend;
+pub use electrum::ElectrumBackend;
+pub use esplora::EsploraBackend;
+
+use crate::error::Result;
+use crate::wallet::{ChainBackendConfig, WalletConfig};
+
+use async_trait::async_trait;
+use bitcoin::{Amount, BlockHash, OutPoint, Script, Transaction, TxOut, Txid};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Where a transaction stands in the best chain
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TxStatus {
+    pub block_height: Option<u32>,
+    pub block_hash: Option<BlockHash>,
+    pub block_time: Option<u64>,
+}
+
+impl TxStatus {
+    pub fn is_confirmed(&self) -> bool {
+        self.block_height.is_some()
+    }
+}
+
+/// Blocks from the one at `block_height` up to the tip, zero if unconfirmed
+pub fn confirmations(block_height: Option<u32>, tip_height: u32) -> u32 {
+    block_height.map_or(0, |height| tip_height.saturating_sub(height) + 1)
+}
+
+/// A transaction from a script's history along with the outputs it spends
+#[derive(Debug, Clone)]
+pub struct ChainTx {
+    pub txid: Txid,
+    pub tx: Transaction,
+    /// Output spent by each input, `None` where the backend didn't report it
+    pub prevouts: Vec<Option<TxOut>>,
+    pub status: TxStatus,
+}
+
+impl ChainTx {
+    /// Fee paid, known only when every spent output is
+    pub fn fee(&self) -> Option<Amount> {
+        let input_total = self
+            .prevouts
+            .iter()
+            .map(|prevout| prevout.as_ref().map(|txout| txout.value))
+            .sum::<Option<Amount>>()?;
+        let output_total = self.tx.output.iter().map(|o| o.value).sum::<Amount>();
+        input_total.checked_sub(output_total)
+    }
+
+    pub fn vsize(&self) -> u64 {
+        self.tx.vsize() as u64
+    }
+}
+
+/// Chain data and broadcasting for the on-chain wallet and the Ark client
+#[async_trait]
+pub trait ChainBackend: Send + Sync {
+    /// Height of the best block
+    async fn tip_height(&self) -> Result<u32>;
+
+    /// Hash of the best chain's block at `height`, `None` above the tip
+    async fn block_hash(&self, height: u32) -> Result<Option<BlockHash>>;
+
+    async fn get_tx(&self, txid: &Txid) -> Result<Option<Transaction>>;
+
+    /// Status of a transaction, unconfirmed if it isn't known at all
+    async fn get_tx_status(&self, txid: &Txid) -> Result<TxStatus>;
+
+    /// Transaction spending `outpoint`, if any
+    async fn get_output_spend(&self, outpoint: &OutPoint) -> Result<Option<Txid>>;
+
+    /// Transactions paying to or spending from `script`. With
+    /// `since_height`, confirmed transactions at or below it may be left
+    /// out; unconfirmed ones are always returned.
+    async fn script_history(
+        &self,
+        script: &Script,
+        since_height: Option<u32>,
+    ) -> Result<Vec<ChainTx>>;
+
+    /// Fee rates in sat/vB keyed by confirmation target in blocks
+    async fn fee_estimates(&self) -> Result<HashMap<u16, f64>>;
+
+    async fn broadcast(&self, tx: &Transaction) -> Result<()>;
+
+    /// Submit transactions together so a child can pay for parents that
+    /// are below the minimum relay fee on their own. Parents come first.
+    async fn broadcast_package(&self, txs: &[Transaction]) -> Result<()>;
+}
+
+/// Connect to the chain backend selected in `config`
+pub fn from_config(config: &WalletConfig) -> Result<Arc<dyn ChainBackend>> {
+    match &config.chain_backend {
+        ChainBackendConfig::Esplora => Ok(Arc::new(EsploraBackend::new(
+            &config.esplora_urls(),
+            &config.esplora_policy,
+        )?)),
+        ChainBackendConfig::Bitcoind(rpc) => Ok(Arc::new(BitcoindBackend::new(rpc)?)),
+        ChainBackendConfig::Electrum(electrum) => Ok(Arc::new(ElectrumBackend::new(electrum))),
+    }
+}
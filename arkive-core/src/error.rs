@@ -22,6 +22,12 @@ pub enum ArkiveError {
     #[error("Esplora error: {0}")]
     Esplora(String),
 
+    #[error("Electrum error: {0}")]
+    Electrum(String),
+
+    #[error("Bitcoin Core RPC error: {0}")]
+    Rpc(String),
+
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
 
@@ -34,6 +40,15 @@ pub enum ArkiveError {
     #[error("Wallet not found: {name}")]
     WalletNotFound { name: String },
 
+    #[error("Invalid passphrase")]
+    InvalidPassphrase,
+
+    #[error("BIP39 passphrase required to derive this wallet's keys")]
+    Bip39PassphraseRequired,
+
+    #[error("Wallet is locked, unlock it with the wallet passphrase first")]
+    WalletLocked,
+
     #[error("Invalid address: {0}")]
     InvalidAddress(String),
 
@@ -56,6 +71,12 @@ impl From<ark_core::Error> for ArkiveError {
     }
 }
 
+impl From<bitcoin::bip32::Error> for ArkiveError {
+    fn from(err: bitcoin::bip32::Error) -> Self {
+        ArkiveError::Bitcoin(format!("Key derivation failed: {}", err))
+    }
+}
+
 impl ArkiveError {
     pub fn wallet(msg: impl Into<String>) -> Self {
         Self::Wallet(msg.into())
@@ -85,6 +106,14 @@ impl ArkiveError {
         Self::Esplora(msg.into())
     }
 
+    pub fn electrum(msg: impl Into<String>) -> Self {
+        Self::Electrum(msg.into())
+    }
+
+    pub fn rpc(msg: impl Into<String>) -> Self {
+        Self::Rpc(msg.into())
+    }
+
     pub fn dialog(msg: impl Into<String>) -> Self {
         Self::Dialog(msg.into())
     }
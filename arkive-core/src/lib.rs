@@ -6,14 +6,17 @@
 pub mod ark;
 pub mod backup;
 pub mod bitcoin;
+pub mod chain;
 pub mod error;
 pub mod storage;
 pub mod sync;
 pub mod types;
+pub mod uri;
 pub mod wallet;
 
 pub use error::{ArkiveError, Result};
 pub use types::{Address, Balance, Transaction};
+pub use uri::PaymentUri;
 pub use wallet::{ArkWallet, WalletConfig, WalletManager};
 
 pub use backup::{BackupManager, EncryptedBackup, WalletBackup};
@@ -28,26 +31,834 @@ mod tests {
     use super::*;
     use tempfile::tempdir;
 
+    const ADDRESS: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+
+    /// Confirmed VTXO of `sats` expiring after `expires_in`, without an exit
+    /// branch
+    fn vtxo(
+        outpoint: &str,
+        sats: u64,
+        expires_in: chrono::Duration,
+    ) -> storage::vtxo_store::VtxoState {
+        storage::vtxo_store::VtxoState {
+            outpoint: outpoint.to_string(),
+            amount: Amount::from_sat(sats),
+            status: types::VtxoStatus::Confirmed,
+            expiry: chrono::Utc::now() + expires_in,
+            address: String::new(),
+            batch_id: String::new(),
+            tree_path: Vec::new(),
+            exit_transactions: Vec::new(),
+        }
+    }
+
+    /// Output of 10 000 sats in a transaction with every txid byte `byte`
+    fn wallet_utxo(byte: u8, confirmation_height: Option<u32>) -> bitcoin::WalletUtxo {
+        use ::bitcoin::hashes::Hash;
+
+        bitcoin::WalletUtxo {
+            outpoint: ::bitcoin::OutPoint::new(::bitcoin::Txid::from_byte_array([byte; 32]), 0),
+            txout: ::bitcoin::TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: ::bitcoin::ScriptBuf::new(),
+            },
+            keychain: types::Keychain::External,
+            index: 0,
+            confirmation_height,
+        }
+    }
+
+    /// Exit of `outpoint` broadcasting its branch from transaction `next_tx`
+    fn exit(outpoint: ::bitcoin::OutPoint, sats: u64, next_tx: usize) -> storage::UnilateralExit {
+        storage::UnilateralExit {
+            outpoint,
+            amount: Amount::from_sat(sats),
+            destination: ADDRESS.to_string(),
+            server_pubkey: "00".repeat(32),
+            exit_delay: 144,
+            stage: storage::ExitStage::Broadcasting,
+            next_tx,
+            confirmed_height: None,
+            confirmed_time: None,
+            claim_txid: None,
+            last_error: None,
+            started_at: chrono::Utc::now(),
+        }
+    }
+
     #[tokio::test]
     async fn test_wallet_creation() {
         let temp_dir = tempdir().unwrap();
         let manager = WalletManager::new(temp_dir.path()).await.unwrap();
 
         let (wallet, _mnemonic) = manager
-            .create_wallet("test-wallet", Network::Regtest)
+            .create_wallet("test-wallet", Network::Regtest, "wallet_passphrase", None)
             .await
             .unwrap();
         assert_eq!(wallet.name(), "test-wallet");
         assert_eq!(wallet.network(), Network::Regtest);
     }
 
+    #[tokio::test]
+    async fn test_seed_encrypted_at_rest() {
+        let temp_dir = tempdir().unwrap();
+        let manager = WalletManager::new(temp_dir.path()).await.unwrap();
+
+        let (_wallet, mnemonic) = manager
+            .create_wallet(
+                "encrypted-wallet",
+                Network::Regtest,
+                "wallet_passphrase",
+                None,
+            )
+            .await
+            .unwrap();
+
+        let storage = storage::Storage::new(&temp_dir.path().join("arkive.db"))
+            .await
+            .unwrap();
+        let wallets = storage::WalletStore::new(&storage)
+            .list_wallets()
+            .await
+            .unwrap();
+        let stored_seed = String::from_utf8_lossy(&wallets[0].encrypted_seed).to_string();
+        assert!(!stored_seed.contains(&mnemonic));
+
+        let result = manager
+            .load_wallet("encrypted-wallet", "wrong_passphrase")
+            .await;
+        assert!(matches!(result, Err(ArkiveError::InvalidPassphrase)));
+
+        let wallet = manager
+            .load_wallet("encrypted-wallet", "wallet_passphrase")
+            .await
+            .unwrap();
+        assert_eq!(wallet.name(), "encrypted-wallet");
+    }
+
+    #[tokio::test]
+    async fn test_bip39_passphrase() {
+        let temp_dir = tempdir().unwrap();
+        let manager = WalletManager::new(temp_dir.path()).await.unwrap();
+        let mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+        let bip39 = wallet::Bip39Passphrase {
+            passphrase: "TREZOR".to_string(),
+            persist: false,
+        };
+        let wallet = manager
+            .import_wallet(
+                "bip39-wallet",
+                mnemonic,
+                Network::Regtest,
+                "wallet_passphrase",
+                Some(&bip39),
+            )
+            .await
+            .unwrap();
+        let onchain_address = wallet.get_onchain_address().await.unwrap().address;
+
+        // A fresh manager has no cached instance to fall back on
+        let manager = WalletManager::new(temp_dir.path()).await.unwrap();
+        let result = manager
+            .load_wallet("bip39-wallet", "wallet_passphrase")
+            .await;
+        assert!(matches!(result, Err(ArkiveError::Bip39PassphraseRequired)));
+
+        let result = manager
+            .load_wallet_with_bip39("bip39-wallet", "wallet_passphrase", Some("wrong"))
+            .await;
+        assert!(matches!(result, Err(ArkiveError::InvalidPassphrase)));
+
+        let wallet = manager
+            .load_wallet_with_bip39("bip39-wallet", "wallet_passphrase", Some("TREZOR"))
+            .await
+            .unwrap();
+        assert_eq!(
+            wallet.get_onchain_address().await.unwrap().address,
+            onchain_address
+        );
+    }
+
+    #[tokio::test]
+    async fn test_key_derivation() {
+        let temp_dir = tempdir().unwrap();
+        let manager = WalletManager::new(temp_dir.path()).await.unwrap();
+
+        let (wallet, _) = manager
+            .create_wallet("keys-test", Network::Regtest, "wallet_passphrase", None)
+            .await
+            .unwrap();
+        let derivation = &wallet.config().key_derivation;
+        assert_eq!(derivation.onchain_path, "m/84'/1'/0'/0/0");
+        assert_eq!(derivation.ark_path, "m/350'/1'/0'/0/0");
+
+        let public_keys = wallet.public_keys();
+        assert_ne!(public_keys.onchain, public_keys.ark);
+
+        // Reloading derives the same keys from the recorded paths
+        let manager = WalletManager::new(temp_dir.path()).await.unwrap();
+        let wallet = manager
+            .load_wallet("keys-test", "wallet_passphrase")
+            .await
+            .unwrap();
+        assert_eq!(wallet.public_keys(), public_keys);
+    }
+
+    #[tokio::test]
+    async fn test_hd_onchain_addresses() {
+        let temp_dir = tempdir().unwrap();
+        let manager = WalletManager::new(temp_dir.path()).await.unwrap();
+
+        let (wallet, _) = manager
+            .create_wallet("hd-test", Network::Regtest, "wallet_passphrase", None)
+            .await
+            .unwrap();
+
+        // Unused addresses are handed out again until they receive funds
+        let first = wallet.get_onchain_address().await.unwrap().address;
+        assert_eq!(wallet.get_onchain_address().await.unwrap().address, first);
+
+        let second = wallet.new_onchain_address().await.unwrap().address;
+        assert_ne!(second, first);
+
+        let addresses = wallet.list_onchain_addresses().await.unwrap();
+        assert_eq!(addresses.len(), 2);
+        assert_eq!(addresses[0].derivation_path, "m/84'/1'/0'/0/0");
+        assert_eq!(addresses[1].derivation_path, "m/84'/1'/0'/0/1");
+        assert_eq!(addresses[1].address, second);
+    }
+
+    #[tokio::test]
+    async fn test_taproot_onchain_addresses() {
+        let temp_dir = tempdir().unwrap();
+        let manager = WalletManager::new(temp_dir.path()).await.unwrap();
+
+        let config = WalletConfig::new(Network::Regtest)
+            .with_onchain_script(wallet::OnchainScriptType::P2tr);
+        let (wallet, _) = manager
+            .create_wallet_with_config("taproot-test", config, "wallet_passphrase", None)
+            .await
+            .unwrap();
+
+        let address = wallet.get_onchain_address().await.unwrap().address;
+        assert!(address.starts_with("bcrt1p"));
+
+        let addresses = wallet.list_onchain_addresses().await.unwrap();
+        assert_eq!(addresses[0].derivation_path, "m/86'/1'/0'/0/0");
+    }
+
+    #[tokio::test]
+    async fn test_utxo_store_sync() {
+        use ::bitcoin::hashes::Hash;
+        use ::bitcoin::Txid;
+        use storage::{ChainSyncState, UtxoSpend, UtxoStore};
+
+        let temp_dir = tempdir().unwrap();
+        let manager = WalletManager::new(temp_dir.path()).await.unwrap();
+        let (wallet, _) = manager
+            .create_wallet("utxo-test", Network::Regtest, "wallet_passphrase", None)
+            .await
+            .unwrap();
+
+        let storage = storage::Storage::new(&temp_dir.path().join("arkive.db"))
+            .await
+            .unwrap();
+        let utxo_store = UtxoStore::new(&storage);
+        assert!(utxo_store
+            .load_sync_state(wallet.id())
+            .await
+            .unwrap()
+            .is_none());
+
+        let state = ChainSyncState {
+            height: 101,
+            tip_hash: Some(::bitcoin::BlockHash::from_byte_array([7; 32])),
+            external_index: 20,
+            internal_index: 20,
+        };
+
+        let confirmed = wallet_utxo(1, Some(100));
+        utxo_store
+            .apply_sync(
+                wallet.id(),
+                &[confirmed.clone(), wallet_utxo(2, None)],
+                &[],
+                &[],
+                &state,
+            )
+            .await
+            .unwrap();
+        assert_eq!(utxo_store.load_unspent(wallet.id()).await.unwrap().len(), 2);
+        assert_eq!(
+            utxo_store.load_sync_state(wallet.id()).await.unwrap(),
+            Some(state)
+        );
+
+        // The unconfirmed output is gone and the confirmed one is being spent
+        let spend = UtxoSpend {
+            outpoint: confirmed.outpoint,
+            spent_by: Txid::from_byte_array([3; 32]),
+            height: None,
+        };
+        utxo_store
+            .apply_sync(wallet.id(), &[], &[spend], &[], &state)
+            .await
+            .unwrap();
+        assert!(utxo_store
+            .load_unspent(wallet.id())
+            .await
+            .unwrap()
+            .is_empty());
+
+        // The spend left the mempool without confirming
+        utxo_store
+            .apply_sync(wallet.id(), &[], &[], &[], &state)
+            .await
+            .unwrap();
+        let unspent = utxo_store.load_unspent(wallet.id()).await.unwrap();
+        assert_eq!(unspent.len(), 1);
+        assert_eq!(unspent[0].outpoint, confirmed.outpoint);
+        assert_eq!(unspent[0].confirmation_height, Some(100));
+
+        // Block 100 was reorganized out
+        utxo_store.rollback(wallet.id(), 100).await.unwrap();
+        let unspent = utxo_store.load_unspent(wallet.id()).await.unwrap();
+        assert_eq!(unspent[0].confirmation_height, None);
+        let rolled_back = utxo_store
+            .load_sync_state(wallet.id())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(rolled_back.height, 99);
+        assert_eq!(rolled_back.tip_hash, None);
+    }
+
+    #[tokio::test]
+    async fn test_reorg_rollback() {
+        use ::bitcoin::hashes::Hash;
+        use ::bitcoin::{BlockHash, OutPoint, Txid};
+        use chain::TxStatus;
+        use storage::{BoardingOutputState, BoardingStore, ChainSyncState, UtxoSpend, UtxoStore};
+        use types::{TransactionStatus, TransactionType};
+
+        let temp_dir = tempdir().unwrap();
+        let manager = WalletManager::new(temp_dir.path()).await.unwrap();
+        let (wallet, _) = manager
+            .create_wallet("reorg-test", Network::Regtest, "wallet_passphrase", None)
+            .await
+            .unwrap();
+
+        let storage = std::sync::Arc::new(
+            storage::Storage::new(&temp_dir.path().join("arkive.db"))
+                .await
+                .unwrap(),
+        );
+        let utxo_store = UtxoStore::new(&storage);
+        let block = |height: u32| BlockHash::from_byte_array([height as u8; 32]);
+
+        // One output confirmed below the fork and spent above it, one
+        // confirmed above it
+        let spend = UtxoSpend {
+            outpoint: wallet_utxo(1, None).outpoint,
+            spent_by: Txid::from_byte_array([3; 32]),
+            height: Some(105),
+        };
+        let state = ChainSyncState {
+            height: 110,
+            tip_hash: Some(block(110)),
+            external_index: 20,
+            internal_index: 20,
+        };
+        utxo_store
+            .apply_sync(
+                wallet.id(),
+                &[wallet_utxo(1, Some(100)), wallet_utxo(2, Some(106))],
+                &[spend],
+                &[(100, block(100)), (105, block(105)), (106, block(106))],
+                &state,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            utxo_store.load_blocks(wallet.id(), 0).await.unwrap().len(),
+            4
+        );
+
+        let boarding = |byte: u8, block_height: u32| BoardingOutputState {
+            outpoint: OutPoint::new(Txid::from_byte_array([byte; 32]), 0),
+            amount: Amount::from_sat(20_000),
+            address: String::new(),
+            script_pubkey: String::new(),
+            exit_delay: 144,
+            server_pubkey: String::new(),
+            user_pubkey: String::new(),
+            confirmation_blocktime: chrono::DateTime::from_timestamp(1_700_000_000, 0),
+            block_height: Some(block_height),
+            block_hash: Some(block(block_height).to_string()),
+            is_spent: false,
+            is_mutinynet: false,
+        };
+        let boarding_store = BoardingStore::new(&storage);
+        boarding_store
+            .save_boarding_output(wallet.id(), &boarding(4, 100))
+            .await
+            .unwrap();
+        boarding_store
+            .save_boarding_output(wallet.id(), &boarding(5, 107))
+            .await
+            .unwrap();
+
+        let tx_manager = ark::TransactionManager::new(storage.clone(), wallet.id().to_string());
+        for (byte, height) in [(6u8, 100), (7, 108)] {
+            tx_manager
+                .upsert_chain_transaction(
+                    &Txid::from_byte_array([byte; 32]).to_string(),
+                    10_000,
+                    TransactionType::OnChain,
+                    None,
+                    &TxStatus {
+                        block_height: Some(height),
+                        block_hash: Some(block(height)),
+                        block_time: Some(1_700_000_000),
+                    },
+                )
+                .await
+                .unwrap();
+        }
+
+        // Blocks from 105 were reorganized out
+        assert_eq!(tx_manager.rollback(105).await.unwrap(), 1);
+        assert_eq!(boarding_store.rollback(wallet.id(), 105).await.unwrap(), 1);
+        utxo_store.rollback(wallet.id(), 105).await.unwrap();
+
+        let transactions = tx_manager
+            .get_transaction_history_by_type(TransactionType::OnChain)
+            .await
+            .unwrap();
+        for tx in transactions {
+            if tx.block_height.is_some() {
+                assert_eq!(tx.block_height, Some(100));
+                assert!(matches!(tx.status, TransactionStatus::Confirmed));
+            } else {
+                assert!(matches!(tx.status, TransactionStatus::Pending));
+                assert_eq!(tx.block_hash, None);
+            }
+        }
+        assert_eq!(
+            tx_manager.load_confirmation_blocks(0).await.unwrap(),
+            vec![(100, block(100).to_string())]
+        );
+
+        let boarding_outputs = boarding_store
+            .load_boarding_outputs(wallet.id())
+            .await
+            .unwrap();
+        for output in &boarding_outputs {
+            match output.block_height {
+                Some(height) => assert_eq!(height, 100),
+                None => assert!(output.confirmation_blocktime.is_none()),
+            }
+        }
+        assert_eq!(
+            boarding_store
+                .load_confirmation_blocks(wallet.id(), 0)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // The spend from 105 and the output from 106 are unconfirmed again
+        let unspent = utxo_store.load_unspent(wallet.id()).await.unwrap();
+        assert_eq!(unspent.len(), 1);
+        assert_eq!(unspent[0].outpoint, wallet_utxo(2, None).outpoint);
+        assert_eq!(unspent[0].confirmation_height, None);
+        assert_eq!(
+            utxo_store.load_blocks(wallet.id(), 0).await.unwrap(),
+            vec![(100, block(100).to_string())]
+        );
+        let rolled_back = utxo_store
+            .load_sync_state(wallet.id())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(rolled_back.height, 104);
+        assert_eq!(rolled_back.tip_hash, None);
+
+        utxo_store
+            .apply_sync(wallet.id(), &[], &[], &[], &rolled_back)
+            .await
+            .unwrap();
+        let unspent = utxo_store.load_unspent(wallet.id()).await.unwrap();
+        assert_eq!(unspent.len(), 1);
+        assert_eq!(unspent[0].outpoint, wallet_utxo(1, None).outpoint);
+        assert_eq!(unspent[0].confirmation_height, Some(100));
+    }
+
+    #[tokio::test]
+    async fn test_exit_store_resume() {
+        use ::bitcoin::hashes::Hash;
+        use ::bitcoin::{OutPoint, Txid};
+        use storage::{ExitStage, ExitStore};
+
+        let temp_dir = tempdir().unwrap();
+        let storage = storage::Storage::new(&temp_dir.path().join("arkive.db"))
+            .await
+            .unwrap();
+        let exit_store = ExitStore::new(&storage);
+
+        let outpoint = OutPoint::new(Txid::from_byte_array([1; 32]), 0);
+        let mut exit = exit(outpoint, 50_000, 0);
+        exit_store.save_exit("wallet", &exit).await.unwrap();
+
+        // Interrupted halfway through the branch
+        exit.next_tx = 2;
+        exit.last_error = Some("broadcast failed".to_string());
+        exit_store.save_exit("wallet", &exit).await.unwrap();
+
+        let exits = exit_store.load_exits("wallet").await.unwrap();
+        assert_eq!(exits.len(), 1);
+        assert_eq!(exits[0].stage, ExitStage::Broadcasting);
+        assert_eq!(exits[0].next_tx, 2);
+        assert_eq!(exits[0].last_error.as_deref(), Some("broadcast failed"));
+
+        exit.stage = ExitStage::Claimed;
+        exit.confirmed_height = Some(200);
+        exit.claim_txid = Some(Txid::from_byte_array([2; 32]));
+        exit_store.save_exit("wallet", &exit).await.unwrap();
+
+        let loaded = exit_store
+            .load_exit("wallet", &outpoint)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.stage, ExitStage::Claimed);
+        assert_eq!(loaded.confirmed_height, Some(200));
+        assert_eq!(loaded.claim_txid, exit.claim_txid);
+        assert!(exit_store
+            .load_exits("other-wallet")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_exit_resumes_broadcasting() {
+        use ::bitcoin::absolute::LockTime;
+        use ::bitcoin::bip32::Xpriv;
+        use ::bitcoin::hashes::Hash;
+        use ::bitcoin::transaction::Version;
+        use ::bitcoin::{OutPoint, ScriptBuf, Sequence, TxIn, TxOut, Txid, Witness};
+        use chain::mock::MockChain;
+        use std::sync::Arc;
+        use storage::vtxo_store::VtxoState;
+        use storage::{ExitStage, ExitStore, VtxoStore};
+
+        let temp_dir = tempdir().unwrap();
+        let storage = Arc::new(
+            storage::Storage::new(&temp_dir.path().join("arkive.db"))
+                .await
+                .unwrap(),
+        );
+
+        let mut config = WalletConfig::new(Network::Regtest);
+        // Nothing listens here, so the Ark service stays offline
+        config.ark_server_url = "http://127.0.0.1:1".to_string();
+        let master = Xpriv::new_master(Network::Regtest, &[7; 32]).unwrap();
+        let keys = wallet::WalletKeys::derive(&master, &config.key_derivation).unwrap();
+        let public_keys = keys.public_keys();
+        let session = wallet::KeySession::unlocked(keys, None);
+        let chain = Arc::new(MockChain::new(200, None));
+
+        let bitcoin_service = bitcoin::BitcoinService::new(
+            session.clone(),
+            public_keys,
+            config.clone(),
+            chain.clone(),
+            storage.clone(),
+            "wallet".to_string(),
+        )
+        .await
+        .unwrap();
+        let ark_service = ark::ArkService::new(
+            session,
+            public_keys,
+            config,
+            chain.clone(),
+            storage.clone(),
+            "wallet".to_string(),
+        )
+        .await
+        .unwrap();
+
+        let output = |sats: u64, script_pubkey: ScriptBuf| TxOut {
+            value: Amount::from_sat(sats),
+            script_pubkey,
+        };
+        let spend = |previous_output: OutPoint, output: Vec<TxOut>| ::bitcoin::Transaction {
+            version: Version(3),
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output,
+        };
+        let anchor = ScriptBuf::from_bytes(vec![0x51, 0x02, 0x4e, 0x73]);
+
+        // A root with two leaves, the second of which pays its fee through
+        // an anchor
+        let root = spend(
+            OutPoint::new(Txid::from_byte_array([9; 32]), 0),
+            vec![
+                output(10_000, ScriptBuf::new()),
+                output(10_000, ScriptBuf::new()),
+            ],
+        );
+        let leaf = spend(
+            OutPoint::new(root.compute_txid(), 0),
+            vec![output(10_000, ScriptBuf::new())],
+        );
+        let anchored_leaf = spend(
+            OutPoint::new(root.compute_txid(), 1),
+            vec![output(10_000, ScriptBuf::new()), output(0, anchor)],
+        );
+        assert!(ark::exit::anchor(&leaf).is_none());
+        assert_eq!(
+            ark::exit::anchor(&anchored_leaf).map(|(outpoint, _)| outpoint.vout),
+            Some(1)
+        );
+
+        let vtxo_store = VtxoStore::new(&storage);
+        let exit_store = ExitStore::new(&storage);
+        for leaf in [&leaf, &anchored_leaf] {
+            let outpoint = OutPoint::new(leaf.compute_txid(), 0);
+            vtxo_store
+                .save_vtxo_state(
+                    "wallet",
+                    &VtxoState {
+                        exit_transactions: vec![
+                            ::bitcoin::consensus::serialize(&root),
+                            ::bitcoin::consensus::serialize(leaf),
+                        ],
+                        ..vtxo(&outpoint.to_string(), 10_000, chrono::Duration::days(1))
+                    },
+                )
+                .await
+                .unwrap();
+
+            // The root went out before an interruption
+            exit_store
+                .save_exit("wallet", &exit(outpoint, 10_000, 1))
+                .await
+                .unwrap();
+        }
+
+        let exits = ark_service.advance_exits(&bitcoin_service).await.unwrap();
+        let exit_of = |leaf: &::bitcoin::Transaction| {
+            exits
+                .iter()
+                .find(|exit| exit.outpoint.txid == leaf.compute_txid())
+                .unwrap()
+        };
+
+        // Only the rest of the branch was broadcast
+        let plain = exit_of(&leaf);
+        assert_eq!(plain.stage, ExitStage::AwaitingConfirmation);
+        assert_eq!(plain.next_tx, 2);
+        assert_eq!(plain.last_error, None);
+
+        // The anchored leaf needs a child, which an empty wallet can't fund,
+        // and isn't broadcast alone
+        let anchored = exit_of(&anchored_leaf);
+        assert_eq!(anchored.stage, ExitStage::Broadcasting);
+        assert_eq!(anchored.next_tx, 1);
+        assert!(anchored.last_error.is_some());
+
+        assert_eq!(*chain.broadcasts.lock(), vec![vec![leaf.compute_txid()]]);
+        let stored = exit_store
+            .load_exit("wallet", &plain.outpoint)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.next_tx, 2);
+    }
+
+    #[tokio::test]
+    async fn test_vtxo_renewal() {
+        use storage::VtxoStore;
+        use wallet::RenewalOutcome;
+
+        let temp_dir = tempdir().unwrap();
+        let manager = WalletManager::new(temp_dir.path()).await.unwrap();
+        let (wallet, _) = manager
+            .create_wallet("renewal-test", Network::Regtest, "wallet_passphrase", None)
+            .await
+            .unwrap();
+
+        let storage = storage::Storage::new(&temp_dir.path().join("arkive.db"))
+            .await
+            .unwrap();
+        let vtxo_store = VtxoStore::new(&storage);
+
+        // Well outside the one hour threshold
+        let later = format!("{}:0", "11".repeat(32));
+        vtxo_store
+            .save_vtxo_state(
+                wallet.id(),
+                &vtxo(&later, 20_000, chrono::Duration::days(1)),
+            )
+            .await
+            .unwrap();
+        let report = wallet.renew_expiring_vtxos().await;
+        assert_eq!(report.outcome, RenewalOutcome::NothingDue);
+        assert!(report.expiring.is_empty());
+
+        // Due, but a locked wallet can't join a round
+        let soon = format!("{}:0", "22".repeat(32));
+        vtxo_store
+            .save_vtxo_state(
+                wallet.id(),
+                &vtxo(&soon, 20_000, chrono::Duration::minutes(10)),
+            )
+            .await
+            .unwrap();
+        wallet.lock();
+        let report = wallet.renew_expiring_vtxos().await;
+        assert!(matches!(report.outcome, RenewalOutcome::Skipped(_)));
+        assert_eq!(report.expiring, vec![soon]);
+        assert_eq!(report.attempts, 0);
+        assert!(report.settled.is_empty());
+        assert!(report.boarded.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_offboard_rejects_bad_amounts() {
+        use storage::VtxoStore;
+
+        let temp_dir = tempdir().unwrap();
+        let manager = WalletManager::new(temp_dir.path()).await.unwrap();
+        let (wallet, _) = manager
+            .create_wallet("offboard-test", Network::Regtest, "wallet_passphrase", None)
+            .await
+            .unwrap();
+
+        let storage = storage::Storage::new(&temp_dir.path().join("arkive.db"))
+            .await
+            .unwrap();
+        VtxoStore::new(&storage)
+            .save_vtxo_state(
+                wallet.id(),
+                &vtxo(
+                    &format!("{}:0", "11".repeat(32)),
+                    20_000,
+                    chrono::Duration::days(1),
+                ),
+            )
+            .await
+            .unwrap();
+
+        let address = ADDRESS;
+        assert!(matches!(
+            wallet.offboard(address, Amount::from_sat(100)).await,
+            Err(ArkiveError::Config(_))
+        ));
+        assert!(matches!(
+            wallet.offboard(address, Amount::from_sat(50_000)).await,
+            Err(ArkiveError::InsufficientFunds { .. })
+        ));
+
+        // The whole balance leaves nothing for the server's fee
+        match wallet.offboard(address, Amount::from_sat(20_000)).await {
+            Err(ArkiveError::InsufficientFunds { need, available }) => {
+                assert!(need > 20_000);
+                assert_eq!(available, 20_000);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lock_unlock() {
+        let temp_dir = tempdir().unwrap();
+        let manager = WalletManager::new(temp_dir.path()).await.unwrap();
+
+        let (wallet, _) = manager
+            .create_wallet("lock-test", Network::Regtest, "wallet_passphrase", None)
+            .await
+            .unwrap();
+        assert!(!wallet.is_locked());
+
+        wallet.lock();
+        assert!(wallet.is_locked());
+
+        // Read-only operations keep working while locked
+        assert!(wallet.list_vtxos().await.is_ok());
+
+        let result = wallet.participate_in_round().await;
+        assert!(matches!(result, Err(ArkiveError::WalletLocked)));
+
+        // No exit is started that couldn't be finished
+        let outpoint = format!("{}:0", "11".repeat(32));
+        let result = wallet.unilateral_exit(&[outpoint]).await;
+        assert!(matches!(result, Err(ArkiveError::WalletLocked)));
+        assert!(wallet.list_exits().await.unwrap().is_empty());
+
+        let result = wallet.unlock("wrong_passphrase", None).await;
+        assert!(matches!(result, Err(ArkiveError::InvalidPassphrase)));
+        assert!(wallet.is_locked());
+
+        wallet.unlock("wallet_passphrase", None).await.unwrap();
+        assert!(!wallet.is_locked());
+    }
+
+    #[tokio::test]
+    async fn test_session_locks_on_timeout() {
+        use ::bitcoin::bip32::Xpriv;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        let master = Xpriv::new_master(Network::Regtest, &[7; 32]).unwrap();
+        let derivation = wallet::KeyDerivation::for_network(Network::Regtest);
+        let keys = || wallet::WalletKeys::derive(&master, &derivation).unwrap();
+
+        let session = wallet::KeySession::default();
+        let locks = Arc::new(AtomicUsize::new(0));
+        let counter = locks.clone();
+        session.on_lock(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        // Locked by the timer without anyone asking for the keys
+        session.unlock(keys(), Some(Duration::from_millis(50)));
+        assert!(session.is_unlocked());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(locks.load(Ordering::SeqCst), 1);
+        assert!(!session.is_unlocked());
+
+        // Unlocking again replaces the pending timer
+        session.unlock(keys(), Some(Duration::from_millis(50)));
+        session.unlock(keys(), None);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(session.is_unlocked());
+        assert_eq!(locks.load(Ordering::SeqCst), 1);
+
+        session.lock();
+        session.lock();
+        assert_eq!(locks.load(Ordering::SeqCst), 2);
+    }
+
     #[tokio::test]
     async fn test_backup_restore() {
         let temp_dir = tempdir().unwrap();
         let manager = WalletManager::new(temp_dir.path()).await.unwrap();
 
         let (wallet, _) = manager
-            .create_wallet("backup-test", Network::Regtest)
+            .create_wallet("backup-test", Network::Regtest, "wallet_passphrase", None)
             .await
             .unwrap();
 
@@ -71,7 +882,7 @@ mod tests {
         let manager = WalletManager::new(temp_dir.path()).await.unwrap();
 
         let (wallet, _) = manager
-            .create_wallet("sync-test", Network::Regtest)
+            .create_wallet("sync-test", Network::Regtest, "wallet_passphrase", None)
             .await
             .unwrap();
 
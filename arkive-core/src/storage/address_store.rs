@@ -0,0 +1,107 @@
+use crate::error::Result;
+use crate::storage::Storage;
+use crate::types::{AddressType, Keychain};
+use chrono::{DateTime, Utc};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+
+/// On-chain address derived from the wallet's HD account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddressRecord {
+    pub address: String,
+    pub keychain: Keychain,
+    pub index: u32,
+    pub derivation_path: String,
+    pub used: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+pub struct AddressStore<'a> {
+    storage: &'a Storage,
+}
+
+impl<'a> AddressStore<'a> {
+    pub fn new(storage: &'a Storage) -> Self {
+        Self { storage }
+    }
+
+    /// Record a derived address. Addresses that are already known keep their
+    /// `used` flag.
+    pub async fn save_address(&self, wallet_id: &str, record: &AddressRecord) -> Result<()> {
+        let conn = self.storage.get_connection().await;
+
+        conn.execute(
+            "INSERT INTO addresses
+             (wallet_id, address, address_type, derivation_path, created_at, keychain, derivation_index, used)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+             ON CONFLICT(wallet_id, address, address_type) DO UPDATE SET
+                keychain = excluded.keychain,
+                derivation_index = excluded.derivation_index,
+                derivation_path = excluded.derivation_path,
+                used = addresses.used OR excluded.used",
+            params![
+                wallet_id,
+                record.address,
+                serde_json::to_string(&AddressType::OnChain)?,
+                record.derivation_path,
+                record.created_at.timestamp(),
+                serde_json::to_string(&record.keychain)?,
+                record.index as i64,
+                record.used,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Load the derived addresses of one keychain, ordered by index
+    pub async fn load_addresses(
+        &self,
+        wallet_id: &str,
+        keychain: Keychain,
+    ) -> Result<Vec<AddressRecord>> {
+        let conn = self.storage.get_connection().await;
+
+        let mut stmt = conn.prepare(
+            "SELECT address, derivation_index, derivation_path, COALESCE(used, FALSE), created_at
+             FROM addresses
+             WHERE wallet_id = ?1 AND keychain = ?2
+             ORDER BY derivation_index ASC",
+        )?;
+
+        let address_iter = stmt.query_map(
+            params![wallet_id, serde_json::to_string(&keychain)?],
+            |row| {
+                let index: i64 = row.get(1)?;
+                let created_at: i64 = row.get(4)?;
+
+                Ok(AddressRecord {
+                    address: row.get(0)?,
+                    keychain,
+                    index: index as u32,
+                    derivation_path: row.get(2)?,
+                    used: row.get(3)?,
+                    created_at: DateTime::from_timestamp(created_at, 0).unwrap_or_else(Utc::now),
+                })
+            },
+        )?;
+
+        let mut addresses = Vec::new();
+        for address in address_iter {
+            addresses.push(address?);
+        }
+
+        Ok(addresses)
+    }
+
+    pub async fn mark_used(&self, wallet_id: &str, address: &str) -> Result<()> {
+        let conn = self.storage.get_connection().await;
+
+        conn.execute(
+            "UPDATE addresses SET used = TRUE WHERE wallet_id = ?1 AND address = ?2",
+            params![wallet_id, address],
+        )?;
+
+        Ok(())
+    }
+}
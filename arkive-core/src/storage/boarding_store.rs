@@ -15,11 +15,20 @@ pub struct BoardingOutputState {
     pub server_pubkey: String,
     pub user_pubkey: String,
     pub confirmation_blocktime: Option<DateTime<Utc>>,
+    /// Block the output confirmed in, cleared if that block is reorganized
+    /// out
+    pub block_height: Option<u32>,
+    pub block_hash: Option<String>,
     pub is_spent: bool,
     pub is_mutinynet: bool,
 }
 
 impl BoardingOutputState {
+    /// Confirmations as of `tip_height`, zero while unconfirmed
+    pub fn confirmations(&self, tip_height: u32) -> u32 {
+        crate::chain::confirmations(self.block_height, tip_height)
+    }
+
     pub fn to_boarding_output(
         &self,
         network: bitcoin::Network,
@@ -79,8 +88,9 @@ impl<'a> BoardingStore<'a> {
         conn.execute(
             "INSERT OR REPLACE INTO boarding_outputs 
              (wallet_id, outpoint, amount, address, script_pubkey, exit_delay, 
-              server_pubkey, user_pubkey, confirmation_blocktime, is_spent, is_mutinynet, created_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+              server_pubkey, user_pubkey, confirmation_blocktime, is_spent, is_mutinynet, created_at,
+              block_height, block_hash)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
             params![
                 wallet_id,
                 boarding_state.outpoint.to_string(),
@@ -94,6 +104,8 @@ impl<'a> BoardingStore<'a> {
                 boarding_state.is_spent,
                 boarding_state.is_mutinynet,
                 Utc::now().timestamp(),
+                boarding_state.block_height,
+                boarding_state.block_hash,
             ],
         )?;
 
@@ -112,7 +124,7 @@ impl<'a> BoardingStore<'a> {
         let mut stmt = conn.prepare(
             "SELECT outpoint, amount, address, script_pubkey, exit_delay, 
                     server_pubkey, user_pubkey, confirmation_blocktime, is_spent,
-                    COALESCE(is_mutinynet, FALSE) as is_mutinynet
+                    COALESCE(is_mutinynet, FALSE) as is_mutinynet, block_height, block_hash
              FROM boarding_outputs 
              WHERE wallet_id = ?1 AND is_spent = FALSE
              ORDER BY created_at DESC",
@@ -143,6 +155,8 @@ impl<'a> BoardingStore<'a> {
                 user_pubkey: row.get(6)?,
                 confirmation_blocktime: confirmation_blocktime
                     .and_then(|t| DateTime::from_timestamp(t, 0)),
+                block_height: row.get(10)?,
+                block_hash: row.get(11)?,
                 is_spent: row.get(8)?,
                 is_mutinynet,
             })
@@ -185,6 +199,62 @@ impl<'a> BoardingStore<'a> {
         Ok(())
     }
 
+    /// Make an output that is no longer confirmed ineligible for boarding
+    /// until it confirms again
+    pub async fn mark_boarding_output_unconfirmed(
+        &self,
+        wallet_id: &str,
+        outpoint: &OutPoint,
+    ) -> Result<()> {
+        let conn = self.storage.get_connection().await;
+
+        conn.execute(
+            "UPDATE boarding_outputs
+             SET confirmation_blocktime = NULL, block_height = NULL, block_hash = NULL
+             WHERE wallet_id = ?1 AND outpoint = ?2",
+            params![wallet_id, outpoint.to_string()],
+        )?;
+
+        Ok(())
+    }
+
+    /// Unconfirm every output confirmed at `fork_height` or above, after
+    /// those blocks were reorganized out. Returns how many were affected.
+    pub async fn rollback(&self, wallet_id: &str, fork_height: u32) -> Result<usize> {
+        let conn = self.storage.get_connection().await;
+
+        let rows_affected = conn.execute(
+            "UPDATE boarding_outputs
+             SET confirmation_blocktime = NULL, block_height = NULL, block_hash = NULL
+             WHERE wallet_id = ?1 AND block_height >= ?2",
+            params![wallet_id, fork_height],
+        )?;
+
+        Ok(rows_affected)
+    }
+
+    /// Height and hash of the blocks holding confirmed boarding outputs at
+    /// or above `min_height`
+    pub async fn load_confirmation_blocks(
+        &self,
+        wallet_id: &str,
+        min_height: u32,
+    ) -> Result<Vec<(u32, String)>> {
+        let conn = self.storage.get_connection().await;
+
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT block_height, block_hash FROM boarding_outputs
+             WHERE wallet_id = ?1 AND block_height >= ?2 AND block_hash IS NOT NULL",
+        )?;
+        let blocks = stmt
+            .query_map(params![wallet_id, min_height], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Ok(blocks)
+    }
+
     pub async fn load_unspent_boarding_outputs(
         &self,
         wallet_id: &str,
@@ -194,7 +264,7 @@ impl<'a> BoardingStore<'a> {
         let mut stmt = conn.prepare(
             "SELECT outpoint, amount, address, script_pubkey, exit_delay, 
                     server_pubkey, user_pubkey, confirmation_blocktime, is_spent,
-                    COALESCE(is_mutinynet, FALSE) as is_mutinynet
+                    COALESCE(is_mutinynet, FALSE) as is_mutinynet, block_height, block_hash
              FROM boarding_outputs 
              WHERE wallet_id = ?1 AND is_spent = FALSE AND confirmation_blocktime IS NOT NULL
              ORDER BY created_at DESC",
@@ -225,6 +295,8 @@ impl<'a> BoardingStore<'a> {
                 user_pubkey: row.get(6)?,
                 confirmation_blocktime: confirmation_blocktime
                     .and_then(|t| DateTime::from_timestamp(t, 0)),
+                block_height: row.get(10)?,
+                block_hash: row.get(11)?,
                 is_spent: row.get(8)?,
                 is_mutinynet,
             })
@@ -0,0 +1,177 @@
+use crate::error::{ArkiveError, Result};
+use crate::storage::Storage;
+use bitcoin::{Amount, OutPoint, Txid};
+use chrono::{DateTime, Utc};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Where a unilateral exit stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExitStage {
+    /// Broadcasting the presigned branch from the commitment transaction
+    /// down to the VTXO
+    Broadcasting,
+    /// Branch broadcast, waiting for the VTXO's transaction to confirm
+    AwaitingConfirmation,
+    /// VTXO confirmed on chain, waiting out the exit delay
+    Timelocked,
+    /// Swept to the destination address
+    Claimed,
+}
+
+/// Progress of a VTXO's unilateral exit, persisted after every step so an
+/// interrupted exit resumes where it stopped
+#[derive(Debug, Clone)]
+pub struct UnilateralExit {
+    pub outpoint: OutPoint,
+    pub amount: Amount,
+    /// On-chain address the VTXO is swept to
+    pub destination: String,
+    pub server_pubkey: String,
+    /// Relative timelock of the VTXO's exit path, in consensus encoding
+    pub exit_delay: u32,
+    pub stage: ExitStage,
+    /// Index of the next branch transaction to broadcast
+    pub next_tx: usize,
+    /// Block height and time the VTXO's transaction confirmed at
+    pub confirmed_height: Option<u32>,
+    pub confirmed_time: Option<u64>,
+    pub claim_txid: Option<Txid>,
+    /// Why the last attempt to advance the exit failed, if it did
+    pub last_error: Option<String>,
+    pub started_at: DateTime<Utc>,
+}
+
+pub struct ExitStore<'a> {
+    storage: &'a Storage,
+}
+
+impl<'a> ExitStore<'a> {
+    pub fn new(storage: &'a Storage) -> Self {
+        Self { storage }
+    }
+
+    pub async fn save_exit(&self, wallet_id: &str, exit: &UnilateralExit) -> Result<()> {
+        let conn = self.storage.get_connection().await;
+
+        conn.execute(
+            "INSERT OR REPLACE INTO vtxo_exits
+             (wallet_id, outpoint, amount, destination, server_pubkey, exit_delay, stage,
+              next_tx, confirmed_height, confirmed_time, claim_txid, last_error,
+              started_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            params![
+                wallet_id,
+                exit.outpoint.to_string(),
+                exit.amount.to_sat() as i64,
+                exit.destination,
+                exit.server_pubkey,
+                exit.exit_delay,
+                serde_json::to_string(&exit.stage)?,
+                exit.next_tx as i64,
+                exit.confirmed_height,
+                exit.confirmed_time.map(|t| t as i64),
+                exit.claim_txid.map(|txid| txid.to_string()),
+                exit.last_error,
+                exit.started_at.timestamp(),
+                Utc::now().timestamp(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Every exit of the wallet, oldest first
+    pub async fn load_exits(&self, wallet_id: &str) -> Result<Vec<UnilateralExit>> {
+        let conn = self.storage.get_connection().await;
+
+        let mut stmt = conn.prepare(
+            "SELECT outpoint, amount, destination, server_pubkey, exit_delay, stage, next_tx,
+                    confirmed_height, confirmed_time, claim_txid, last_error, started_at
+             FROM vtxo_exits
+             WHERE wallet_id = ?1
+             ORDER BY started_at ASC",
+        )?;
+
+        let rows = stmt
+            .query_map([wallet_id], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, u32>(4)?,
+                    row.get::<_, String>(5)?,
+                    row.get::<_, i64>(6)?,
+                    row.get::<_, Option<u32>>(7)?,
+                    row.get::<_, Option<i64>>(8)?,
+                    row.get::<_, Option<String>>(9)?,
+                    row.get::<_, Option<String>>(10)?,
+                    row.get::<_, i64>(11)?,
+                ))
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        rows.into_iter()
+            .map(
+                |(
+                    outpoint,
+                    amount,
+                    destination,
+                    server_pubkey,
+                    exit_delay,
+                    stage,
+                    next_tx,
+                    confirmed_height,
+                    confirmed_time,
+                    claim_txid,
+                    last_error,
+                    started_at,
+                )| {
+                    Ok(UnilateralExit {
+                        outpoint: OutPoint::from_str(&outpoint).map_err(|e| {
+                            ArkiveError::internal(format!(
+                                "Invalid stored outpoint {}: {}",
+                                outpoint, e
+                            ))
+                        })?,
+                        amount: Amount::from_sat(amount as u64),
+                        destination,
+                        server_pubkey,
+                        exit_delay,
+                        stage: serde_json::from_str(&stage)?,
+                        next_tx: next_tx as usize,
+                        confirmed_height,
+                        confirmed_time: confirmed_time.map(|t| t as u64),
+                        claim_txid: claim_txid
+                            .map(|txid| {
+                                Txid::from_str(&txid).map_err(|e| {
+                                    ArkiveError::internal(format!(
+                                        "Invalid stored txid {}: {}",
+                                        txid, e
+                                    ))
+                                })
+                            })
+                            .transpose()?,
+                        last_error,
+                        started_at: DateTime::from_timestamp(started_at, 0)
+                            .unwrap_or_else(Utc::now),
+                    })
+                },
+            )
+            .collect()
+    }
+
+    pub async fn load_exit(
+        &self,
+        wallet_id: &str,
+        outpoint: &OutPoint,
+    ) -> Result<Option<UnilateralExit>> {
+        Ok(self
+            .load_exits(wallet_id)
+            .await?
+            .into_iter()
+            .find(|exit| exit.outpoint == *outpoint))
+    }
+}
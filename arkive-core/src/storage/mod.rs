@@ -1,10 +1,16 @@
 #![allow(unused_imports)]
+pub mod address_store;
 pub mod boarding_store;
+pub mod exit_store;
+pub mod utxo_store;
 pub mod vtxo_store;
 pub mod wallet_store;
 
+pub use address_store::{AddressRecord, AddressStore};
 pub use boarding_store::{BoardingOutputState, BoardingStore};
-pub use vtxo_store::VtxoStore;
+pub use exit_store::{ExitStage, ExitStore, UnilateralExit};
+pub use utxo_store::{ChainSyncState, UtxoSpend, UtxoStore};
+pub use vtxo_store::{ArkServerParams, VtxoStore};
 pub use wallet_store::WalletStore;
 
 use crate::error::{ArkiveError, Result};
@@ -66,6 +72,17 @@ impl Storage {
             [],
         )?;
 
+        // HD account position of on-chain addresses
+        Self::add_column_if_missing(&conn, "addresses", "keychain", "TEXT")?;
+        Self::add_column_if_missing(&conn, "addresses", "derivation_index", "INTEGER")?;
+        Self::add_column_if_missing(&conn, "addresses", "used", "BOOLEAN DEFAULT FALSE")?;
+
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_addresses_keychain 
+            ON addresses(wallet_id, keychain, derivation_index)",
+            [],
+        )?;
+
         // Tx table
         conn.execute(
             "CREATE TABLE IF NOT EXISTS transactions (
@@ -86,6 +103,10 @@ impl Storage {
             [],
         )?;
 
+        // Block each transaction confirmed in, to detect reorgs
+        Self::add_column_if_missing(&conn, "transactions", "block_height", "INTEGER")?;
+        Self::add_column_if_missing(&conn, "transactions", "block_hash", "TEXT")?;
+
         // Performance indexes
         conn.execute(
             "CREATE INDEX IF NOT EXISTS idx_transactions_wallet_timestamp 
@@ -156,6 +177,9 @@ impl Storage {
             [],
         )?;
 
+        Self::add_column_if_missing(&conn, "boarding_outputs", "block_height", "INTEGER")?;
+        Self::add_column_if_missing(&conn, "boarding_outputs", "block_hash", "TEXT")?;
+
         // Sync metadata table for multi-device sync
         conn.execute(
             "CREATE TABLE IF NOT EXISTS sync_metadata (
@@ -185,6 +209,130 @@ impl Storage {
             [],
         )?;
 
+        // On-chain outputs of the wallet, kept current by incremental sync
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS utxos (
+                wallet_id TEXT NOT NULL,
+                outpoint TEXT NOT NULL,
+                value INTEGER NOT NULL,
+                script_pubkey TEXT NOT NULL,
+                keychain TEXT NOT NULL,
+                derivation_index INTEGER NOT NULL,
+                confirmation_height INTEGER,
+                spent_by TEXT,
+                spent_height INTEGER,
+                FOREIGN KEY (wallet_id) REFERENCES wallets(id),
+                PRIMARY KEY (wallet_id, outpoint)
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_utxos_unspent 
+            ON utxos(wallet_id, spent_by)",
+            [],
+        )?;
+
+        // Chain height and address range covered by the last on-chain sync
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS chain_sync (
+                wallet_id TEXT PRIMARY KEY,
+                height INTEGER NOT NULL,
+                external_index INTEGER NOT NULL,
+                internal_index INTEGER NOT NULL,
+                updated_at INTEGER NOT NULL,
+                FOREIGN KEY (wallet_id) REFERENCES wallets(id)
+            )",
+            [],
+        )?;
+
+        Self::add_column_if_missing(&conn, "chain_sync", "tip_hash", "TEXT")?;
+
+        // Recent blocks the on-chain sync saw, to find the fork point when
+        // some of them are reorganized out
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS chain_blocks (
+                wallet_id TEXT NOT NULL,
+                height INTEGER NOT NULL,
+                block_hash TEXT NOT NULL,
+                PRIMARY KEY (wallet_id, height),
+                FOREIGN KEY (wallet_id) REFERENCES wallets(id)
+            )",
+            [],
+        )?;
+
+        // Ark server parameters from the last connection, needed to exit
+        // without the server
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS ark_server_params (
+                wallet_id TEXT PRIMARY KEY,
+                server_pubkey TEXT NOT NULL,
+                unilateral_exit_delay INTEGER NOT NULL,
+                boarding_exit_delay INTEGER NOT NULL,
+                updated_at INTEGER NOT NULL,
+                FOREIGN KEY (wallet_id) REFERENCES wallets(id)
+            )",
+            [],
+        )?;
+
+        // Progress of unilateral exits
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS vtxo_exits (
+                wallet_id TEXT NOT NULL,
+                outpoint TEXT NOT NULL,
+                amount INTEGER NOT NULL,
+                destination TEXT NOT NULL,
+                server_pubkey TEXT NOT NULL,
+                exit_delay INTEGER NOT NULL,
+                stage TEXT NOT NULL,
+                next_tx INTEGER NOT NULL,
+                confirmed_height INTEGER,
+                confirmed_time INTEGER,
+                claim_txid TEXT,
+                last_error TEXT,
+                started_at INTEGER NOT NULL,
+                updated_at INTEGER NOT NULL,
+                FOREIGN KEY (wallet_id) REFERENCES wallets(id),
+                PRIMARY KEY (wallet_id, outpoint)
+            )",
+            [],
+        )?;
+
+        // On-chain outputs excluded from automatic coin selection
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS frozen_utxos (
+                wallet_id TEXT NOT NULL,
+                outpoint TEXT NOT NULL,
+                frozen_at INTEGER NOT NULL,
+                FOREIGN KEY (wallet_id) REFERENCES wallets(id),
+                PRIMARY KEY (wallet_id, outpoint)
+            )",
+            [],
+        )?;
+
+        Ok(())
+    }
+
+    /// Add a column to a table created by an older version of the schema
+    fn add_column_if_missing(
+        conn: &Connection,
+        table: &str,
+        column: &str,
+        definition: &str,
+    ) -> Result<()> {
+        let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+        let exists = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .filter_map(|name| name.ok())
+            .any(|name| name == column);
+
+        if !exists {
+            conn.execute(
+                &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+                [],
+            )?;
+        }
+
         Ok(())
     }
 
@@ -0,0 +1,328 @@
+use crate::bitcoin::{WalletUtxo, REORG_DEPTH};
+use crate::error::{ArkiveError, Result};
+use crate::storage::Storage;
+use crate::types::Keychain;
+use bitcoin::{Amount, BlockHash, OutPoint, ScriptBuf, TxOut, Txid};
+use chrono::Utc;
+use rusqlite::params;
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// Progress of the incremental on-chain sync
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainSyncState {
+    /// Tip height when the last sync started
+    pub height: u32,
+    /// Hash of the block at `height`, to notice when it is reorganized out
+    pub tip_hash: Option<BlockHash>,
+    /// Last address index scanned on each keychain
+    pub external_index: u32,
+    pub internal_index: u32,
+}
+
+impl ChainSyncState {
+    pub fn last_index(&self, keychain: Keychain) -> u32 {
+        match keychain {
+            Keychain::External => self.external_index,
+            Keychain::Internal => self.internal_index,
+        }
+    }
+}
+
+/// Wallet output spent by a transaction, confirmed at `height` if known
+#[derive(Debug, Clone)]
+pub struct UtxoSpend {
+    pub outpoint: OutPoint,
+    pub spent_by: Txid,
+    pub height: Option<u32>,
+}
+
+pub struct UtxoStore<'a> {
+    storage: &'a Storage,
+}
+
+impl<'a> UtxoStore<'a> {
+    pub fn new(storage: &'a Storage) -> Self {
+        Self { storage }
+    }
+
+    /// Exclude an output from automatic coin selection
+    pub async fn freeze_utxo(&self, wallet_id: &str, outpoint: &OutPoint) -> Result<()> {
+        let conn = self.storage.get_connection().await;
+
+        conn.execute(
+            "INSERT OR IGNORE INTO frozen_utxos (wallet_id, outpoint, frozen_at)
+             VALUES (?1, ?2, ?3)",
+            params![wallet_id, outpoint.to_string(), Utc::now().timestamp()],
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns whether the output was frozen
+    pub async fn unfreeze_utxo(&self, wallet_id: &str, outpoint: &OutPoint) -> Result<bool> {
+        let conn = self.storage.get_connection().await;
+
+        let rows_affected = conn.execute(
+            "DELETE FROM frozen_utxos WHERE wallet_id = ?1 AND outpoint = ?2",
+            params![wallet_id, outpoint.to_string()],
+        )?;
+
+        Ok(rows_affected > 0)
+    }
+
+    pub async fn load_frozen_outpoints(&self, wallet_id: &str) -> Result<HashSet<OutPoint>> {
+        let conn = self.storage.get_connection().await;
+
+        let mut stmt = conn.prepare("SELECT outpoint FROM frozen_utxos WHERE wallet_id = ?1")?;
+        let outpoints = stmt
+            .query_map([wallet_id], |row| row.get::<_, String>(0))?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        outpoints
+            .iter()
+            .map(|outpoint| {
+                OutPoint::from_str(outpoint).map_err(|e| {
+                    ArkiveError::internal(format!("Invalid stored outpoint {}: {}", outpoint, e))
+                })
+            })
+            .collect()
+    }
+
+    /// Unspent outputs as of the last sync
+    pub async fn load_unspent(&self, wallet_id: &str) -> Result<Vec<WalletUtxo>> {
+        let conn = self.storage.get_connection().await;
+
+        let mut stmt = conn.prepare(
+            "SELECT outpoint, value, script_pubkey, keychain, derivation_index, confirmation_height
+             FROM utxos
+             WHERE wallet_id = ?1 AND spent_by IS NULL",
+        )?;
+
+        let rows = stmt
+            .query_map([wallet_id], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, i64>(4)?,
+                    row.get::<_, Option<i64>>(5)?,
+                ))
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        rows.into_iter()
+            .map(
+                |(outpoint, value, script_pubkey, keychain, index, height)| {
+                    Ok(WalletUtxo {
+                        outpoint: OutPoint::from_str(&outpoint).map_err(|e| {
+                            ArkiveError::internal(format!(
+                                "Invalid stored outpoint {}: {}",
+                                outpoint, e
+                            ))
+                        })?,
+                        txout: TxOut {
+                            value: Amount::from_sat(value as u64),
+                            script_pubkey: ScriptBuf::from_hex(&script_pubkey).map_err(|e| {
+                                ArkiveError::internal(format!("Invalid stored script: {}", e))
+                            })?,
+                        },
+                        keychain: serde_json::from_str(&keychain)?,
+                        index: index as u32,
+                        confirmation_height: height.map(|h| h as u32),
+                    })
+                },
+            )
+            .collect()
+    }
+
+    pub async fn load_sync_state(&self, wallet_id: &str) -> Result<Option<ChainSyncState>> {
+        let conn = self.storage.get_connection().await;
+
+        let result = conn.query_row(
+            "SELECT height, tip_hash, external_index, internal_index
+             FROM chain_sync WHERE wallet_id = ?1",
+            [wallet_id],
+            |row| {
+                Ok((
+                    row.get::<_, u32>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, u32>(2)?,
+                    row.get::<_, u32>(3)?,
+                ))
+            },
+        );
+
+        match result {
+            Ok((height, tip_hash, external_index, internal_index)) => Ok(Some(ChainSyncState {
+                height,
+                tip_hash: tip_hash
+                    .map(|hash| {
+                        BlockHash::from_str(&hash).map_err(|e| {
+                            ArkiveError::internal(format!("Invalid stored block hash: {}", e))
+                        })
+                    })
+                    .transpose()?,
+                external_index,
+                internal_index,
+            })),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(ArkiveError::Storage(e)),
+        }
+    }
+
+    /// Apply the outputs and spends found by a sync and record its progress,
+    /// along with the blocks that confirmed them.
+    ///
+    /// Unconfirmed activity is refetched on every sync, so it is dropped
+    /// first; outputs and spends that left the mempool don't linger.
+    pub async fn apply_sync(
+        &self,
+        wallet_id: &str,
+        outputs: &[WalletUtxo],
+        spends: &[UtxoSpend],
+        blocks: &[(u32, BlockHash)],
+        state: &ChainSyncState,
+    ) -> Result<()> {
+        let conn = self.storage.get_connection().await;
+        let tx = conn.unchecked_transaction()?;
+
+        tx.execute(
+            "DELETE FROM utxos WHERE wallet_id = ?1 AND confirmation_height IS NULL",
+            [wallet_id],
+        )?;
+        tx.execute(
+            "UPDATE utxos SET spent_by = NULL
+             WHERE wallet_id = ?1 AND spent_by IS NOT NULL AND spent_height IS NULL",
+            [wallet_id],
+        )?;
+
+        for utxo in outputs {
+            tx.execute(
+                "INSERT INTO utxos
+                 (wallet_id, outpoint, value, script_pubkey, keychain, derivation_index, confirmation_height)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                 ON CONFLICT(wallet_id, outpoint) DO UPDATE SET
+                    confirmation_height = excluded.confirmation_height",
+                params![
+                    wallet_id,
+                    utxo.outpoint.to_string(),
+                    utxo.txout.value.to_sat() as i64,
+                    utxo.txout.script_pubkey.to_hex_string(),
+                    serde_json::to_string(&utxo.keychain)?,
+                    utxo.index as i64,
+                    utxo.confirmation_height,
+                ],
+            )?;
+        }
+
+        for spend in spends {
+            tx.execute(
+                "UPDATE utxos SET spent_by = ?1, spent_height = ?2
+                 WHERE wallet_id = ?3 AND outpoint = ?4",
+                params![
+                    spend.spent_by.to_string(),
+                    spend.height,
+                    wallet_id,
+                    spend.outpoint.to_string(),
+                ],
+            )?;
+        }
+
+        tx.execute(
+            "INSERT INTO chain_sync
+             (wallet_id, height, tip_hash, external_index, internal_index, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT(wallet_id) DO UPDATE SET
+                height = excluded.height,
+                tip_hash = excluded.tip_hash,
+                external_index = excluded.external_index,
+                internal_index = excluded.internal_index,
+                updated_at = excluded.updated_at",
+            params![
+                wallet_id,
+                state.height,
+                state.tip_hash.map(|hash| hash.to_string()),
+                state.external_index,
+                state.internal_index,
+                Utc::now().timestamp(),
+            ],
+        )?;
+
+        let tip = state.tip_hash.map(|hash| (state.height, hash));
+        for (height, hash) in blocks.iter().copied().chain(tip) {
+            tx.execute(
+                "INSERT OR REPLACE INTO chain_blocks (wallet_id, height, block_hash)
+                 VALUES (?1, ?2, ?3)",
+                params![wallet_id, height, hash.to_string()],
+            )?;
+        }
+        // Blocks this deep are taken as final and never checked again
+        tx.execute(
+            "DELETE FROM chain_blocks WHERE wallet_id = ?1 AND height < ?2",
+            params![wallet_id, state.height.saturating_sub(REORG_DEPTH)],
+        )?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Height and hash of the blocks recorded by previous syncs at or above
+    /// `min_height`
+    pub async fn load_blocks(
+        &self,
+        wallet_id: &str,
+        min_height: u32,
+    ) -> Result<Vec<(u32, String)>> {
+        let conn = self.storage.get_connection().await;
+
+        let mut stmt = conn.prepare(
+            "SELECT height, block_hash FROM chain_blocks
+             WHERE wallet_id = ?1 AND height >= ?2",
+        )?;
+        let blocks = stmt
+            .query_map(params![wallet_id, min_height], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Ok(blocks)
+    }
+
+    /// Forget everything learned from blocks at `fork_height` and above,
+    /// which are no longer part of the best chain. Outputs and spends from
+    /// those blocks count as unconfirmed until the next sync fetches them
+    /// again from the fork point.
+    pub async fn rollback(&self, wallet_id: &str, fork_height: u32) -> Result<()> {
+        let conn = self.storage.get_connection().await;
+        let tx = conn.unchecked_transaction()?;
+
+        tx.execute(
+            "UPDATE utxos SET confirmation_height = NULL
+             WHERE wallet_id = ?1 AND confirmation_height >= ?2",
+            params![wallet_id, fork_height],
+        )?;
+        tx.execute(
+            "UPDATE utxos SET spent_height = NULL
+             WHERE wallet_id = ?1 AND spent_height >= ?2",
+            params![wallet_id, fork_height],
+        )?;
+        tx.execute(
+            "DELETE FROM chain_blocks WHERE wallet_id = ?1 AND height >= ?2",
+            params![wallet_id, fork_height],
+        )?;
+        tx.execute(
+            "UPDATE chain_sync SET height = MIN(height, ?1), tip_hash = NULL, updated_at = ?2
+             WHERE wallet_id = ?3",
+            params![
+                fork_height.saturating_sub(1),
+                Utc::now().timestamp(),
+                wallet_id
+            ],
+        )?;
+
+        tx.commit()?;
+        Ok(())
+    }
+}
@@ -17,6 +17,16 @@ pub struct VtxoTreeData {
     pub user_pubkey: String,
 }
 
+/// Server key and timelocks the wallet's VTXOs and boarding outputs are
+/// built with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArkServerParams {
+    pub server_pubkey: String,
+    /// Relative timelocks in consensus encoding
+    pub unilateral_exit_delay: u32,
+    pub boarding_exit_delay: u32,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VtxoState {
     pub outpoint: String,
@@ -25,8 +35,10 @@ pub struct VtxoState {
     pub expiry: DateTime<Utc>,
     pub address: String,
     pub batch_id: String,
-    pub tree_path: Vec<u32>,             // Path to this VTXO in the tree
-    pub exit_transactions: Vec<Vec<u8>>, // Presigned exit path
+    pub tree_path: Vec<u32>, // Path to this VTXO in the tree
+    /// Presigned exit path: consensus-encoded transactions from the one
+    /// spending the commitment transaction down to the one creating the VTXO
+    pub exit_transactions: Vec<Vec<u8>>,
 }
 
 pub struct VtxoStore<'a> {
@@ -78,6 +90,53 @@ impl<'a> VtxoStore<'a> {
         Ok(tree_data)
     }
 
+    pub async fn save_server_params(
+        &self,
+        wallet_id: &str,
+        params: &ArkServerParams,
+    ) -> Result<()> {
+        let conn = self.storage.get_connection().await;
+
+        conn.execute(
+            "INSERT OR REPLACE INTO ark_server_params
+             (wallet_id, server_pubkey, unilateral_exit_delay, boarding_exit_delay, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                wallet_id,
+                params.server_pubkey,
+                params.unilateral_exit_delay,
+                params.boarding_exit_delay,
+                Utc::now().timestamp(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Server parameters from the last connection, if the wallet ever connected
+    pub async fn load_server_params(&self, wallet_id: &str) -> Result<Option<ArkServerParams>> {
+        let conn = self.storage.get_connection().await;
+
+        let result = conn.query_row(
+            "SELECT server_pubkey, unilateral_exit_delay, boarding_exit_delay
+             FROM ark_server_params WHERE wallet_id = ?1",
+            params![wallet_id],
+            |row| {
+                Ok(ArkServerParams {
+                    server_pubkey: row.get(0)?,
+                    unilateral_exit_delay: row.get(1)?,
+                    boarding_exit_delay: row.get(2)?,
+                })
+            },
+        );
+
+        match result {
+            Ok(params) => Ok(Some(params)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(ArkiveError::Storage(e)),
+        }
+    }
+
     /// Save individual VTXO with complete state
     pub async fn save_vtxo_state(&self, wallet_id: &str, vtxo_state: &VtxoState) -> Result<()> {
         let conn = self.storage.get_connection().await;
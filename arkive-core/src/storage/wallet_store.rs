@@ -47,6 +47,21 @@ impl<'a> WalletStore<'a> {
         Ok(())
     }
 
+    pub async fn update_encrypted_seed(
+        &self,
+        wallet_id: &str,
+        encrypted_seed: &[u8],
+    ) -> Result<()> {
+        let conn = self.storage.get_connection().await;
+
+        conn.execute(
+            "UPDATE wallets SET encrypted_seed = ?1 WHERE id = ?2",
+            params![encrypted_seed, wallet_id],
+        )?;
+
+        Ok(())
+    }
+
     pub async fn load_wallet(&self, wallet_id: &str) -> Result<WalletData> {
         let conn = self.storage.get_connection().await;
 
@@ -125,6 +140,27 @@ impl<'a> WalletStore<'a> {
             "DELETE FROM addresses WHERE wallet_id = ?1",
             params![wallet_id],
         )?;
+        conn.execute("DELETE FROM utxos WHERE wallet_id = ?1", params![wallet_id])?;
+        conn.execute(
+            "DELETE FROM frozen_utxos WHERE wallet_id = ?1",
+            params![wallet_id],
+        )?;
+        conn.execute(
+            "DELETE FROM chain_sync WHERE wallet_id = ?1",
+            params![wallet_id],
+        )?;
+        conn.execute(
+            "DELETE FROM chain_blocks WHERE wallet_id = ?1",
+            params![wallet_id],
+        )?;
+        conn.execute(
+            "DELETE FROM vtxo_exits WHERE wallet_id = ?1",
+            params![wallet_id],
+        )?;
+        conn.execute(
+            "DELETE FROM ark_server_params WHERE wallet_id = ?1",
+            params![wallet_id],
+        )?;
         conn.execute("DELETE FROM wallets WHERE id = ?1", params![wallet_id])?;
 
         Ok(())
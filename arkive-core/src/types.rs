@@ -33,11 +33,25 @@ pub struct Transaction {
     pub fee: Option<Amount>,
     pub source: TransactionSource,
     pub ark_round_id: Option<String>,
+    /// Block the transaction confirmed in, for on-chain transactions
+    #[serde(default)]
+    pub block_height: Option<u32>,
+    #[serde(default)]
+    pub block_hash: Option<String>,
+}
+
+impl Transaction {
+    /// Confirmations as of `tip_height`, zero while unconfirmed
+    pub fn confirmations(&self, tip_height: u32) -> u32 {
+        crate::chain::confirmations(self.block_height, tip_height)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TransactionType {
     OnChain,
+    /// On-chain transaction between the wallet's own addresses
+    SelfTransfer,
     Ark,
     Boarding,
     Exit,
@@ -49,6 +63,8 @@ pub enum TransactionStatus {
     Confirmed,
     Failed,
     Spent,
+    /// Superseded by a fee-bumped replacement
+    Replaced,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -64,6 +80,36 @@ pub enum AddressType {
     Boarding,
 }
 
+/// Branch of the on-chain HD account an address is derived from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Keychain {
+    /// Receive addresses handed out to payers
+    External,
+    /// Change addresses for our own transactions
+    Internal,
+}
+
+impl Keychain {
+    /// BIP44 `<chain>` component of the derivation path
+    pub fn index(self) -> u32 {
+        match self {
+            Keychain::External => 0,
+            Keychain::Internal => 1,
+        }
+    }
+}
+
+/// Unspent on-chain output of the wallet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UtxoInfo {
+    pub outpoint: String,
+    pub value: Amount,
+    pub confirmations: u32,
+    pub address: String,
+    /// Excluded from automatic coin selection
+    pub frozen: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VtxoInfo {
     pub outpoint: String,
@@ -79,6 +125,25 @@ pub enum VtxoStatus {
     Confirmed,
     Spent,
     Expired,
+    /// Being exited on chain without the server
+    Exiting,
+}
+
+/// Whether a VTXO could be exited from local data alone, were the Ark
+/// server to disappear
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExitReadiness {
+    pub outpoint: String,
+    pub amount: Amount,
+    pub status: VtxoStatus,
+    /// Everything standing in the way of a unilateral exit
+    pub problems: Vec<String>,
+}
+
+impl ExitReadiness {
+    pub fn is_exit_capable(&self) -> bool {
+        self.problems.is_empty()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -0,0 +1,237 @@
+//! BIP21 payment URIs carrying an Ark address next to the on-chain one
+//!
+//! `bitcoin:<address>?amount=<btc>&ark=<ark address>&label=..&message=..`
+//! lets a payer pick either rail. The on-chain address may be left empty for
+//! Ark-only requests.
+
+use crate::error::{ArkiveError, Result};
+
+use bitcoin::{Amount, Denomination};
+use std::fmt;
+use std::str::FromStr;
+
+const SCHEME: &str = "bitcoin:";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PaymentUri {
+    /// On-chain address, unchecked against any network
+    pub address: Option<String>,
+    /// Ark address from the `ark` parameter
+    pub ark_address: Option<String>,
+    pub amount: Option<Amount>,
+    pub label: Option<String>,
+    pub message: Option<String>,
+}
+
+impl PaymentUri {
+    pub fn new(address: Option<String>, ark_address: Option<String>) -> Self {
+        Self {
+            address,
+            ark_address,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_amount(mut self, amount: Amount) -> Self {
+        self.amount = Some(amount);
+        self
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_message(mut self, message: impl Into<String>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    /// Parse a BIP21 URI, or a bare on-chain or Ark address
+    pub fn parse(s: &str) -> Result<Self> {
+        let s = s.trim();
+
+        let Some(rest) = strip_scheme(s) else {
+            if s.is_empty() {
+                return Err(ArkiveError::InvalidAddress("Empty payment URI".to_string()));
+            }
+            // Ark addresses are bech32m with their own human readable part
+            return Ok(if ark_core::ArkAddress::decode(s).is_ok() {
+                Self::new(None, Some(s.to_string()))
+            } else {
+                Self::new(Some(s.to_string()), None)
+            });
+        };
+
+        let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let mut uri = Self::new(
+            Some(percent_decode(address)?).filter(|a| !a.is_empty()),
+            None,
+        );
+
+        for param in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            let value = percent_decode(value)?;
+
+            match key.to_ascii_lowercase().as_str() {
+                "amount" => {
+                    let amount =
+                        Amount::from_str_in(&value, Denomination::Bitcoin).map_err(|e| {
+                            ArkiveError::InvalidAddress(format!("Invalid amount {}: {}", value, e))
+                        })?;
+                    uri.amount = Some(amount);
+                }
+                "ark" => uri.ark_address = Some(value),
+                "label" => uri.label = Some(value),
+                "message" => uri.message = Some(value),
+                // Required parameters we don't understand make the URI
+                // unusable, other unknown ones are ignored
+                key if key.starts_with("req-") => {
+                    return Err(ArkiveError::InvalidAddress(format!(
+                        "Unsupported required parameter {}",
+                        key
+                    )));
+                }
+                _ => {}
+            }
+        }
+
+        if uri.address.is_none() && uri.ark_address.is_none() {
+            return Err(ArkiveError::InvalidAddress(
+                "Payment URI has neither an on-chain nor an Ark address".to_string(),
+            ));
+        }
+
+        Ok(uri)
+    }
+}
+
+impl FromStr for PaymentUri {
+    type Err = ArkiveError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for PaymentUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", SCHEME)?;
+        if let Some(address) = &self.address {
+            write!(f, "{}", address)?;
+        }
+
+        let mut params = Vec::new();
+        if let Some(amount) = self.amount {
+            params.push(format!(
+                "amount={}",
+                amount.to_string_in(Denomination::Bitcoin)
+            ));
+        }
+        if let Some(ark_address) = &self.ark_address {
+            params.push(format!("ark={}", percent_encode(ark_address)));
+        }
+        if let Some(label) = &self.label {
+            params.push(format!("label={}", percent_encode(label)));
+        }
+        if let Some(message) = &self.message {
+            params.push(format!("message={}", percent_encode(message)));
+        }
+
+        if !params.is_empty() {
+            write!(f, "?{}", params.join("&"))?;
+        }
+        Ok(())
+    }
+}
+
+fn strip_scheme(s: &str) -> Option<&str> {
+    s.get(..SCHEME.len())
+        .filter(|scheme| scheme.eq_ignore_ascii_case(SCHEME))
+        .map(|_| &s[SCHEME.len()..])
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn percent_decode(s: &str) -> Result<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let byte = s
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| {
+                    ArkiveError::InvalidAddress(format!("Invalid percent-encoding in {}", s))
+                })?;
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded)
+        .map_err(|_| ArkiveError::InvalidAddress(format!("Invalid UTF-8 in {}", s)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+
+    #[test]
+    fn test_round_trip() {
+        let uri = PaymentUri::new(Some(ADDRESS.to_string()), Some("tark1example".to_string()))
+            .with_amount(Amount::from_sat(150_000))
+            .with_label("Alice & Bob")
+            .with_message("Lunch 50%");
+
+        let encoded = uri.to_string();
+        assert_eq!(
+            encoded,
+            format!(
+                "bitcoin:{}?amount=0.0015&ark=tark1example&label=Alice%20%26%20Bob&message=Lunch%2050%25",
+                ADDRESS
+            )
+        );
+        assert_eq!(PaymentUri::parse(&encoded).unwrap(), uri);
+    }
+
+    #[test]
+    fn test_parse_variants() {
+        // Scheme is case-insensitive and unknown optional parameters are ignored
+        let uri = PaymentUri::parse(&format!("BITCOIN:{}?foo=bar&amount=1", ADDRESS)).unwrap();
+        assert_eq!(uri.address.as_deref(), Some(ADDRESS));
+        assert_eq!(uri.amount, Some(Amount::ONE_BTC));
+        assert_eq!(uri.ark_address, None);
+
+        // Ark-only request
+        let uri = PaymentUri::parse("bitcoin:?ark=tark1example").unwrap();
+        assert_eq!(uri.address, None);
+        assert_eq!(uri.ark_address.as_deref(), Some("tark1example"));
+
+        // Bare on-chain address
+        let uri = PaymentUri::parse(ADDRESS).unwrap();
+        assert_eq!(uri.address.as_deref(), Some(ADDRESS));
+
+        assert!(PaymentUri::parse(&format!("bitcoin:{}?req-pop=1", ADDRESS)).is_err());
+        assert!(PaymentUri::parse("bitcoin:?label=nothing").is_err());
+        assert!(PaymentUri::parse(&format!("bitcoin:{}?amount=abc", ADDRESS)).is_err());
+    }
+}
@@ -1,6 +1,10 @@
 use crate::error::{ArkiveError, Result};
-use bitcoin::Network;
+use crate::wallet::KeyDerivation;
+use bitcoin::key::CompressedPublicKey;
+use bitcoin::secp256k1::{PublicKey, Secp256k1};
+use bitcoin::{Address, Network};
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 use std::time::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -12,6 +16,135 @@ pub struct WalletConfig {
     pub renewal_threshold: Duration,
     pub fee_policy: FeePolicy,
     pub is_mutinynet: bool,
+    /// How long the wallet stays unlocked before the signing key is erased
+    #[serde(default = "default_auto_lock_timeout")]
+    pub auto_lock_timeout: Option<Duration>,
+    /// Derivation paths of the wallet keys. Configs saved before this field
+    /// existed keep the single legacy key.
+    #[serde(default = "KeyDerivation::legacy")]
+    pub key_derivation: KeyDerivation,
+    /// Consecutive unused addresses to scan past the last used one
+    #[serde(default = "default_gap_limit")]
+    pub gap_limit: u32,
+    /// Script type of on-chain receive and change addresses
+    #[serde(default)]
+    pub onchain_script: OnchainScriptType,
+    /// Source of chain data for the on-chain wallet and the Ark client
+    #[serde(default)]
+    pub chain_backend: ChainBackendConfig,
+    /// Esplora endpoints to fail over to, in order, when `esplora_url` is
+    /// unavailable
+    #[serde(default)]
+    pub esplora_fallback_urls: Vec<String>,
+    #[serde(default)]
+    pub esplora_policy: EsploraPolicy,
+}
+
+fn default_auto_lock_timeout() -> Option<Duration> {
+    Some(Duration::from_secs(900)) // 15 minutes
+}
+
+fn default_gap_limit() -> u32 {
+    20
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OnchainScriptType {
+    /// BIP84 native segwit v0
+    #[default]
+    P2wpkh,
+    /// BIP86 single-key taproot, matching the Ark side's x-only keys
+    P2tr,
+}
+
+impl OnchainScriptType {
+    /// BIP43 purpose of the account the addresses are derived from
+    pub fn purpose(self) -> u32 {
+        match self {
+            OnchainScriptType::P2wpkh => 84,
+            OnchainScriptType::P2tr => 86,
+        }
+    }
+
+    pub fn address(self, public_key: &PublicKey, network: Network) -> Address {
+        match self {
+            OnchainScriptType::P2wpkh => {
+                Address::p2wpkh(&CompressedPublicKey(*public_key), network)
+            }
+            OnchainScriptType::P2tr => {
+                let secp = Secp256k1::verification_only();
+                let (internal_key, _) = public_key.x_only_public_key();
+                Address::p2tr(&secp, internal_key, None, network)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChainBackendConfig {
+    /// The Esplora HTTP API at `esplora_url`
+    #[default]
+    Esplora,
+    /// A Bitcoin Core node's JSON-RPC interface
+    Bitcoind(BitcoindConfig),
+    /// An Electrum server such as electrs or Fulcrum
+    Electrum(ElectrumConfig),
+}
+
+/// Retry and rate limiting behaviour towards Esplora endpoints
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EsploraPolicy {
+    /// Further rounds over every endpoint after the first round fails
+    pub max_retries: u32,
+    /// Delay before the first retry round, doubled for each later one
+    pub retry_backoff: Duration,
+    /// Requests per second sent to any one endpoint
+    pub max_requests_per_second: u32,
+    /// Time after which a request to an unresponsive endpoint is abandoned
+    pub request_timeout: Duration,
+}
+
+impl Default for EsploraPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            retry_backoff: Duration::from_millis(500),
+            max_requests_per_second: 5,
+            request_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BitcoindConfig {
+    pub url: String,
+    pub auth: RpcAuth,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ElectrumConfig {
+    /// `tcp://host:port` or `ssl://host:port`
+    pub url: String,
+    /// Check the server's TLS certificate against its domain. Self-hosted
+    /// servers with self-signed certificates need this off.
+    #[serde(default = "default_validate_domain")]
+    pub validate_domain: bool,
+}
+
+fn default_validate_domain() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RpcAuth {
+    None,
+    UserPass {
+        username: String,
+        password: String,
+    },
+    /// The node's `.cookie` file, read on every request so node restarts
+    /// are picked up
+    CookieFile(PathBuf),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,7 +153,7 @@ pub struct FeePolicy {
     pub max_fee_rate: u64, // sat/vB
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum FeePriority {
     Slow,
     Normal,
@@ -28,6 +161,18 @@ pub enum FeePriority {
     Fastest,
 }
 
+impl FeePriority {
+    /// Number of blocks within which a transaction should confirm
+    pub fn confirmation_target(&self) -> u16 {
+        match self {
+            FeePriority::Slow => 144,
+            FeePriority::Normal => 6,
+            FeePriority::Fast => 3,
+            FeePriority::Fastest => 1,
+        }
+    }
+}
+
 impl Default for WalletConfig {
     fn default() -> Self {
         Self {
@@ -41,6 +186,13 @@ impl Default for WalletConfig {
                 max_fee_rate: 100, // 100 sat/vB
             },
             is_mutinynet: false,
+            auto_lock_timeout: default_auto_lock_timeout(),
+            key_derivation: KeyDerivation::for_network(Network::Regtest),
+            gap_limit: default_gap_limit(),
+            onchain_script: OnchainScriptType::default(),
+            chain_backend: ChainBackendConfig::default(),
+            esplora_fallback_urls: Vec::new(),
+            esplora_policy: EsploraPolicy::default(),
         }
     }
 }
@@ -58,6 +210,7 @@ impl WalletConfig {
         let mut config = WalletConfig {
             network,
             is_mutinynet,
+            key_derivation: KeyDerivation::for_network(network),
             ..Default::default()
         };
 
@@ -68,6 +221,8 @@ impl WalletConfig {
             }
             (Network::Signet, false) => {
                 config.esplora_url = "https://mempool.space/signet/api".to_string();
+                config.esplora_fallback_urls =
+                    vec!["https://blockstream.info/signet/api".to_string()];
                 config.ark_server_url = "https://signet.arkade.sh".to_string();
             }
             (Network::Regtest, _) => {
@@ -82,19 +237,70 @@ impl WalletConfig {
         config
     }
 
+    /// Use `script_type` for on-chain addresses, deriving them from the
+    /// matching BIP44-style account. Only meaningful before the wallet is
+    /// created, as it changes the wallet's keys.
+    pub fn with_onchain_script(mut self, script_type: OnchainScriptType) -> Self {
+        self.onchain_script = script_type;
+        self.key_derivation = KeyDerivation::new(self.network, script_type);
+        self
+    }
+
+    /// Primary Esplora endpoint followed by the fallbacks
+    pub fn esplora_urls(&self) -> Vec<String> {
+        std::iter::once(self.esplora_url.clone())
+            .chain(self.esplora_fallback_urls.iter().cloned())
+            .collect()
+    }
+
+    pub fn with_esplora_fallbacks(mut self, urls: Vec<String>) -> Self {
+        self.esplora_fallback_urls = urls;
+        self
+    }
+
+    pub fn with_chain_backend(mut self, chain_backend: ChainBackendConfig) -> Self {
+        self.chain_backend = chain_backend;
+        self
+    }
+
     pub fn validate(&self) -> Result<()> {
         if self.ark_server_url.is_empty() {
             return Err(ArkiveError::config("Ark server URL cannot be empty"));
         }
 
-        if self.esplora_url.is_empty() {
-            return Err(ArkiveError::config("Esplora URL cannot be empty"));
+        match &self.chain_backend {
+            ChainBackendConfig::Esplora if self.esplora_url.is_empty() => {
+                return Err(ArkiveError::config("Esplora URL cannot be empty"));
+            }
+            ChainBackendConfig::Bitcoind(rpc) if rpc.url.is_empty() => {
+                return Err(ArkiveError::config("bitcoind RPC URL cannot be empty"));
+            }
+            ChainBackendConfig::Electrum(electrum) if electrum.url.is_empty() => {
+                return Err(ArkiveError::config("Electrum server URL cannot be empty"));
+            }
+            _ => {}
+        }
+
+        if self.esplora_fallback_urls.iter().any(|url| url.is_empty()) {
+            return Err(ArkiveError::config("Esplora fallback URLs cannot be empty"));
+        }
+
+        if self.esplora_policy.max_requests_per_second == 0 {
+            return Err(ArkiveError::config(
+                "Esplora request rate must be greater than 0",
+            ));
         }
 
         if self.fee_policy.max_fee_rate == 0 {
             return Err(ArkiveError::config("Max fee rate must be greater than 0"));
         }
 
+        if self.gap_limit == 0 {
+            return Err(ArkiveError::config("Gap limit must be greater than 0"));
+        }
+
+        self.key_derivation.validate()?;
+
         Ok(())
     }
 }
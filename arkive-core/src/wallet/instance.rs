@@ -1,20 +1,29 @@
 use crate::ark::ArkService;
-use crate::bitcoin::BitcoinService;
+use crate::bitcoin::{BitcoinService, FeeQuote};
+use crate::chain;
 use crate::error::{ArkiveError, Result};
-use crate::storage::Storage;
-use crate::types::{Address, AddressType, Balance, Transaction, VtxoInfo};
-use crate::wallet::WalletConfig;
+use crate::storage::{AddressRecord, Storage, UnilateralExit, WalletStore};
+use crate::types::{Address, AddressType, Balance, ExitReadiness, Transaction, UtxoInfo, VtxoInfo};
+use crate::uri::PaymentUri;
+use crate::wallet::renewal::{RENEWAL_ATTEMPTS, RENEWAL_RETRY_DELAY};
+use crate::wallet::{
+    decrypt_seed, FeePriority, KeySession, RenewalOutcome, RenewalReport, WalletConfig, WalletKeys,
+    WalletPublicKeys,
+};
 
 use ark_core::ArkAddress;
-use bitcoin::key::Keypair;
-use bitcoin::{Amount, Network};
+use bitcoin::{Amount, FeeRate, Network, OutPoint};
+use std::collections::HashSet;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 #[allow(dead_code)]
 pub struct ArkWallet {
     id: String,
     name: String,
-    keypair: Keypair,
+    session: KeySession,
+    public_keys: WalletPublicKeys,
     config: WalletConfig,
     bitcoin_service: BitcoinService,
     ark_service: ArkService,
@@ -25,20 +34,39 @@ impl ArkWallet {
     pub async fn new(
         id: String,
         name: String,
-        keypair: Keypair,
+        keys: WalletKeys,
         config: WalletConfig,
         storage: Arc<Storage>,
     ) -> Result<Self> {
-        let bitcoin_service =
-            BitcoinService::new(keypair, config.clone(), storage.clone(), id.clone()).await?;
-
-        let ark_service =
-            ArkService::new(keypair, config.clone(), storage.clone(), id.clone()).await?;
+        let public_keys = keys.public_keys();
+        let session = KeySession::unlocked(keys, config.auto_lock_timeout);
+        let chain = chain::from_config(&config)?;
+
+        let bitcoin_service = BitcoinService::new(
+            session.clone(),
+            public_keys,
+            config.clone(),
+            chain.clone(),
+            storage.clone(),
+            id.clone(),
+        )
+        .await?;
+
+        let ark_service = ArkService::new(
+            session.clone(),
+            public_keys,
+            config.clone(),
+            chain,
+            storage.clone(),
+            id.clone(),
+        )
+        .await?;
 
         Ok(Self {
             id,
             name,
-            keypair,
+            session,
+            public_keys,
             config,
             bitcoin_service,
             ark_service,
@@ -63,6 +91,15 @@ impl ArkWallet {
         self.config.is_mutinynet
     }
 
+    pub fn config(&self) -> &WalletConfig {
+        &self.config
+    }
+
+    /// Public on-chain and Ark keys, available while locked
+    pub fn public_keys(&self) -> WalletPublicKeys {
+        self.public_keys
+    }
+
     pub fn network_display(&self) -> String {
         if self.config.is_mutinynet {
             "Mutinynet".to_string()
@@ -71,7 +108,59 @@ impl ArkWallet {
         }
     }
 
+    // Session management
+    /// Decrypt the seed and keep the signing key in memory for `timeout`,
+    /// or until [`ArkWallet::lock`] is called. `None` keeps it unlocked
+    /// indefinitely.
+    pub async fn unlock(&self, passphrase: &str, timeout: Option<Duration>) -> Result<()> {
+        self.unlock_with_bip39(passphrase, None, timeout).await
+    }
+
+    /// Unlock a wallet whose BIP39 passphrase was not persisted
+    pub async fn unlock_with_bip39(
+        &self,
+        passphrase: &str,
+        bip39_passphrase: Option<&str>,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        let wallet_store = WalletStore::new(&self.storage);
+        let wallet_data = wallet_store.load_wallet(&self.id).await?;
+
+        let seed_secret = decrypt_seed(&wallet_data.encrypted_seed, passphrase)?;
+        let keys = seed_secret.keys(
+            wallet_data.network,
+            &self.config.key_derivation,
+            bip39_passphrase,
+        )?;
+        if keys.public_keys() != self.public_keys {
+            return Err(ArkiveError::internal(
+                "Decrypted seed does not match this wallet",
+            ));
+        }
+
+        self.session.unlock(keys, timeout);
+
+        if let Err(e) = self.ark_service.connect().await {
+            tracing::warn!("Failed to connect to Ark server: {}", e);
+        }
+
+        tracing::info!("Unlocked wallet '{}'", self.name);
+        Ok(())
+    }
+
+    /// Erase the signing key. Read-only operations keep working from local data.
+    pub fn lock(&self) {
+        self.session.lock();
+        self.ark_service.disconnect();
+        tracing::info!("Locked wallet '{}'", self.name);
+    }
+
+    pub fn is_locked(&self) -> bool {
+        !self.session.is_unlocked()
+    }
+
     // Address generation
+    /// Next unused on-chain receive address
     pub async fn get_onchain_address(&self) -> Result<Address> {
         let address = self.bitcoin_service.get_address().await?;
         Ok(Address {
@@ -80,6 +169,20 @@ impl ArkWallet {
         })
     }
 
+    /// Derive a fresh on-chain receive address, even if earlier ones are unused
+    pub async fn new_onchain_address(&self) -> Result<Address> {
+        let address = self.bitcoin_service.new_address().await?;
+        Ok(Address {
+            address,
+            address_type: AddressType::OnChain,
+        })
+    }
+
+    /// On-chain addresses revealed so far with their derivation paths
+    pub async fn list_onchain_addresses(&self) -> Result<Vec<AddressRecord>> {
+        self.bitcoin_service.list_addresses().await
+    }
+
     pub async fn get_ark_address(&self) -> Result<Address> {
         let address = self.ark_service.get_address().await?;
         Ok(Address {
@@ -96,6 +199,26 @@ impl ArkWallet {
         })
     }
 
+    /// BIP21 URI with the next unused on-chain address and the Ark address,
+    /// so the payer can pick either rail
+    pub async fn payment_uri(
+        &self,
+        amount: Option<Amount>,
+        label: Option<String>,
+        message: Option<String>,
+    ) -> Result<PaymentUri> {
+        let address = self.bitcoin_service.get_address().await?;
+        let ark_address = self.ark_service.get_address().await?;
+
+        Ok(PaymentUri {
+            address: Some(address),
+            ark_address: Some(ark_address),
+            amount,
+            label,
+            message,
+        })
+    }
+
     // Balance operations
     pub async fn balance(&self) -> Result<Balance> {
         let onchain_balance = self.bitcoin_service.get_balance().await?;
@@ -108,16 +231,92 @@ impl ArkWallet {
         self.bitcoin_service.get_balance().await
     }
 
+    /// On-chain balance without frozen outputs, i.e. what sending without
+    /// explicit inputs can spend
+    pub async fn unfrozen_onchain_balance(&self) -> Result<Amount> {
+        self.bitcoin_service.get_unfrozen_balance().await
+    }
+
+    /// Bring the local on-chain UTXO set up to date with the chain
+    pub async fn sync_onchain(&self) -> Result<()> {
+        self.bitcoin_service.sync().await
+    }
+
+    /// Current chain height, for confirmation counts of transactions and
+    /// boarding outputs
+    pub async fn tip_height(&self) -> Result<u32> {
+        self.bitcoin_service.tip_height().await
+    }
+
+    pub async fn list_utxos(&self) -> Result<Vec<UtxoInfo>> {
+        self.bitcoin_service.list_utxos().await
+    }
+
+    /// Keep an on-chain output out of automatic coin selection
+    pub async fn freeze_utxo(&self, outpoint: &str) -> Result<()> {
+        self.bitcoin_service.freeze_utxo(outpoint).await
+    }
+
+    pub async fn unfreeze_utxo(&self, outpoint: &str) -> Result<()> {
+        self.bitcoin_service.unfreeze_utxo(outpoint).await
+    }
+
     pub async fn ark_balance(&self) -> Result<(Amount, Amount)> {
         self.ark_service.get_balance().await
     }
 
     // Tx operations
     pub async fn send_onchain(&self, address: &str, amount: Amount) -> Result<String> {
-        self.bitcoin_service.send(address, amount).await
+        self.session.ensure_unlocked()?;
+        self.bitcoin_service.send(address, amount, None).await
+    }
+
+    /// Pay several on-chain recipients from one transaction with a single
+    /// change output
+    pub async fn send_onchain_batch(&self, recipients: &[(String, Amount)]) -> Result<String> {
+        self.session.ensure_unlocked()?;
+        self.bitcoin_service.send_batch(recipients).await
+    }
+
+    /// Empty the on-chain wallet into `address`: every unfrozen output worth
+    /// more than its own fee is spent and the fee is deducted from the
+    /// amount sent. Returns the txid and the amount the recipient receives.
+    pub async fn send_onchain_max(&self, address: &str) -> Result<(String, Amount)> {
+        self.session.ensure_unlocked()?;
+        self.bitcoin_service.send_max(address).await
+    }
+
+    /// Send on-chain spending exactly `inputs`, including any change back to
+    /// the wallet. Duplicate inputs are spent once, frozen ones are refused.
+    pub async fn send_onchain_with_inputs(
+        &self,
+        address: &str,
+        amount: Amount,
+        inputs: &[OutPoint],
+    ) -> Result<String> {
+        self.session.ensure_unlocked()?;
+        self.bitcoin_service
+            .send(address, amount, Some(inputs))
+            .await
+    }
+
+    /// Replace an unconfirmed on-chain send with one paying `new_fee_rate`,
+    /// returning the replacement's txid
+    pub async fn bump_fee(&self, txid: &str, new_fee_rate: FeeRate) -> Result<String> {
+        self.session.ensure_unlocked()?;
+        self.bitcoin_service.bump_fee(txid, new_fee_rate).await
+    }
+
+    /// Accelerate an unconfirmed incoming transaction with a child paying
+    /// `fee_rate` for both, returning the child's txid
+    pub async fn cpfp(&self, parent_txid: &str, fee_rate: FeeRate) -> Result<String> {
+        self.session.ensure_unlocked()?;
+        self.bitcoin_service.cpfp(parent_txid, fee_rate).await
     }
 
     pub async fn send_ark(&self, address: &str, amount: Amount) -> Result<String> {
+        self.session.ensure_unlocked()?;
+
         let ark_address = ArkAddress::decode(address)
             .map_err(|e| ArkiveError::InvalidAddress(format!("Invalid Ark address: {}", e)))?;
 
@@ -133,26 +332,226 @@ impl ArkWallet {
         self.ark_service.send(ark_address, amount).await
     }
 
+    /// Move `amount` from Ark to an on-chain address in the next round,
+    /// with the server's cooperation. All spendable VTXOs may go into the
+    /// round, the change coming back as a new VTXO less the server's fee.
+    /// Returns the round's commitment txid.
+    pub async fn offboard(&self, onchain_address: &str, amount: Amount) -> Result<String> {
+        self.session.ensure_unlocked()?;
+
+        let address = bitcoin::Address::from_str(onchain_address)
+            .map_err(|e| ArkiveError::InvalidAddress(format!("{}: {}", onchain_address, e)))?
+            .require_network(self.config.network)
+            .map_err(|e| ArkiveError::InvalidAddress(format!("{}: {}", onchain_address, e)))?;
+
+        self.ark_service.offboard(address, amount).await
+    }
+
+    /// Rough fee the Ark server charges for offboarding `amount`
+    pub async fn estimate_offboard_fee(&self, amount: Amount) -> Result<Amount> {
+        self.ark_service.estimate_fee(amount).await
+    }
+
+    /// Pay a BIP21 URI or bare address. `amount` is needed when the URI
+    /// carries none and must match it otherwise. Ark is used when the URI
+    /// has an Ark address and the confirmed Ark balance covers the amount,
+    /// on-chain otherwise. Returns the rail used and the txid.
+    pub async fn send_to_uri(
+        &self,
+        uri: &str,
+        amount: Option<Amount>,
+    ) -> Result<(AddressType, String)> {
+        self.session.ensure_unlocked()?;
+
+        let uri = PaymentUri::parse(uri)?;
+        let amount = match (uri.amount, amount) {
+            (Some(requested), Some(amount)) if requested != amount => {
+                return Err(ArkiveError::config(format!(
+                    "Amount {} sats differs from the {} sats requested by the URI",
+                    amount.to_sat(),
+                    requested.to_sat()
+                )))
+            }
+            (Some(amount), _) | (None, Some(amount)) => amount,
+            (None, None) => {
+                return Err(ArkiveError::config(
+                    "The payment URI has no amount, specify one",
+                ))
+            }
+        };
+
+        if let Some(ark_address) = &uri.ark_address {
+            let (confirmed, _) = self.ark_service.get_balance().await?;
+            if confirmed >= amount || uri.address.is_none() {
+                let txid = self.send_ark(ark_address, amount).await?;
+                return Ok((AddressType::Ark, txid));
+            }
+            tracing::info!(
+                "Ark balance of {} sats can't cover {} sats, paying on-chain",
+                confirmed.to_sat(),
+                amount.to_sat()
+            );
+        }
+
+        let address = uri
+            .address
+            .ok_or_else(|| ArkiveError::InvalidAddress("No on-chain address".to_string()))?;
+        let txid = self.bitcoin_service.send(&address, amount, None).await?;
+        Ok((AddressType::OnChain, txid))
+    }
+
     // VTXO operations
     pub async fn list_vtxos(&self) -> Result<Vec<VtxoInfo>> {
         self.ark_service.list_vtxos().await
     }
 
     pub async fn participate_in_round(&self) -> Result<Option<String>> {
+        self.session.ensure_unlocked()?;
         self.ark_service.participate_in_round().await
     }
 
+    /// Settle VTXOs expiring within the configured renewal threshold into
+    /// fresh ones in the next round, retrying failed attempts with backoff.
+    /// The round settles every spendable VTXO and boarding output, not only
+    /// the expiring ones, so the report lists what it actually took.
+    pub async fn renew_expiring_vtxos(&self) -> RenewalReport {
+        let mut report = RenewalReport::new(&self.name);
+
+        let expiring = match self
+            .ark_service
+            .get_renewable_vtxos(self.config.renewal_threshold)
+            .await
+        {
+            Ok(expiring) => expiring,
+            Err(e) => {
+                report.outcome = RenewalOutcome::Failed(e.to_string());
+                return report;
+            }
+        };
+        if expiring.is_empty() {
+            return report;
+        }
+        report.expiring = expiring.into_iter().map(|vtxo| vtxo.outpoint).collect();
+
+        if self.is_locked() {
+            tracing::warn!(
+                "{} VTXOs of locked wallet '{}' are about to expire",
+                report.expiring.len(),
+                self.name
+            );
+            report.outcome = RenewalOutcome::Skipped("Wallet is locked".to_string());
+            return report;
+        }
+
+        let mut delay = RENEWAL_RETRY_DELAY;
+        for attempt in 1..=RENEWAL_ATTEMPTS {
+            report.attempts = attempt;
+
+            let before = self.ark_service.settleable_outpoints().await;
+            let error = match self.ark_service.renew_vtxos().await {
+                Ok(Some(round_txid)) => {
+                    // Whatever was settleable before and no longer is went
+                    // into the round
+                    if let (Ok((vtxos, boarding)), Ok((vtxos_left, boarding_left))) =
+                        (before, self.ark_service.settleable_outpoints().await)
+                    {
+                        report.settled = vtxos.difference(&vtxos_left).cloned().collect();
+                        report.boarded = boarding.difference(&boarding_left).cloned().collect();
+                        report.settled.sort();
+                        report.boarded.sort();
+                    }
+
+                    tracing::info!(
+                        "Renewed {} expiring VTXOs of wallet '{}' in round {}, settling {} VTXOs and {} boarding outputs",
+                        report.expiring.len(),
+                        self.name,
+                        round_txid,
+                        report.settled.len(),
+                        report.boarded.len()
+                    );
+                    report.outcome = RenewalOutcome::Renewed { round_txid };
+                    return report;
+                }
+                Ok(None) => "Server did not settle the VTXOs".to_string(),
+                Err(e) => e.to_string(),
+            };
+
+            tracing::warn!(
+                "Renewal attempt {} for wallet '{}' failed: {}",
+                attempt,
+                self.name,
+                error
+            );
+            report.outcome = RenewalOutcome::Failed(error);
+
+            if attempt < RENEWAL_ATTEMPTS {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+
+        report
+    }
+
+    /// Exit VTXOs on chain from their presigned transactions, without the
+    /// Ark server. Each is swept to a wallet address once its exit delay
+    /// has passed, which takes further calls to [`Self::resume_exits`] or
+    /// [`Self::sync`]. Fees of tree transactions with an anchor output are
+    /// paid by children funded from confirmed on-chain coins.
+    pub async fn unilateral_exit(&self, outpoints: &[String]) -> Result<Vec<UnilateralExit>> {
+        self.session.ensure_unlocked()?;
+
+        let outpoints = outpoints
+            .iter()
+            .map(|outpoint| crate::bitcoin::parse_outpoint(outpoint))
+            .collect::<Result<Vec<_>>>()?;
+        let destination = self.bitcoin_service.get_address().await?;
+
+        self.ark_service
+            .start_unilateral_exit(&outpoints, &destination)
+            .await?;
+        self.resume_exits().await
+    }
+
+    /// Take unfinished exits as far as the chain allows right now
+    pub async fn resume_exits(&self) -> Result<Vec<UnilateralExit>> {
+        self.ark_service.advance_exits(&self.bitcoin_service).await
+    }
+
+    pub async fn list_exits(&self) -> Result<Vec<UnilateralExit>> {
+        self.ark_service.list_exits().await
+    }
+
+    /// Check, per unspent VTXO, whether it could be exited without the Ark
+    /// server from what is stored locally
+    pub async fn verify_exit_readiness(&self) -> Result<Vec<ExitReadiness>> {
+        self.ark_service.verify_exit_readiness().await
+    }
+
     // Tx history
     pub async fn transaction_history(&self) -> Result<Vec<Transaction>> {
-        let mut transactions = Vec::new();
+        let boarding_script = self
+            .ark_service
+            .get_boarding_address()
+            .await
+            .ok()
+            .and_then(|address| bitcoin::Address::from_str(&address).ok())
+            .map(|address| address.assume_checked().script_pubkey());
 
         // Get onchain tx
-        let onchain_txs = self.bitcoin_service.get_transaction_history().await?;
-        transactions.extend(onchain_txs);
+        let mut transactions = self
+            .bitcoin_service
+            .get_transaction_history(boarding_script.as_deref())
+            .await?;
 
-        // Get Ark tx
+        // Get Ark tx, which also reads back the on-chain records
+        let mut seen: HashSet<String> = transactions.iter().map(|tx| tx.txid.clone()).collect();
         let ark_txs = self.ark_service.get_transaction_history().await?;
-        transactions.extend(ark_txs);
+        transactions.extend(
+            ark_txs
+                .into_iter()
+                .filter(|tx| seen.insert(tx.txid.clone())),
+        );
 
         // Sort by timestamp
         transactions.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
@@ -165,6 +564,9 @@ impl ArkWallet {
         // Sync both services
         self.bitcoin_service.sync().await?;
         self.ark_service.sync().await?;
+        self.ark_service
+            .advance_exits(&self.bitcoin_service)
+            .await?;
 
         // Cleanup expired VTXOs after sync
         let cleaned = self.cleanup_expired_data().await?;
@@ -180,6 +582,34 @@ impl ArkWallet {
         self.bitcoin_service.estimate_fee(address, amount).await
     }
 
+    /// Quote an on-chain send at `priority`, or the wallet's default priority
+    pub async fn quote_onchain_fee(
+        &self,
+        address: &str,
+        amount: Amount,
+        priority: Option<FeePriority>,
+    ) -> Result<FeeQuote> {
+        let priority = priority.unwrap_or(self.config.fee_policy.default_priority);
+        self.bitcoin_service
+            .quote_fee(address, amount, priority)
+            .await
+    }
+
+    /// Quote [`Self::send_onchain_batch`] at the wallet's default priority
+    pub async fn quote_onchain_batch(&self, recipients: &[(String, Amount)]) -> Result<FeeQuote> {
+        self.bitcoin_service
+            .quote_batch(recipients, self.config.fee_policy.default_priority)
+            .await
+    }
+
+    /// Quote [`Self::send_onchain_max`] at the wallet's default priority,
+    /// returning the amount that would be sent and its fee
+    pub async fn quote_onchain_max(&self, address: &str) -> Result<(Amount, FeeQuote)> {
+        self.bitcoin_service
+            .quote_send_max(address, self.config.fee_policy.default_priority)
+            .await
+    }
+
     pub async fn estimate_ark_fee(&self, amount: Amount) -> Result<Amount> {
         self.ark_service.estimate_fee(amount).await
     }
@@ -0,0 +1,148 @@
+use crate::error::{ArkiveError, Result};
+use crate::wallet::OnchainScriptType;
+use bitcoin::bip32::{DerivationPath, Xpriv, Xpub};
+use bitcoin::key::Keypair;
+use bitcoin::secp256k1::{PublicKey, Secp256k1};
+use bitcoin::Network;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Path used by every wallet created before per-network derivation existed.
+/// The same key was used on-chain and for Ark.
+const LEGACY_PATH: &str = "m/84'/0'/0'/0/0";
+
+/// Non-BIP purpose reserved for Ark (VTXO owner) keys, so they never collide
+/// with an on-chain BIP44/49/84/86 account
+pub const ARK_PURPOSE: u32 = 350;
+
+/// Derivation paths of a wallet's keys, recorded in its config so a wallet
+/// keeps deriving the same keys when the defaults change
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyDerivation {
+    /// Path of the on-chain key
+    pub onchain_path: String,
+    /// Path of the Ark (VTXO owner) key
+    pub ark_path: String,
+}
+
+impl KeyDerivation {
+    /// Standard paths for `network`: BIP84 for on-chain, [`ARK_PURPOSE`] for Ark
+    pub fn for_network(network: Network) -> Self {
+        Self::new(network, OnchainScriptType::P2wpkh)
+    }
+
+    /// Standard paths for `network` with the on-chain account matching `script_type`
+    pub fn new(network: Network, script_type: OnchainScriptType) -> Self {
+        let coin_type = coin_type(network);
+        Self {
+            onchain_path: format!("m/{}'/{}'/0'/0/0", script_type.purpose(), coin_type),
+            ark_path: format!("m/{}'/{}'/0'/0/0", ARK_PURPOSE, coin_type),
+        }
+    }
+
+    /// Paths of wallets created before derivation was recorded
+    pub fn legacy() -> Self {
+        Self {
+            onchain_path: LEGACY_PATH.to_string(),
+            ark_path: LEGACY_PATH.to_string(),
+        }
+    }
+
+    /// Account path of the on-chain HD account: `onchain_path` without its
+    /// trailing `<chain>/<index>`, so the recorded key is receive address 0
+    pub fn onchain_account_path(&self) -> Result<DerivationPath> {
+        let path = parse_path(&self.onchain_path)?;
+        let depth = path.len();
+        if depth < 2 || path[depth - 2].is_hardened() || path[depth - 1].is_hardened() {
+            return Err(ArkiveError::config(format!(
+                "On-chain derivation path {} must end in a non-hardened <chain>/<index>",
+                self.onchain_path
+            )));
+        }
+
+        Ok(DerivationPath::from(&path[..depth - 2]))
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.onchain_account_path()?;
+        parse_path(&self.ark_path)?;
+        Ok(())
+    }
+}
+
+/// SLIP-44 coin type: 0 for mainnet, 1 for every test network
+pub fn coin_type(network: Network) -> u32 {
+    match network {
+        Network::Bitcoin => 0,
+        _ => 1,
+    }
+}
+
+/// Signing keys of an unlocked wallet. Neither `Clone` nor `Copy`, so the
+/// only copy is the one erased when it is dropped.
+pub struct WalletKeys {
+    pub onchain: Keypair,
+    pub ark: Keypair,
+    /// On-chain HD account, parent of every receive and change key
+    pub onchain_account: Xpriv,
+}
+
+impl WalletKeys {
+    pub fn derive(master_key: &Xpriv, derivation: &KeyDerivation) -> Result<Self> {
+        let secp = Secp256k1::new();
+        let onchain_account = master_key
+            .derive_priv(&secp, &derivation.onchain_account_path()?)
+            .map_err(|e| ArkiveError::internal(format!("Failed to derive account key: {}", e)))?;
+
+        Ok(Self {
+            onchain: derive_keypair(master_key, &derivation.onchain_path)?,
+            ark: derive_keypair(master_key, &derivation.ark_path)?,
+            onchain_account,
+        })
+    }
+
+    pub fn public_keys(&self) -> WalletPublicKeys {
+        let secp = Secp256k1::new();
+        WalletPublicKeys {
+            onchain: self.onchain.public_key(),
+            ark: self.ark.public_key(),
+            onchain_account: Xpub::from_priv(&secp, &self.onchain_account),
+        }
+    }
+
+    fn erase(&mut self) {
+        self.onchain.non_secure_erase();
+        self.ark.non_secure_erase();
+        self.onchain_account.private_key.non_secure_erase();
+    }
+}
+
+impl Drop for WalletKeys {
+    fn drop(&mut self) {
+        self.erase();
+    }
+}
+
+/// Public halves of [`WalletKeys`], available while the wallet is locked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalletPublicKeys {
+    pub onchain: PublicKey,
+    pub ark: PublicKey,
+    pub onchain_account: Xpub,
+}
+
+fn parse_path(path: &str) -> Result<DerivationPath> {
+    DerivationPath::from_str(path)
+        .map_err(|e| ArkiveError::config(format!("Invalid derivation path {}: {}", path, e)))
+}
+
+fn derive_keypair(master_key: &Xpriv, path: &str) -> Result<Keypair> {
+    let secp = Secp256k1::new();
+    let path = parse_path(path)?;
+
+    let child_key = master_key
+        .derive_priv(&secp, &path)
+        .map_err(|e| ArkiveError::internal(format!("Failed to derive child key: {}", e)))?;
+
+    Ok(child_key.to_keypair(&secp))
+}
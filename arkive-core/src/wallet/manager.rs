@@ -1,15 +1,28 @@
 use crate::error::{ArkiveError, Result};
 use crate::storage::wallet_store::WalletData;
 use crate::storage::{Storage, WalletStore};
-use crate::wallet::{generate_mnemonic, mnemonic_to_keypair, ArkWallet, WalletConfig};
+use crate::wallet::{
+    decrypt_seed, encrypt_seed, generate_mnemonic, ArkWallet, Bip39Passphrase, KeyDerivation,
+    RenewalScheduler, SeedSecret, WalletConfig,
+};
+use bip39::{Language, Mnemonic};
 use bitcoin::Network;
 use chrono::Utc;
 use parking_lot::RwLock;
 use std::collections::HashMap;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 use uuid::Uuid;
 
+/// Wallet listing entry that can be produced without decrypting the seed
+#[derive(Debug, Clone)]
+pub struct WalletSummary {
+    pub name: String,
+    pub network_display: String,
+    pub seed_encrypted: bool,
+}
+
 pub struct WalletManager {
     storage: Arc<Storage>,
     wallets: Arc<RwLock<HashMap<String, Arc<ArkWallet>>>>,
@@ -30,114 +43,51 @@ impl WalletManager {
         &self,
         name: &str,
         network: Network,
+        passphrase: &str,
+        bip39_passphrase: Option<&Bip39Passphrase>,
     ) -> Result<(Arc<ArkWallet>, String)> {
-        // Check if wallet already exists
-        let wallet_store = WalletStore::new(&self.storage);
-        if wallet_store.wallet_exists(name).await? {
-            return Err(ArkiveError::config(format!(
-                "Wallet '{}' already exists",
-                name
-            )));
-        }
-
-        // Generate mnemonic and keypair
-        let mnemonic = generate_mnemonic()?;
-        let keypair = mnemonic_to_keypair(&mnemonic, network)?;
-
-        // Create wallet config
-        let config = WalletConfig::new(network);
-        config.validate()?;
-
-        // Create wallet data
-        let wallet_id = Uuid::new_v4().to_string();
-        let wallet_data = WalletData {
-            id: wallet_id.clone(),
-            name: name.to_string(),
-            network,
-            created_at: Utc::now(),
-            encrypted_seed: self.encrypt_seed(&mnemonic)?,
-            config: Some(serde_json::to_string(&config)?),
-            is_mutinynet: false,
-        };
-
-        // Save to storage
-        wallet_store.save_wallet(&wallet_data).await?;
-
-        // Create wallet instance
-        let wallet = Arc::new(
-            ArkWallet::new(
-                wallet_id.clone(),
-                name.to_string(),
-                keypair,
-                config,
-                self.storage.clone(),
-            )
-            .await?,
-        );
-
-        // Cache the wallet
-        {
-            let mut wallets = self.wallets.write();
-            wallets.insert(wallet_id, wallet.clone());
-        }
-
-        tracing::info!("Created wallet '{}' with ID: {}", name, wallet.id());
-        Ok((wallet, mnemonic))
+        self.create_wallet_with_config(
+            name,
+            WalletConfig::new(network),
+            passphrase,
+            bip39_passphrase,
+        )
+        .await
     }
 
-    pub async fn create_wallet_mutinynet(&self, name: &str) -> Result<(Arc<ArkWallet>, String)> {
-        // Check if wallet already exists
-        let wallet_store = WalletStore::new(&self.storage);
-        if wallet_store.wallet_exists(name).await? {
-            return Err(ArkiveError::config(format!(
-                "Wallet '{}' already exists",
-                name
-            )));
-        }
+    pub async fn create_wallet_mutinynet(
+        &self,
+        name: &str,
+        passphrase: &str,
+        bip39_passphrase: Option<&Bip39Passphrase>,
+    ) -> Result<(Arc<ArkWallet>, String)> {
+        self.create_wallet_with_config(
+            name,
+            WalletConfig::new_mutinynet(),
+            passphrase,
+            bip39_passphrase,
+        )
+        .await
+    }
 
-        // Generate mnemonic and keypair
+    /// Create a wallet from a custom config, e.g. one using taproot on-chain
+    /// addresses
+    pub async fn create_wallet_with_config(
+        &self,
+        name: &str,
+        config: WalletConfig,
+        passphrase: &str,
+        bip39_passphrase: Option<&Bip39Passphrase>,
+    ) -> Result<(Arc<ArkWallet>, String)> {
+        // Generate mnemonic
         let mnemonic = generate_mnemonic()?;
-        let keypair = mnemonic_to_keypair(&mnemonic, Network::Signet)?;
-
-        // Create Mutinynet config
-        let config = WalletConfig::new_mutinynet();
-        config.validate()?;
-
-        // Create wallet data
-        let wallet_id = Uuid::new_v4().to_string();
-        let wallet_data = WalletData {
-            id: wallet_id.clone(),
-            name: name.to_string(),
-            network: Network::Signet,
-            created_at: Utc::now(),
-            encrypted_seed: self.encrypt_seed(&mnemonic)?,
-            config: Some(serde_json::to_string(&config)?),
-            is_mutinynet: true,
-        };
-
-        // Save to storage
-        wallet_store.save_wallet(&wallet_data).await?;
-
-        // Create wallet instance
-        let wallet = Arc::new(
-            ArkWallet::new(
-                wallet_id.clone(),
-                name.to_string(),
-                keypair,
-                config,
-                self.storage.clone(),
-            )
-            .await?,
-        );
-
-        // Cache the wallet
-        {
-            let mut wallets = self.wallets.write();
-            wallets.insert(wallet_id, wallet.clone());
-        }
+        let wallet = self
+            .save_new_wallet(name, &mnemonic, config, passphrase, bip39_passphrase)
+            .await?;
 
         tracing::info!(
-            "Created Mutinynet wallet '{}' with ID: {}",
+            "Created {} wallet '{}' with ID: {}",
+            wallet.network_display(),
             name,
             wallet.id()
         );
@@ -148,75 +98,32 @@ impl WalletManager {
         &self,
         name: &str,
         mnemonic: &str,
+        passphrase: &str,
+        bip39_passphrase: Option<&Bip39Passphrase>,
     ) -> Result<Arc<ArkWallet>> {
-        // Check if wallet already exists
-        let wallet_store = WalletStore::new(&self.storage);
-        if wallet_store.wallet_exists(name).await? {
-            return Err(ArkiveError::config(format!(
-                "Wallet '{}' already exists",
-                name
-            )));
-        }
-
-        // Validate mnemonic and create keypair
-        let keypair = mnemonic_to_keypair(mnemonic, Network::Signet)?;
-
-        // Create Mutinynet config
-        let config = WalletConfig::new_mutinynet();
-        config.validate()?;
-
-        // Create wallet data
-        let wallet_id = Uuid::new_v4().to_string();
-        let wallet_data = WalletData {
-            id: wallet_id.clone(),
-            name: name.to_string(),
-            network: Network::Signet,
-            created_at: Utc::now(),
-            encrypted_seed: self.encrypt_seed(mnemonic)?,
-            config: Some(serde_json::to_string(&config)?),
-            is_mutinynet: true,
-        };
-
-        // Save to storage
-        wallet_store.save_wallet(&wallet_data).await?;
-
-        // Create wallet instance
-        let wallet = Arc::new(
-            ArkWallet::new(
-                wallet_id.clone(),
-                name.to_string(),
-                keypair,
-                config,
-                self.storage.clone(),
-            )
-            .await?,
-        );
-
-        // Cache the wallet
-        {
-            let mut wallets = self.wallets.write();
-            wallets.insert(wallet_id, wallet.clone());
-        }
-
-        tracing::info!(
-            "Imported Mutinynet wallet '{}' with ID: {}",
+        self.import_wallet_with_config(
             name,
-            wallet.id()
-        );
-        Ok(wallet)
+            mnemonic,
+            WalletConfig::new_mutinynet(),
+            passphrase,
+            bip39_passphrase,
+        )
+        .await
     }
 
-    pub async fn load_wallet(&self, name: &str) -> Result<Arc<ArkWallet>> {
-        // Check cache first
-        {
-            let wallets = self.wallets.read();
-            for wallet in wallets.values() {
-                if wallet.name() == name {
-                    return Ok(wallet.clone());
-                }
-            }
-        }
+    pub async fn load_wallet(&self, name: &str, passphrase: &str) -> Result<Arc<ArkWallet>> {
+        self.load_wallet_with_bip39(name, passphrase, None).await
+    }
 
+    /// Load a wallet whose seed is protected by a BIP39 passphrase that was
+    /// not persisted. Returns [`ArkiveError::Bip39PassphraseRequired`] if one
+    /// is needed but `bip39_passphrase` is `None`.
+    pub async fn load_wallet_with_bip39(
+        &self,
+        name: &str,
+        passphrase: &str,
+        bip39_passphrase: Option<&str>,
+    ) -> Result<Arc<ArkWallet>> {
         // Load from storage
         let wallet_store = WalletStore::new(&self.storage);
         let wallets_data = wallet_store.list_wallets().await?;
@@ -228,23 +135,56 @@ impl WalletManager {
                 name: name.to_string(),
             })?;
 
-        // Decrypt seed and create keypair
-        let mnemonic = self.decrypt_seed(&wallet_data.encrypted_seed)?;
-        let keypair = mnemonic_to_keypair(&mnemonic, wallet_data.network)?;
+        // Decrypt seed, re-encrypting rows written before seeds were encrypted
+        let seed_secret = if Self::is_plaintext_seed(&wallet_data.encrypted_seed) {
+            Self::validate_passphrase(passphrase)?;
+            let seed_secret = self.decrypt_legacy_seed(&wallet_data.encrypted_seed)?;
+            wallet_store
+                .update_encrypted_seed(&wallet_data.id, &encrypt_seed(&seed_secret, passphrase)?)
+                .await?;
+            tracing::info!("Encrypted legacy plaintext seed for wallet '{}'", name);
+            seed_secret
+        } else {
+            decrypt_seed(&wallet_data.encrypted_seed, passphrase)?
+        };
+
+        // Check cache only once the passphrase has been verified
+        let cached = self.wallets.read().get(&wallet_data.id).cloned();
+        if let Some(wallet) = cached {
+            if wallet.is_locked() {
+                wallet
+                    .unlock_with_bip39(
+                        passphrase,
+                        bip39_passphrase,
+                        wallet.config().auto_lock_timeout,
+                    )
+                    .await?;
+            }
+            return Ok(wallet);
+        }
 
         // Parse config
         let config = if let Some(config_str) = &wallet_data.config {
             serde_json::from_str(config_str)?
         } else {
-            WalletConfig::new(wallet_data.network)
+            WalletConfig {
+                key_derivation: KeyDerivation::legacy(),
+                ..WalletConfig::new(wallet_data.network)
+            }
         };
 
+        let keys = seed_secret.keys(
+            wallet_data.network,
+            &config.key_derivation,
+            bip39_passphrase,
+        )?;
+
         // Create wallet instance
         let wallet = Arc::new(
             ArkWallet::new(
                 wallet_data.id.clone(),
                 wallet_data.name.clone(),
-                keypair,
+                keys,
                 config,
                 self.storage.clone(),
             )
@@ -260,12 +200,36 @@ impl WalletManager {
         Ok(wallet)
     }
 
+    /// Check loaded wallets for expiring VTXOs every `interval` and renew
+    /// them in the background, for those with `auto_renew_vtxos` set
+    pub fn start_auto_renewal(&self, interval: Duration) -> RenewalScheduler {
+        RenewalScheduler::spawn(self.wallets.clone(), interval)
+    }
+
     pub async fn list_wallets(&self) -> Result<Vec<String>> {
         let wallet_store = WalletStore::new(&self.storage);
         let wallets_data = wallet_store.list_wallets().await?;
         Ok(wallets_data.into_iter().map(|w| w.name).collect())
     }
 
+    pub async fn list_wallet_summaries(&self) -> Result<Vec<WalletSummary>> {
+        let wallet_store = WalletStore::new(&self.storage);
+        let wallets_data = wallet_store.list_wallets().await?;
+
+        Ok(wallets_data
+            .into_iter()
+            .map(|w| WalletSummary {
+                network_display: if w.is_mutinynet {
+                    "Mutinynet".to_string()
+                } else {
+                    format!("{:?}", w.network)
+                },
+                seed_encrypted: !Self::is_plaintext_seed(&w.encrypted_seed),
+                name: w.name,
+            })
+            .collect())
+    }
+
     pub async fn delete_wallet(&self, name: &str) -> Result<()> {
         let wallet_store = WalletStore::new(&self.storage);
         let wallets_data = wallet_store.list_wallets().await?;
@@ -295,6 +259,49 @@ impl WalletManager {
         name: &str,
         mnemonic: &str,
         network: Network,
+        passphrase: &str,
+        bip39_passphrase: Option<&Bip39Passphrase>,
+    ) -> Result<Arc<ArkWallet>> {
+        self.import_wallet_with_config(
+            name,
+            mnemonic,
+            WalletConfig::new(network),
+            passphrase,
+            bip39_passphrase,
+        )
+        .await
+    }
+
+    /// Import a wallet with a custom config. The config's key derivation must
+    /// match the one the mnemonic was used with to find existing funds.
+    pub async fn import_wallet_with_config(
+        &self,
+        name: &str,
+        mnemonic: &str,
+        config: WalletConfig,
+        passphrase: &str,
+        bip39_passphrase: Option<&Bip39Passphrase>,
+    ) -> Result<Arc<ArkWallet>> {
+        let wallet = self
+            .save_new_wallet(name, mnemonic, config, passphrase, bip39_passphrase)
+            .await?;
+
+        tracing::info!(
+            "Imported {} wallet '{}' with ID: {}",
+            wallet.network_display(),
+            name,
+            wallet.id()
+        );
+        Ok(wallet)
+    }
+
+    async fn save_new_wallet(
+        &self,
+        name: &str,
+        mnemonic: &str,
+        config: WalletConfig,
+        passphrase: &str,
+        bip39_passphrase: Option<&Bip39Passphrase>,
     ) -> Result<Arc<ArkWallet>> {
         // Check if wallet already exists
         let wallet_store = WalletStore::new(&self.storage);
@@ -304,14 +311,18 @@ impl WalletManager {
                 name
             )));
         }
-
-        // Validate mnemonic and create keypair
-        let keypair = mnemonic_to_keypair(mnemonic, network)?;
-
-        // Create wallet config
-        let config = WalletConfig::new(network);
+        Self::validate_passphrase(passphrase)?;
         config.validate()?;
 
+        // Validate mnemonic and derive keys
+        let network = config.network;
+        let seed_secret = SeedSecret::new(mnemonic, bip39_passphrase, network)?;
+        let keys = seed_secret.keys(
+            network,
+            &config.key_derivation,
+            bip39_passphrase.map(|b| b.passphrase.as_str()),
+        )?;
+
         // Create wallet data
         let wallet_id = Uuid::new_v4().to_string();
         let wallet_data = WalletData {
@@ -319,9 +330,9 @@ impl WalletManager {
             name: name.to_string(),
             network,
             created_at: Utc::now(),
-            encrypted_seed: self.encrypt_seed(mnemonic)?,
+            encrypted_seed: encrypt_seed(&seed_secret, passphrase)?,
             config: Some(serde_json::to_string(&config)?),
-            is_mutinynet: false,
+            is_mutinynet: config.is_mutinynet,
         };
 
         // Save to storage
@@ -332,7 +343,7 @@ impl WalletManager {
             ArkWallet::new(
                 wallet_id.clone(),
                 name.to_string(),
-                keypair,
+                keys,
                 config,
                 self.storage.clone(),
             )
@@ -345,18 +356,56 @@ impl WalletManager {
             wallets.insert(wallet_id, wallet.clone());
         }
 
-        tracing::info!("Imported wallet '{}' with ID: {}", name, wallet.id());
         Ok(wallet)
     }
 
-    fn encrypt_seed(&self, mnemonic: &str) -> Result<Vec<u8>> {
-        // [TODO] Impl proper encryption with user password/keychain
-        Ok(mnemonic.as_bytes().to_vec())
+    /// Re-encrypt every wallet whose seed is still stored as plaintext.
+    ///
+    /// Returns the number of wallets that were migrated.
+    pub async fn migrate_plaintext_seeds(&self, passphrase: &str) -> Result<usize> {
+        Self::validate_passphrase(passphrase)?;
+
+        let wallet_store = WalletStore::new(&self.storage);
+        let wallets_data = wallet_store.list_wallets().await?;
+
+        let mut migrated = 0;
+        for wallet_data in wallets_data {
+            if !Self::is_plaintext_seed(&wallet_data.encrypted_seed) {
+                continue;
+            }
+
+            let seed_secret = self.decrypt_legacy_seed(&wallet_data.encrypted_seed)?;
+            wallet_store
+                .update_encrypted_seed(&wallet_data.id, &encrypt_seed(&seed_secret, passphrase)?)
+                .await?;
+
+            migrated += 1;
+            tracing::info!(
+                "Encrypted legacy plaintext seed for wallet '{}'",
+                wallet_data.name
+            );
+        }
+
+        Ok(migrated)
+    }
+
+    fn validate_passphrase(passphrase: &str) -> Result<()> {
+        if passphrase.is_empty() {
+            return Err(ArkiveError::config("Wallet passphrase cannot be empty"));
+        }
+        Ok(())
+    }
+
+    /// Seeds written by earlier versions were stored as raw mnemonic bytes
+    fn is_plaintext_seed(encrypted_seed: &[u8]) -> bool {
+        std::str::from_utf8(encrypted_seed)
+            .map(|s| Mnemonic::parse_in(Language::English, s).is_ok())
+            .unwrap_or(false)
     }
 
-    fn decrypt_seed(&self, encrypted_seed: &[u8]) -> Result<String> {
-        // [TODO] Impl proper decryption
-        String::from_utf8(encrypted_seed.to_vec())
-            .map_err(|e| ArkiveError::internal(format!("Failed to decrypt seed: {}", e)))
+    fn decrypt_legacy_seed(&self, encrypted_seed: &[u8]) -> Result<SeedSecret> {
+        let mnemonic = String::from_utf8(encrypted_seed.to_vec())
+            .map_err(|e| ArkiveError::internal(format!("Failed to read legacy seed: {}", e)))?;
+        SeedSecret::new(&mnemonic, None, Network::Regtest)
     }
 }
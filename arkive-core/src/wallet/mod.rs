@@ -1,15 +1,25 @@
 pub mod config;
 pub mod instance;
+pub mod keys;
 pub mod manager;
+pub mod renewal;
+pub mod session;
 
-pub use config::WalletConfig;
+pub use config::{
+    BitcoindConfig, ChainBackendConfig, ElectrumConfig, FeePolicy, FeePriority, OnchainScriptType,
+    RpcAuth, WalletConfig,
+};
 pub use instance::ArkWallet;
-pub use manager::WalletManager;
+pub use keys::{KeyDerivation, WalletKeys, WalletPublicKeys};
+pub use manager::{WalletManager, WalletSummary};
+pub use renewal::{RenewalOutcome, RenewalReport, RenewalScheduler};
+pub use session::KeySession;
 
+use crate::backup::{encryption, EncryptedBackup};
 use crate::error::{ArkiveError, Result};
 use bip39::{Language, Mnemonic};
-use bitcoin::key::Keypair;
-use bitcoin::secp256k1::{Secp256k1, SecretKey};
+use bitcoin::secp256k1::Secp256k1;
+use serde::{Deserialize, Serialize};
 
 pub fn generate_mnemonic() -> Result<String> {
     let mut rng = bip39::rand::thread_rng();
@@ -18,28 +28,128 @@ pub fn generate_mnemonic() -> Result<String> {
     Ok(mnemonic.to_string())
 }
 
-pub fn mnemonic_to_keypair(mnemonic: &str, network: bitcoin::Network) -> Result<Keypair> {
-    let mnemonic = Mnemonic::parse_in(Language::English, mnemonic)
-        .map_err(|e| ArkiveError::config(format!("Invalid mnemonic: {}", e)))?;
+/// Optional BIP39 passphrase ("25th word") protecting a wallet's seed
+#[derive(Debug, Clone)]
+pub struct Bip39Passphrase {
+    pub passphrase: String,
+    /// Store the passphrase encrypted with the seed. When false, it has to be
+    /// supplied every time the wallet is loaded or unlocked.
+    pub persist: bool,
+}
 
-    let seed = mnemonic.to_seed("");
-    let secp = Secp256k1::new();
+/// Secret material stored encrypted in `wallets.encrypted_seed`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SeedSecret {
+    pub mnemonic: String,
+    /// BIP39 passphrase, only present when the user chose to persist it
+    #[serde(default)]
+    pub bip39_passphrase: Option<String>,
+    /// Master key fingerprint used to verify a BIP39 passphrase that is not persisted
+    #[serde(default)]
+    pub bip39_fingerprint: Option<String>,
+}
 
-    let master_key = bitcoin::bip32::Xpriv::new_master(network, &seed)
-        .map_err(|e| ArkiveError::internal(format!("Failed to derive master key: {}", e)))?;
+impl SeedSecret {
+    pub fn new(
+        mnemonic: &str,
+        bip39: Option<&Bip39Passphrase>,
+        network: bitcoin::Network,
+    ) -> Result<Self> {
+        let (bip39_passphrase, bip39_fingerprint) = match bip39 {
+            Some(bip39) if bip39.persist => (Some(bip39.passphrase.clone()), None),
+            Some(bip39) => (
+                None,
+                Some(master_fingerprint(mnemonic, network, &bip39.passphrase)?),
+            ),
+            None => (None, None),
+        };
 
-    let path = bitcoin::bip32::DerivationPath::from_str("m/84'/0'/0'/0/0")
-        .map_err(|e| ArkiveError::config(format!("Invalid derivation path: {}", e)))?;
+        Ok(Self {
+            mnemonic: mnemonic.to_string(),
+            bip39_passphrase,
+            bip39_fingerprint,
+        })
+    }
 
-    let child_key = master_key
-        .derive_priv(&secp, &path)
-        .map_err(|e| ArkiveError::internal(format!("Failed to derive child key: {}", e)))?;
+    /// Derive the wallet keys. `bip39_passphrase` is only consulted when
+    /// the wallet uses a BIP39 passphrase that was not persisted.
+    pub fn keys(
+        &self,
+        network: bitcoin::Network,
+        derivation: &KeyDerivation,
+        bip39_passphrase: Option<&str>,
+    ) -> Result<WalletKeys> {
+        match (&self.bip39_passphrase, &self.bip39_fingerprint) {
+            (Some(stored), _) => {
+                mnemonic_to_keys(&self.mnemonic, network, Some(stored), derivation)
+            }
+            (None, Some(fingerprint)) => {
+                let passphrase = bip39_passphrase.ok_or(ArkiveError::Bip39PassphraseRequired)?;
+                if master_fingerprint(&self.mnemonic, network, passphrase)? != *fingerprint {
+                    return Err(ArkiveError::InvalidPassphrase);
+                }
+                mnemonic_to_keys(&self.mnemonic, network, Some(passphrase), derivation)
+            }
+            (None, None) => mnemonic_to_keys(&self.mnemonic, network, None, derivation),
+        }
+    }
+}
+
+pub(crate) fn encrypt_seed(secret: &SeedSecret, passphrase: &str) -> Result<Vec<u8>> {
+    let plaintext = serde_json::to_vec(secret)?;
+    let encrypted = encryption::encrypt_data(&plaintext, passphrase)?;
+    Ok(serde_json::to_vec(&encrypted)?)
+}
 
-    let secret_key = SecretKey::from_slice(&child_key.private_key.secret_bytes())
-        .map_err(|e| ArkiveError::internal(format!("Invalid secret key: {}", e)))?;
+pub(crate) fn decrypt_seed(encrypted_seed: &[u8], passphrase: &str) -> Result<SeedSecret> {
+    let encrypted: EncryptedBackup = serde_json::from_slice(encrypted_seed)?;
+    let decrypted = encryption::decrypt_data(&encrypted, passphrase)
+        .map_err(|_| ArkiveError::InvalidPassphrase)?;
 
-    let keypair = Keypair::from_secret_key(&secp, &secret_key);
-    Ok(keypair)
+    if let Ok(secret) = serde_json::from_slice::<SeedSecret>(&decrypted) {
+        return Ok(secret);
+    }
+
+    // Seeds encrypted before BIP39 passphrase support hold only the mnemonic
+    let mnemonic = String::from_utf8(decrypted)
+        .map_err(|e| ArkiveError::internal(format!("Failed to decrypt seed: {}", e)))?;
+    Ok(SeedSecret {
+        mnemonic,
+        bip39_passphrase: None,
+        bip39_fingerprint: None,
+    })
 }
 
-use std::str::FromStr;
+fn master_key(
+    mnemonic: &str,
+    network: bitcoin::Network,
+    bip39_passphrase: Option<&str>,
+) -> Result<bitcoin::bip32::Xpriv> {
+    let mnemonic = Mnemonic::parse_in(Language::English, mnemonic)
+        .map_err(|e| ArkiveError::config(format!("Invalid mnemonic: {}", e)))?;
+
+    let seed = mnemonic.to_seed(bip39_passphrase.unwrap_or(""));
+
+    bitcoin::bip32::Xpriv::new_master(network, &seed)
+        .map_err(|e| ArkiveError::internal(format!("Failed to derive master key: {}", e)))
+}
+
+fn master_fingerprint(
+    mnemonic: &str,
+    network: bitcoin::Network,
+    bip39_passphrase: &str,
+) -> Result<String> {
+    let secp = Secp256k1::new();
+    let master_key = master_key(mnemonic, network, Some(bip39_passphrase))?;
+    Ok(master_key.fingerprint(&secp).to_string())
+}
+
+pub fn mnemonic_to_keys(
+    mnemonic: &str,
+    network: bitcoin::Network,
+    bip39_passphrase: Option<&str>,
+    derivation: &KeyDerivation,
+) -> Result<WalletKeys> {
+    let master_key = master_key(mnemonic, network, bip39_passphrase)?;
+    WalletKeys::derive(&master_key, derivation)
+}
@@ -0,0 +1,151 @@
+//! Renewal of VTXOs before they expire
+//!
+//! A VTXO expires with its batch, after which the server may sweep it.
+//! Settling it in a new round before then moves the funds into a fresh
+//! VTXO.
+
+use crate::wallet::ArkWallet;
+
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Rounds joined before giving up on renewing a wallet's VTXOs until the
+/// next pass
+pub(crate) const RENEWAL_ATTEMPTS: u32 = 3;
+
+/// Wait before the first retry, doubled for every further one
+pub(crate) const RENEWAL_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RenewalOutcome {
+    /// No VTXO expires within the renewal threshold
+    NothingDue,
+    /// Expiring VTXOs were settled into the round with this commitment
+    /// txid, along with the rest listed in the report
+    Renewed { round_txid: String },
+    /// Renewal was not attempted, e.g. because the wallet is locked
+    Skipped(String),
+    /// Every attempt failed, with the last error
+    Failed(String),
+}
+
+/// What one renewal pass did for one wallet
+#[derive(Debug, Clone)]
+pub struct RenewalReport {
+    pub wallet: String,
+    /// Outpoints of the VTXOs that were due for renewal
+    pub expiring: Vec<String>,
+    /// Outpoints of every VTXO the round settled, which includes spendable
+    /// ones that weren't due yet
+    pub settled: Vec<String>,
+    /// Outpoints of the boarding outputs settled into the same round
+    pub boarded: Vec<String>,
+    pub attempts: u32,
+    pub outcome: RenewalOutcome,
+}
+
+impl RenewalReport {
+    pub(crate) fn new(wallet: &str) -> Self {
+        Self {
+            wallet: wallet.to_string(),
+            expiring: Vec::new(),
+            settled: Vec::new(),
+            boarded: Vec::new(),
+            attempts: 0,
+            outcome: RenewalOutcome::NothingDue,
+        }
+    }
+}
+
+/// Background task renewing the expiring VTXOs of every loaded wallet with
+/// `auto_renew_vtxos` set. Each wallet renews in a task of its own, so one
+/// waiting to retry doesn't hold up the others or the next pass. The tasks
+/// stop when the scheduler is dropped.
+pub struct RenewalScheduler {
+    task: JoinHandle<()>,
+    /// Renewal in progress, by wallet name
+    renewals: Arc<Mutex<HashMap<String, JoinHandle<()>>>>,
+    reports: mpsc::UnboundedReceiver<RenewalReport>,
+}
+
+impl RenewalScheduler {
+    pub(crate) fn spawn(
+        wallets: Arc<RwLock<HashMap<String, Arc<ArkWallet>>>>,
+        interval: Duration,
+    ) -> Self {
+        let (sender, reports) = mpsc::unbounded_channel();
+        let renewals: Arc<Mutex<HashMap<String, JoinHandle<()>>>> = Arc::default();
+
+        let task = tokio::spawn({
+            let renewals = renewals.clone();
+            async move {
+                let mut ticker = tokio::time::interval(interval);
+                ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+                loop {
+                    ticker.tick().await;
+
+                    // Wallets loaded since the last pass are picked up here
+                    let wallets: Vec<Arc<ArkWallet>> = wallets.read().values().cloned().collect();
+                    let mut renewals = renewals.lock();
+                    renewals.retain(|_, renewal| !renewal.is_finished());
+
+                    for wallet in wallets {
+                        if !wallet.config().auto_renew_vtxos {
+                            continue;
+                        }
+                        // Still retrying since an earlier pass
+                        if renewals.contains_key(wallet.name()) {
+                            continue;
+                        }
+
+                        let name = wallet.name().to_string();
+                        let sender = sender.clone();
+                        let renewal = tokio::spawn(async move {
+                            let report = wallet.renew_expiring_vtxos().await;
+                            if report.outcome != RenewalOutcome::NothingDue {
+                                // Nobody listening is fine, the log has it too
+                                let _ = sender.send(report);
+                            }
+                        });
+                        renewals.insert(name, renewal);
+                    }
+                }
+            }
+        });
+
+        Self {
+            task,
+            renewals,
+            reports,
+        }
+    }
+
+    /// Next report of a wallet that had VTXOs due, waiting for one if
+    /// there is none yet
+    pub async fn next_report(&mut self) -> Option<RenewalReport> {
+        self.reports.recv().await
+    }
+
+    /// Reports produced since the last call, without waiting
+    pub fn drain_reports(&mut self) -> Vec<RenewalReport> {
+        let mut reports = Vec::new();
+        while let Ok(report) = self.reports.try_recv() {
+            reports.push(report);
+        }
+        reports
+    }
+}
+
+impl Drop for RenewalScheduler {
+    fn drop(&mut self) {
+        self.task.abort();
+        for (_, renewal) in self.renewals.lock().drain() {
+            renewal.abort();
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use crate::error::{ArkiveError, Result};
+use crate::wallet::WalletKeys;
+use parking_lot::RwLock;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+type LockHook = Box<dyn Fn() + Send + Sync>;
+
+struct UnlockedKeys {
+    keys: WalletKeys,
+    expires_at: Option<Instant>,
+    /// Task locking the session when the timeout elapses
+    timer: Option<JoinHandle<()>>,
+}
+
+impl UnlockedKeys {
+    /// Stop the timer, the keys erase themselves when dropped
+    fn erase(mut self) {
+        if let Some(timer) = self.timer.take() {
+            timer.abort();
+        }
+    }
+}
+
+/// Shared handle to the wallet signing keys.
+///
+/// The keys are only held while the wallet is unlocked. Once the session is
+/// locked, or its timeout elapses, they are erased and every signing path
+/// returns [`ArkiveError::WalletLocked`].
+#[derive(Clone, Default)]
+pub struct KeySession {
+    inner: Arc<RwLock<Option<UnlockedKeys>>>,
+    on_lock: Arc<RwLock<Vec<LockHook>>>,
+}
+
+impl KeySession {
+    pub fn unlocked(keys: WalletKeys, timeout: Option<Duration>) -> Self {
+        let session = Self::default();
+        session.unlock(keys, timeout);
+        session
+    }
+
+    /// Make the keys available for signing, optionally for a limited time
+    pub fn unlock(&self, keys: WalletKeys, timeout: Option<Duration>) {
+        let timer = timeout.and_then(|timeout| self.spawn_timer(timeout));
+
+        let mut inner = self.inner.write();
+        if let Some(previous) = inner.take() {
+            previous.erase();
+        }
+
+        *inner = Some(UnlockedKeys {
+            keys,
+            expires_at: timeout.map(|t| Instant::now() + t),
+            timer,
+        });
+    }
+
+    /// Lock the session once `timeout` has elapsed. Outside a Tokio runtime
+    /// the timeout is only noticed the next time the keys are requested.
+    fn spawn_timer(&self, timeout: Duration) -> Option<JoinHandle<()>> {
+        let runtime = tokio::runtime::Handle::try_current().ok()?;
+        let inner = Arc::downgrade(&self.inner);
+        let on_lock = Arc::downgrade(&self.on_lock);
+
+        Some(runtime.spawn(async move {
+            tokio::time::sleep(timeout).await;
+            if let (Some(inner), Some(on_lock)) = (inner.upgrade(), on_lock.upgrade()) {
+                tracing::info!("Wallet session timed out, wallet locked");
+                KeySession { inner, on_lock }.lock();
+            }
+        }))
+    }
+
+    /// Erase the keys from memory
+    pub fn lock(&self) {
+        let unlocked = self.inner.write().take();
+        if let Some(unlocked) = unlocked {
+            unlocked.erase();
+            for hook in self.on_lock.read().iter() {
+                hook();
+            }
+        }
+    }
+
+    /// Run `hook` every time the session locks, on request or on timeout,
+    /// e.g. to drop other copies of the keys
+    pub fn on_lock(&self, hook: impl Fn() + Send + Sync + 'static) {
+        self.on_lock.write().push(Box::new(hook));
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.ensure_unlocked().is_ok()
+    }
+
+    /// Fail with [`ArkiveError::WalletLocked`] unless the keys are
+    /// available, without handing them out
+    pub fn ensure_unlocked(&self) -> Result<()> {
+        self.with_keys(|_| ())
+    }
+
+    /// Run `f` with the signing keys, locking the session if its timeout
+    /// has elapsed. The keys are only borrowed for the call, so nothing
+    /// outlives the session's own copy; `f` must not use the session.
+    pub fn with_keys<T>(&self, f: impl FnOnce(&WalletKeys) -> T) -> Result<T> {
+        let expired = {
+            let inner = self.inner.read();
+            match inner.as_ref() {
+                Some(unlocked) => match unlocked.expires_at {
+                    Some(expires_at) if Instant::now() >= expires_at => true,
+                    _ => return Ok(f(&unlocked.keys)),
+                },
+                None => false,
+            }
+        };
+
+        if expired {
+            self.lock();
+            tracing::info!("Wallet session timed out, wallet locked");
+        }
+        Err(ArkiveError::WalletLocked)
+    }
+}